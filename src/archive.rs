@@ -0,0 +1,325 @@
+use std::{
+    collections::HashSet,
+    io,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use async_compression::tokio::write::{GzipEncoder, ZstdEncoder};
+use async_zip::{tokio::write::ZipFileWriter, Compression, ZipDateTimeBuilder, ZipEntryBuilder};
+use axum::{
+    body::Body,
+    extract,
+    http::{HeaderMap, Response, StatusCode},
+};
+use futures_util::{future::ready, stream, StreamExt};
+use serde::Deserialize;
+use tokio::{
+    fs::File,
+    io::{copy, duplex, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::oneshot,
+};
+use tokio_util::{compat::FuturesAsyncWriteCompatExt, io::ReaderStream};
+use tracing::error;
+
+use crate::{
+    audit::{self, AuditAction, AuditEvent},
+    auth::AuthRequest,
+    auth_helper::authorize_by_headers,
+    content_type::DEFAULT_CONTENT_TYPE,
+    download::{authorize_download, blob_encryption, original_size},
+    error::UploadError,
+    file_data::FileData,
+    filename::{content_disposition, original_filename},
+    list::stored_files,
+    state::State,
+    storage::{self, BlobReader},
+    webhook::{self, WebhookEvent},
+};
+
+/// the size of the buffer between the task writing the archive and the response body
+const BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Deserialize, Default, Clone, Copy)]
+pub enum ArchiveFormat {
+    #[default]
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar")]
+    Tar,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+    #[serde(rename = "tar.zst")]
+    TarZst,
+}
+
+impl ArchiveFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Zip => "zip",
+            Self::Tar => "tar",
+            Self::TarGz => "tar.gz",
+            Self::TarZst => "tar.zst",
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            Self::Zip => "application/zip",
+            Self::Tar => "application/x-tar",
+            Self::TarGz => "application/gzip",
+            Self::TarZst => "application/zstd",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ArchiveQuery {
+    /// the names of the stored files separated by `/`
+    files: Option<String>,
+    /// all files the user is allowed to list whose name or original filename starts with the prefix
+    prefix: Option<String>,
+    #[serde(default)]
+    format: ArchiveFormat,
+}
+
+/// a file that gets added to the archive
+struct ArchiveEntry {
+    /// the name of the stored file
+    name: String,
+    /// the path of the file in the archive
+    path: String,
+    file_data: FileData,
+}
+
+impl ArchiveEntry {
+    /// returns the size of the original file and a reader of its content
+    async fn open(&self, state: &State) -> Result<(u64, BlobReader), UploadError> {
+        let path = state.upload_directory.join(&self.name);
+        let encryption = blob_encryption(state, &self.file_data, &self.name)?;
+        let size = original_size(state, &self.file_data, &self.name)?;
+        let file = File::open(path).await?;
+        let reader = storage::reader(
+            file,
+            self.file_data.compressed.unwrap_or_default(),
+            encryption,
+        )
+        .await?;
+        Ok((size, reader))
+    }
+
+    fn modified(&self) -> u64 {
+        self.file_data.uploaded_at.unwrap_or_default()
+    }
+}
+
+async fn write_tar<W: AsyncWrite + Unpin + Send + Sync + 'static>(
+    state: &State,
+    entries: &[ArchiveEntry],
+    writer: W,
+) -> Result<W, UploadError> {
+    let mut builder = tokio_tar::Builder::new(writer);
+    for entry in entries {
+        let (size, reader) = entry.open(state).await?;
+        let mut header = tokio_tar::Header::new_gnu();
+        header.set_size(size);
+        header.set_mode(0o644);
+        header.set_mtime(entry.modified());
+        // the tar entry would be corrupted if the reader returned more bytes than the header announced
+        builder
+            .append_data(&mut header, &entry.path, reader.take(size))
+            .await?;
+    }
+    Ok(builder.into_inner().await?)
+}
+
+async fn write_zip<W: AsyncWrite + Unpin>(
+    state: &State,
+    entries: &[ArchiveEntry],
+    writer: W,
+) -> Result<W, UploadError> {
+    let mut zip = ZipFileWriter::with_tokio(writer);
+    for entry in entries {
+        let (_, mut reader) = entry.open(state).await?;
+        let content_type = entry
+            .file_data
+            .content_type
+            .as_deref()
+            .unwrap_or(DEFAULT_CONTENT_TYPE);
+        // already compressed formats are only stored
+        let compression = if state.config.compression.is_compressible(content_type) {
+            Compression::Deflate
+        } else {
+            Compression::Stored
+        };
+        let builder = ZipEntryBuilder::new(entry.path.clone().into(), compression)
+            .last_modification_date(zip_date(entry.modified()))
+            .unix_permissions(0o644);
+        let mut entry_writer = zip
+            .write_entry_stream(builder)
+            .await
+            .map_err(io::Error::other)?
+            .compat_write();
+        copy(&mut reader, &mut entry_writer).await?;
+        entry_writer
+            .into_inner()
+            .close()
+            .await
+            .map_err(io::Error::other)?;
+    }
+    Ok(zip.close().await.map_err(io::Error::other)?.into_inner())
+}
+
+/// converts the unix timestamp into the date format of zip files (in utc)
+fn zip_date(timestamp: u64) -> async_zip::ZipDateTime {
+    // the formatted date looks like this: `2024-03-13T15:30:10Z`
+    let date =
+        humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(timestamp)).to_string();
+    let field = |range: std::ops::Range<usize>| date[range].parse::<u32>().unwrap_or_default();
+    ZipDateTimeBuilder::new()
+        .year(field(0..4) as i32)
+        .month(field(5..7))
+        .day(field(8..10))
+        .hour(field(11..13))
+        .minute(field(14..16))
+        .second(field(17..19))
+        .build()
+}
+
+/// writes the archive into the `writer`, the writer gets shut down at the end
+async fn write_archive<W: AsyncWrite + Unpin + Send + Sync + 'static>(
+    state: &State,
+    entries: &[ArchiveEntry],
+    format: ArchiveFormat,
+    writer: W,
+) -> Result<(), UploadError> {
+    match format {
+        ArchiveFormat::Zip => write_zip(state, entries, writer).await?.shutdown().await?,
+        ArchiveFormat::Tar => write_tar(state, entries, writer).await?.shutdown().await?,
+        ArchiveFormat::TarGz => {
+            write_tar(state, entries, GzipEncoder::new(writer))
+                .await?
+                .shutdown()
+                .await?
+        }
+        ArchiveFormat::TarZst => {
+            write_tar(state, entries, ZstdEncoder::new(writer))
+                .await?
+                .shutdown()
+                .await?
+        }
+    }
+    Ok(())
+}
+
+/// downloads multiple files as archive, the archive is written while it gets sent
+pub async fn archive(
+    extract::State(state): extract::State<State>,
+    extract::Query(query): extract::Query<ArchiveQuery>,
+    headers: HeaderMap,
+) -> Result<Response<Body>, UploadError> {
+    let mut names = query
+        .files
+        .as_deref()
+        .unwrap_or_default()
+        .split('/')
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_string())
+        .collect::<Vec<String>>();
+    if let Some(prefix) = &query.prefix {
+        // the prefix reveals which files exist, so the user needs the list permission
+        let authorization = authorize_by_headers(&state, &headers, AuthRequest::List)?;
+        names.extend(
+            stored_files(&state)
+                .await?
+                .into_iter()
+                .filter(|file| {
+                    file.visible_for(&authorization)
+                        && (file.name.starts_with(prefix) || file.filename().starts_with(prefix))
+                })
+                .map(|file| file.name),
+        );
+    }
+    let filename = format!(
+        "{}.{}",
+        query.prefix.as_deref().unwrap_or("files"),
+        query.format.extension()
+    );
+    archive_response(state, &headers, names, query.format, &filename).await
+}
+
+/// checks if the user may download every file and responds with the archive of the files `names`
+pub async fn archive_response(
+    state: State,
+    headers: &HeaderMap,
+    mut names: Vec<String>,
+    format: ArchiveFormat,
+    filename: &str,
+) -> Result<Response<Body>, UploadError> {
+    names.sort();
+    names.dedup();
+    if names.is_empty() {
+        return Err(UploadError::InvalidQueryArgument("files".to_string()));
+    }
+
+    // every file is checked before the archive gets sent, the response can't fail afterwards
+    let mut paths = HashSet::new();
+    let mut entries = Vec::with_capacity(names.len());
+    for name in names {
+        let file_data = authorize_download(&state, headers, &name).await?;
+        // files with the same filename keep their hash prefix
+        let mut path = file_data.display_name(original_filename(&name)).to_string();
+        if !paths.insert(path.clone()) {
+            path = name.clone();
+            paths.insert(path.clone());
+        }
+        entries.push(ArchiveEntry {
+            name,
+            path,
+            file_data,
+        });
+    }
+    // the downloads are only recorded if every file may be downloaded
+    for entry in &entries {
+        audit::record(
+            &state,
+            AuditEvent::file(AuditAction::Download, &entry.name, &entry.file_data)
+                .with_detail("archive"),
+        );
+        webhook::notify(
+            &state,
+            WebhookEvent::Downloaded,
+            &entry.name,
+            &entry.file_data,
+        );
+    }
+
+    let (writer, reader) = duplex(BUFFER_SIZE);
+    let (result_sender, result_receiver) = oneshot::channel();
+    tokio::spawn(async move {
+        let result = write_archive(&state, &entries, format, writer).await;
+        if let Err(e) = &result {
+            error!(error = ?e, "error while writing archive");
+        }
+        let _ = result_sender.send(result.is_ok());
+    });
+    // the body ends with an error if the archive couldn't be written completely,
+    // so the client doesn't think a truncated archive is complete
+    let result = stream::once(async move {
+        match result_receiver.await {
+            Ok(true) => None,
+            _ => Some(Err(io::Error::other("Error while writing archive"))),
+        }
+    })
+    .filter_map(ready);
+    let body = Body::from_stream(ReaderStream::new(reader).chain(result));
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", format.content_type())
+        .header(
+            "Content-Disposition",
+            content_disposition("attachment", filename),
+        )
+        .body(body)
+        .unwrap())
+}
@@ -0,0 +1,427 @@
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    env,
+    ffi::OsString,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use axum::{
+    extract::{self, Request},
+    http::HeaderMap,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::prelude::*;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{create_dir_all, remove_file, rename, File, OpenOptions},
+    io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader},
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+};
+use tracing::{error, info_span, Instrument};
+
+use crate::{
+    auth_helper::authorize_admin,
+    error::UploadError,
+    file_data::{current_unix_timestamp, FileData},
+    state::State,
+};
+
+/// the events the query endpoint returns if the request has no limit
+const DEFAULT_QUERY_LIMIT: usize = 100;
+/// the events the query endpoint returns at most
+const MAX_QUERY_LIMIT: usize = 10_000;
+
+tokio::task_local! {
+    /// the client of the request the task is handling
+    static CLIENT: RefCell<Client>;
+}
+
+#[derive(Default)]
+struct Client {
+    ip: Option<String>,
+    identity: Option<String>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    /// Record uploads, downloads, changes, deletions and failed authorizations in the audit log
+    pub enabled: bool,
+    /// The size in bytes the audit log gets rotated at
+    pub max_size: u64,
+    /// The number of rotated audit logs that are kept, all are kept if it isn't set
+    pub keep: Option<usize>,
+    /// The header containing the ip of the client if the server is behind a reverse proxy (e.g. `X-Forwarded-For`)
+    pub forwarded_header: Option<String>,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_size: 16 * 1024 * 1024,
+            keep: None,
+            forwarded_header: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Upload,
+    Download,
+    /// the file data of a file was changed
+    Update,
+    Delete,
+    /// the file was removed because its ttl was reached
+    Expire,
+    /// the file was restored from the trash
+    Restore,
+    AuthFailure,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AuditEvent {
+    /// the unix timestamp of the event
+    pub time: u64,
+    pub action: AuditAction,
+    /// the username (basic) or the `sub` claim (bearer) of the user, the attempted username of failed authorizations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    /// the name of the stored file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// the blake3 hash of the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// more information like the reason of a deletion
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl AuditEvent {
+    /// an event of the client of the current request, events of background tasks have no client
+    pub fn new(action: AuditAction) -> Self {
+        let (ip, identity) = CLIENT
+            .try_with(|client| {
+                let client = client.borrow();
+                (client.ip.clone(), client.identity.clone())
+            })
+            .unwrap_or_default();
+        Self {
+            time: current_unix_timestamp(),
+            action,
+            identity,
+            ip,
+            file: None,
+            hash: None,
+            detail: None,
+        }
+    }
+
+    /// an event concerning the stored file `name`
+    pub fn file(action: AuditAction, name: &str, file_data: &FileData) -> Self {
+        Self {
+            file: Some(name.to_string()),
+            hash: file_data.hash.clone(),
+            ..Self::new(action)
+        }
+    }
+
+    pub fn with_detail(mut self, detail: &str) -> Self {
+        self.detail = Some(detail.to_string());
+        self
+    }
+}
+
+/// the audit log, the events are written by a background task in the order they were recorded
+#[derive(Clone)]
+pub struct AuditLog {
+    sender: UnboundedSender<AuditEvent>,
+    path: PathBuf,
+}
+
+impl AuditLog {
+    /// opens the audit log at `AUDIT_LOG_PATH`, `None` if the audit log is disabled
+    pub async fn open(config: &AuditConfig) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        let path =
+            PathBuf::from(env::var("AUDIT_LOG_PATH").unwrap_or_else(|_| "audit.log".to_string()));
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)
+                .await
+                .expect("Error while creating audit log directory");
+        }
+        let file = open_append(&path)
+            .await
+            .expect("Error while opening audit log");
+        let size = file
+            .metadata()
+            .await
+            .expect("Error while reading the size of the audit log")
+            .len();
+        let (sender, receiver) = unbounded_channel();
+        let writer = AuditWriter {
+            path: path.clone(),
+            file,
+            size,
+            max_size: config.max_size,
+            keep: config.keep,
+        };
+        tokio::spawn(write_events(receiver, writer).instrument(info_span!("audit")));
+        Some(Self { sender, path })
+    }
+}
+
+/// records the event in the audit log if it's enabled
+pub fn record(state: &State, event: AuditEvent) {
+    if let Some(audit) = &state.audit {
+        // the writer only stops if the server stops
+        let _ = audit.sender.send(event);
+    }
+}
+
+/// adds the identity of the authorized user to the events of the request
+pub fn record_identity(identity: &str) {
+    let _ = CLIENT.try_with(|client| client.borrow_mut().identity = Some(identity.to_string()));
+}
+
+/// records a failed authorization with the username of the `Authorization` header if it's basic auth
+pub fn record_auth_failure(state: &State, authorization: &str) {
+    let (scheme, payload) = authorization.split_once(' ').unwrap_or((authorization, ""));
+    let username = match scheme {
+        "Basic" => BASE64_STANDARD
+            .decode(payload)
+            .ok()
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .and_then(|decoded| decoded.split(':').next().map(str::to_string)),
+        _ => None,
+    };
+    let mut event = AuditEvent::new(AuditAction::AuthFailure).with_detail(scheme);
+    event.identity = username;
+    record(state, event);
+}
+
+/// middleware that makes the ip of the client available to the events of the request
+pub async fn track_client(
+    extract::State(state): extract::State<State>,
+    extract::ConnectInfo(address): extract::ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // the first address of a forwarded header is the client
+    let forwarded = state
+        .config
+        .audit
+        .forwarded_header
+        .as_ref()
+        .and_then(|name| request.headers().get(name))
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(',').next())
+        .map(|ip| ip.trim().to_string());
+    let client = Client {
+        ip: Some(forwarded.unwrap_or_else(|| address.ip().to_string())),
+        identity: None,
+    };
+    CLIENT.scope(RefCell::new(client), next.run(request)).await
+}
+
+async fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+}
+
+/// the path of the rotated audit log, 1 is the most recent one
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = OsString::from(path.as_os_str());
+    rotated.push(format!(".{index}"));
+    PathBuf::from(rotated)
+}
+
+struct AuditWriter {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    keep: Option<usize>,
+}
+
+impl AuditWriter {
+    async fn write(&mut self, event: &AuditEvent) -> io::Result<()> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate().await?;
+        }
+        self.file.write_all(&line).await?;
+        self.file.flush().await?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// renames the audit log to `<path>.1`, the existing rotated logs are shifted by one
+    async fn rotate(&mut self) -> io::Result<()> {
+        let mut rotated = 0;
+        while rotated_path(&self.path, rotated + 1).exists() {
+            rotated += 1;
+        }
+        for index in (1..=rotated).rev() {
+            let from = rotated_path(&self.path, index);
+            // at least the most recent rotated log is kept
+            if self.keep.is_some_and(|keep| index >= keep.max(1)) {
+                remove_file(from).await?;
+            } else {
+                rename(from, rotated_path(&self.path, index + 1)).await?;
+            }
+        }
+        rename(&self.path, rotated_path(&self.path, 1)).await?;
+        self.file = open_append(&self.path).await?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+async fn write_events(mut receiver: UnboundedReceiver<AuditEvent>, mut writer: AuditWriter) {
+    while let Some(event) = receiver.recv().await {
+        if let Err(e) = writer.write(&event).await {
+            error!(error = ?e, "error while writing the audit log");
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    action: Option<AuditAction>,
+    identity: Option<String>,
+    ip: Option<String>,
+    file: Option<String>,
+    hash: Option<String>,
+    /// unix timestamp of the earliest event
+    since: Option<u64>,
+    /// unix timestamp of the latest event
+    until: Option<u64>,
+    limit: Option<usize>,
+}
+
+impl AuditQuery {
+    fn matches(&self, event: &AuditEvent) -> bool {
+        fn matches_value(filter: &Option<String>, value: &Option<String>) -> bool {
+            filter.is_none() || filter == value
+        }
+        self.action.is_none_or(|action| action == event.action)
+            && matches_value(&self.identity, &event.identity)
+            && matches_value(&self.ip, &event.ip)
+            && matches_value(&self.file, &event.file)
+            && matches_value(&self.hash, &event.hash)
+            && self.since.is_none_or(|since| event.time >= since)
+            && self.until.is_none_or(|until| event.time <= until)
+    }
+}
+
+#[derive(Serialize)]
+struct AuditResponse {
+    events: Vec<AuditEvent>,
+}
+
+/// the `count` most recent events of the log that match the query, the most recent events first,
+/// the log is read line by line, so only the returned events are kept in memory
+async fn recent_events(
+    file: File,
+    query: &AuditQuery,
+    count: usize,
+) -> io::Result<Vec<AuditEvent>> {
+    let mut events = VecDeque::with_capacity(count);
+    let mut lines = BufReader::new(file).lines();
+    while let Some(line) = lines.next_line().await? {
+        // the last line could be written in the meantime
+        let Ok(event) = serde_json::from_str::<AuditEvent>(&line) else {
+            continue;
+        };
+        if query.matches(&event) {
+            if events.len() == count {
+                events.pop_front();
+            }
+            events.push_back(event);
+        }
+    }
+    Ok(events.into_iter().rev().collect())
+}
+
+/// searches the audit log and the rotated logs, the most recent events first
+pub async fn query(
+    extract::Query(query): extract::Query<AuditQuery>,
+    extract::State(state): extract::State<State>,
+    headers: HeaderMap,
+) -> Result<Response, UploadError> {
+    authorize_admin(&state, &headers)?;
+    let audit = state
+        .audit
+        .as_ref()
+        .ok_or(UploadError::InternalServerError)?;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_QUERY_LIMIT)
+        .clamp(1, MAX_QUERY_LIMIT);
+
+    let mut events = Vec::new();
+    let mut index = 0;
+    loop {
+        let path = match index {
+            0 => audit.path.clone(),
+            index => rotated_path(&audit.path, index),
+        };
+        let file = match File::open(&path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => break,
+            Err(e) => return Err(e.into()),
+        };
+        events.extend(recent_events(file, &query, limit - events.len()).await?);
+        if events.len() >= limit {
+            break;
+        }
+        index += 1;
+    }
+    Ok(Json(AuditResponse { events }).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::generate_random_characters;
+
+    #[tokio::test]
+    async fn recent_events_keeps_the_most_recent_matches() {
+        let path = env::temp_dir().join(generate_random_characters(16));
+        let mut content = String::new();
+        for time in 0..10 {
+            let action = if time % 2 == 0 { "upload" } else { "download" };
+            content.push_str(&format!("{{\"time\":{time},\"action\":\"{action}\"}}\n"));
+        }
+        // a partially written line
+        content.push_str("{\"time\":10,");
+        tokio::fs::write(&path, content).await.unwrap();
+
+        let query: AuditQuery =
+            serde_json::from_value(serde_json::json!({ "action": "upload" })).unwrap();
+        let events = recent_events(File::open(&path).await.unwrap(), &query, 3)
+            .await
+            .unwrap();
+        remove_file(&path).await.unwrap();
+        assert_eq!(
+            events.iter().map(|event| event.time).collect::<Vec<u64>>(),
+            [8, 6, 4]
+        );
+    }
+}
@@ -1,7 +1,7 @@
 use bcrypt::verify;
 use serde::Deserialize;
 
-use super::{AuthError, AuthRequest};
+use super::{AuthError, AuthRequest, Authorization, TtlPolicy};
 
 fn true_fn() -> bool {
     true
@@ -17,6 +17,12 @@ pub struct BasicAuth {
     pub allow_download: bool,
     #[serde(default = "true_fn")]
     pub allow_upload: bool,
+    #[serde(default)]
+    pub allow_list: bool,
+    #[serde(default)]
+    pub admin: bool,
+    #[serde(flatten)]
+    pub ttl_policy: TtlPolicy,
 }
 
 pub struct BasicAuthArgument<'a> {
@@ -29,14 +35,24 @@ impl BasicAuth {
         &self,
         request: &AuthRequest,
         argument: BasicAuthArgument,
-    ) -> Result<Option<u64>, AuthError> {
+        default_max_filesize: u64,
+        default_ttl_policy: &TtlPolicy,
+    ) -> Result<Authorization, AuthError> {
         if self.username == argument.username {
+            let allowed = match request {
+                AuthRequest::Download => self.allow_download,
+                AuthRequest::Upload => self.allow_upload,
+                AuthRequest::List => self.allow_list,
+                AuthRequest::Admin => self.admin,
+            };
             // if the password is correct and this authorization does allow the required `AuthRequest`
-            if verify(argument.password, &self.password).unwrap_or_default()
-                && ((request == &AuthRequest::Download && self.allow_download)
-                    || (request == &AuthRequest::Upload && self.allow_upload))
-            {
-                return Ok(self.max_filesize);
+            if verify(argument.password, &self.password).unwrap_or_default() && allowed {
+                return Ok(Authorization {
+                    max_filesize: self.max_filesize.unwrap_or(default_max_filesize),
+                    identity: Some(self.username.clone()),
+                    admin: self.admin,
+                    ttl_policy: self.ttl_policy.or(default_ttl_policy),
+                });
             }
             // incorrect password or permission, but the auth scheme seems to be right
             return Err(AuthError::InvalidAuth(Some("Basic".to_string())));
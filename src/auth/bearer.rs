@@ -2,7 +2,7 @@ use jsonwebtoken::{decode, DecodingKey, Validation};
 use serde::Deserialize;
 use serde_json::Value;
 
-use super::{AuthError, AuthRequest};
+use super::{AuthError, AuthRequest, Authorization, TtlPolicy};
 
 /// just returns a invalid auth error that contains 'Bearer' as scheme
 macro_rules! invalid_auth_bearer {
@@ -33,6 +33,8 @@ pub struct BearerAuthConfig {
     pub max_filesize_field_name: String,
     #[serde(default = "default_permissions_field_name")] // "permissions"
     pub permissions_field_name: String,
+    #[serde(flatten)]
+    pub ttl_policy: TtlPolicy,
 }
 
 pub struct BearerAuthArgument(pub String);
@@ -46,7 +48,9 @@ impl BearerAuthConfig {
         &self,
         request: &AuthRequest,
         BearerAuthArgument(token): BearerAuthArgument,
-    ) -> Result<Option<u64>, AuthError> {
+        default_max_filesize: u64,
+        default_ttl_policy: &TtlPolicy,
+    ) -> Result<Authorization, AuthError> {
         // decode the jsonwebtoken and extract the claims as `serde_json::Value`
         let token = decode::<Value>(&token, &self.get_decoding_key(), &Validation::default())
             .map_err(|_| AuthError::InvalidAuth(None))?;
@@ -78,14 +82,23 @@ impl BearerAuthConfig {
                 _ => self.default_permissions.clone(),
             };
 
-            // check if the permissions include the right request ('download' or 'upload')
-            if (request == &AuthRequest::Download && !permissions.contains(&"download".to_string()))
-                || (request == &AuthRequest::Upload && !permissions.contains(&"upload".to_string()))
+            // check if the permissions include the right request ('download', 'upload' or 'list')
+            if !permissions
+                .iter()
+                .any(|permission| permission == request.permission_name())
             {
                 return Err(invalid_auth_bearer!());
             }
 
-            return Ok(max_filesize);
+            return Ok(Authorization {
+                max_filesize: max_filesize.unwrap_or(default_max_filesize),
+                identity: map
+                    .get("sub")
+                    .and_then(|sub| sub.as_str())
+                    .map(|sub| sub.to_string()),
+                admin: permissions.iter().any(|permission| permission == "admin"),
+                ttl_policy: self.ttl_policy.or(default_ttl_policy),
+            });
         }
         Err(AuthError::InvalidAuth(None))
     }
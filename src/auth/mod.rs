@@ -1,11 +1,15 @@
 use std::{env, fs::read_to_string};
 
-use crate::auth::{
-    basic::{BasicAuth, BasicAuthArgument},
-    bearer::BearerAuthArgument,
+use crate::{
+    auth::{
+        basic::{BasicAuth, BasicAuthArgument},
+        bearer::BearerAuthArgument,
+    },
+    file_data::deserialize_optional_duration,
 };
 use base64::prelude::*;
 use serde::Deserialize;
+use tracing::warn;
 
 use self::bearer::BearerAuthConfig;
 
@@ -15,11 +19,16 @@ mod bearer;
 /// iterate through a auth method until the authorization was successful or
 /// the method responded with a InvalidAuth error that contains a scheme (the authorization was partially successful)
 macro_rules! iterate_auth_method {
-    ($method:expr, $default_max_filesize:expr, $request:expr, $argument:expr) => {
+    ($method:expr, $default_max_filesize:expr, $default_ttl_policy:expr, $request:expr, $argument:expr) => {
         for auth in $method {
-            let auth_resp = auth.authorize($request, $argument);
-            if let Ok(filesize) = auth_resp {
-                return Ok(filesize.unwrap_or($default_max_filesize));
+            let auth_resp = auth.authorize(
+                $request,
+                $argument,
+                $default_max_filesize,
+                $default_ttl_policy,
+            );
+            if let Ok(authorization) = auth_resp {
+                return Ok(authorization);
             } else if let Err(e) = auth_resp {
                 if let AuthError::InvalidAuth(Some(_)) = &e {
                     return Err(e);
@@ -37,10 +46,70 @@ fn default_max_filesize() -> u64 {
     1024 * 1024 * 1024 * 10 // 10 GB
 }
 
+/// The rules for the ttl of uploaded files, unset values fall back to the global ones
+#[derive(Clone, Copy, Deserialize, Default)]
+pub struct TtlPolicy {
+    /// The ttl of uploads that don't set one
+    #[serde(default, deserialize_with = "deserialize_optional_duration")]
+    pub default_ttl: Option<u64>,
+    /// The maximal lifetime of a file, counted from its upload
+    #[serde(default, deserialize_with = "deserialize_optional_duration")]
+    pub max_ttl: Option<u64>,
+    /// Uploads must set a ttl, the default ttl isn't used
+    #[serde(default)]
+    pub require_ttl: Option<bool>,
+}
+
+impl TtlPolicy {
+    /// fills the unset values with the ones of `fallback`
+    pub fn or(self, fallback: &TtlPolicy) -> TtlPolicy {
+        TtlPolicy {
+            default_ttl: self.default_ttl.or(fallback.default_ttl),
+            max_ttl: self.max_ttl.or(fallback.max_ttl),
+            require_ttl: self.require_ttl.or(fallback.require_ttl),
+        }
+    }
+}
+
 #[derive(PartialEq)]
 pub enum AuthRequest {
     Upload,
     Download,
+    List,
+    /// managing the trash and reading the audit log, independent of the other permissions
+    Admin,
+}
+
+impl AuthRequest {
+    /// the name of the permission in the bearer token
+    pub fn permission_name(&self) -> &'static str {
+        match self {
+            Self::Upload => "upload",
+            Self::Download => "download",
+            Self::List => "list",
+            Self::Admin => "admin",
+        }
+    }
+}
+
+/// A successful authorization
+pub struct Authorization {
+    /// The maximal filesize the user can upload
+    pub max_filesize: u64,
+    /// The username (basic) or the `sub` claim (bearer), `None` if no authorization was required
+    pub identity: Option<String>,
+    /// The user has the `admin` permission
+    pub admin: bool,
+    /// The rules for the ttl of the files the user uploads
+    pub ttl_policy: TtlPolicy,
+}
+
+impl Authorization {
+    /// checks if the user owns something created by `owner`, admins own everything,
+    /// nobody owns what was created without authorization
+    pub fn owns(&self, owner: &Option<String>) -> bool {
+        self.admin || (self.identity.is_some() && owner == &self.identity)
+    }
 }
 
 #[derive(Debug)]
@@ -61,6 +130,9 @@ pub struct AuthConfig {
     pub allow_downloading_for_everyone: bool,
     #[serde(default)]
     pub allow_uploading_for_everyone: bool,
+    /// The ttl rules of unauthenticated uploads and auth methods that don't have their own
+    #[serde(flatten)]
+    pub ttl_policy: TtlPolicy,
     #[serde(default)]
     pub basic: Vec<BasicAuth>,
     #[serde(default)]
@@ -76,31 +148,57 @@ impl AuthConfig {
         if (!config.allow_downloading_for_everyone || !config.allow_uploading_for_everyone)
             && config.basic.is_empty()
         {
-            eprintln!(
-                "WARNING: You didn't configure any auth scheme. Downloading / uploading will be impossible."
+            warn!(
+                "you didn't configure any auth scheme, downloading / uploading will be impossible"
             );
         }
         config
     }
 
-    pub fn authorize(
+    /// downloading and uploading can be allowed for everyone
+    pub fn requires_authorization(&self, request: &AuthRequest) -> bool {
+        !((request == &AuthRequest::Download && self.allow_downloading_for_everyone)
+            || (request == &AuthRequest::Upload && self.allow_uploading_for_everyone))
+    }
+
+    /// the authorization of users without valid credentials, `error` if the request requires authorization
+    fn anonymous(
         &self,
         request: &AuthRequest,
-        authorization: Option<&str>,
-    ) -> Result<u64, AuthError> {
-        // check if no authorization is required
-        if (request == &AuthRequest::Download && self.allow_downloading_for_everyone)
-            || (request == &AuthRequest::Upload && self.allow_uploading_for_everyone)
-        {
-            return Ok(self.default_max_filesize);
+        error: AuthError,
+    ) -> Result<Authorization, AuthError> {
+        if self.requires_authorization(request) {
+            return Err(error);
         }
+        Ok(Authorization {
+            max_filesize: self.default_max_filesize,
+            identity: None,
+            admin: false,
+            ttl_policy: self.ttl_policy,
+        })
+    }
 
-        // authorization is required
-        if authorization.is_none() {
-            return Err(AuthError::InvalidAuth(None));
+    /// picks the result of the evaluated `credentials` (`None` if the request had no credentials),
+    /// requests that don't require authorization fall back to anonymous if the credentials are missing or invalid
+    pub fn authorize(
+        &self,
+        request: &AuthRequest,
+        credentials: Option<Result<Authorization, AuthError>>,
+    ) -> Result<Authorization, AuthError> {
+        match credentials {
+            Some(Ok(authorization)) => Ok(authorization),
+            Some(Err(error)) => self.anonymous(request, error),
+            None => self.anonymous(request, AuthError::InvalidAuth(None)),
         }
-        let authorization = authorization.unwrap();
+    }
 
+    /// evaluates the credentials of the authorization header, even if no authorization is required,
+    /// so users keep their own rules on open servers
+    pub fn authorize_credentials(
+        &self,
+        request: &AuthRequest,
+        authorization: &str,
+    ) -> Result<Authorization, AuthError> {
         // the authorization string should look like this: `<auth scheme> <payload>`
         let splitted = authorization.split(' ').collect::<Vec<&str>>();
         if splitted.len() != 2 {
@@ -124,6 +222,7 @@ impl AuthConfig {
             iterate_auth_method!(
                 &self.basic,
                 self.default_max_filesize,
+                &self.ttl_policy,
                 request,
                 BasicAuthArgument {
                     username: decoded[0],
@@ -134,6 +233,7 @@ impl AuthConfig {
             iterate_auth_method!(
                 &self.bearer,
                 self.default_max_filesize,
+                &self.ttl_policy,
                 request,
                 BearerAuthArgument(splitted[1].to_string())
             );
@@ -141,3 +241,188 @@ impl AuthConfig {
         Err(AuthError::InvalidAuth(None))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ttl_policy_falls_back_to_unset_values() {
+        let policy = TtlPolicy {
+            default_ttl: Some(10),
+            max_ttl: None,
+            require_ttl: Some(false),
+        };
+        let fallback = TtlPolicy {
+            default_ttl: Some(20),
+            max_ttl: Some(30),
+            require_ttl: Some(true),
+        };
+        let merged = policy.or(&fallback);
+        assert_eq!(merged.default_ttl, Some(10));
+        assert_eq!(merged.max_ttl, Some(30));
+        assert_eq!(merged.require_ttl, Some(false));
+
+        let merged = TtlPolicy::default().or(&TtlPolicy::default());
+        assert_eq!(merged.default_ttl, None);
+        assert_eq!(merged.max_ttl, None);
+        assert_eq!(merged.require_ttl, None);
+    }
+
+    fn user(identity: Option<&str>, admin: bool) -> Authorization {
+        Authorization {
+            max_filesize: 0,
+            identity: identity.map(str::to_string),
+            admin,
+            ttl_policy: TtlPolicy::default(),
+        }
+    }
+
+    #[test]
+    fn users_own_what_they_created() {
+        let alice = user(Some("alice"), false);
+        assert!(alice.owns(&Some("alice".to_string())));
+        assert!(!alice.owns(&Some("bob".to_string())));
+        assert!(!alice.owns(&None));
+    }
+
+    #[test]
+    fn anonymous_users_own_nothing() {
+        let anonymous = user(None, false);
+        // two anonymous users must not be treated as the same owner
+        assert!(!anonymous.owns(&None));
+        assert!(!anonymous.owns(&Some("alice".to_string())));
+    }
+
+    #[test]
+    fn admins_own_everything() {
+        assert!(user(Some("admin"), true).owns(&Some("alice".to_string())));
+        assert!(user(Some("admin"), true).owns(&None));
+        assert!(user(None, true).owns(&None));
+    }
+
+    fn auth_config(allow_for_everyone: bool) -> AuthConfig {
+        let password = bcrypt::hash("secret", 4).unwrap();
+        toml::from_str(&format!(
+            r#"
+            allow_downloading_for_everyone = {allow_for_everyone}
+            allow_uploading_for_everyone = {allow_for_everyone}
+
+            [[basic]]
+            username = "alice"
+            password = "{password}"
+
+            [[basic]]
+            username = "bob"
+            password = "{password}"
+            allow_download = false
+            "#
+        ))
+        .unwrap()
+    }
+
+    fn basic(username: &str, password: &str) -> String {
+        format!(
+            "Basic {}",
+            BASE64_STANDARD.encode(format!("{username}:{password}"))
+        )
+    }
+
+    fn authorize(
+        config: &AuthConfig,
+        request: AuthRequest,
+        authorization: Option<&str>,
+    ) -> Result<Authorization, AuthError> {
+        let credentials = authorization
+            .map(|authorization| config.authorize_credentials(&request, authorization));
+        config.authorize(&request, credentials)
+    }
+
+    #[test]
+    fn open_server_allows_wrong_credentials_anonymously() {
+        let config = auth_config(true);
+        for authorization in [
+            basic("alice", "wrong"),
+            basic("unknown", "secret"),
+            basic("bob", "secret"),
+            "Bearer invalid.jwt.token".to_string(),
+            "Digest something".to_string(),
+            "garbage".to_string(),
+        ] {
+            let authorization = authorize(&config, AuthRequest::Download, Some(&authorization))
+                .unwrap_or_else(|_| panic!("{authorization} was rejected"));
+            assert_eq!(authorization.identity, None);
+            assert!(!authorization.admin);
+        }
+        assert!(authorize(&config, AuthRequest::Upload, Some(&basic("alice", "wrong"))).is_ok());
+    }
+
+    #[test]
+    fn open_server_keeps_valid_credentials() {
+        let config = auth_config(true);
+        let authorization = authorize(
+            &config,
+            AuthRequest::Download,
+            Some(&basic("alice", "secret")),
+        )
+        .ok()
+        .unwrap();
+        assert_eq!(authorization.identity.as_deref(), Some("alice"));
+        let authorization = authorize(&config, AuthRequest::Download, None)
+            .ok()
+            .unwrap();
+        assert_eq!(authorization.identity, None);
+    }
+
+    #[test]
+    fn closed_server_rejects_wrong_or_missing_credentials() {
+        let config = auth_config(false);
+        assert!(matches!(
+            authorize(
+                &config,
+                AuthRequest::Download,
+                Some(&basic("alice", "wrong"))
+            ),
+            Err(AuthError::InvalidAuth(Some(_)))
+        ));
+        assert!(authorize(
+            &config,
+            AuthRequest::Download,
+            Some(&basic("bob", "secret"))
+        )
+        .is_err());
+        assert!(authorize(&config, AuthRequest::Download, None).is_err());
+        // listing always requires authorization
+        assert!(authorize(
+            &auth_config(true),
+            AuthRequest::List,
+            Some(&basic("alice", "wrong"))
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn admins_are_authorized_without_the_list_permission() {
+        let password = bcrypt::hash("secret", 4).unwrap();
+        let config: AuthConfig = toml::from_str(&format!(
+            r#"
+            [[basic]]
+            username = "admin"
+            password = "{password}"
+            admin = true
+
+            [[basic]]
+            username = "alice"
+            password = "{password}"
+            allow_list = true
+            "#
+        ))
+        .unwrap();
+        let admin = authorize(&config, AuthRequest::Admin, Some(&basic("admin", "secret")))
+            .ok()
+            .unwrap();
+        assert!(admin.admin);
+        assert!(authorize(&config, AuthRequest::List, Some(&basic("admin", "secret"))).is_err());
+        assert!(authorize(&config, AuthRequest::Admin, Some(&basic("alice", "secret"))).is_err());
+    }
+}
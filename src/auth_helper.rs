@@ -1,26 +1,54 @@
 use axum::http::HeaderMap;
 
 use crate::{
-    auth::{AuthError, AuthRequest},
+    audit,
+    auth::{AuthError, AuthRequest, Authorization},
     error::UploadError,
+    logging,
     state::State,
 };
 
+/// only admins can manage the trash and read the audit log, they don't need the `list` permission
+pub fn authorize_admin(state: &State, headers: &HeaderMap) -> Result<Authorization, UploadError> {
+    let authorization = authorize_by_headers(state, headers, AuthRequest::Admin)?;
+    if !authorization.admin {
+        return Err(UploadError::Forbidden);
+    }
+    Ok(authorization)
+}
+
 pub fn authorize_by_headers(
     state: &State,
     headers: &HeaderMap,
     request: AuthRequest,
-) -> Result<u64, UploadError> {
-    state
-        .auth_config
-        .authorize(
-            &request,
-            headers.get("Authorization").map(|h| h.to_str().unwrap()),
-        )
-        .map_err(|err| match err {
-            AuthError::InvalidAuth(scheme) => match scheme {
-                Some(scheme) => UploadError::InvalidAuth(scheme), // the authorization was partially correct, we do know the correct authorization scheme
-                None => UploadError::InvalidAuth(state.auth_config.default_auth_scheme.clone()), // we take the default authorization scheme
-            },
-        })
+) -> Result<Authorization, UploadError> {
+    let authorization = headers.get("Authorization").map(|h| h.to_str().unwrap());
+    let credentials = authorization.map(|authorization| {
+        let credentials = state
+            .auth_config
+            .authorize_credentials(&request, authorization);
+        // only authorizations with a known scheme are counted
+        if let Some(scheme @ ("Basic" | "Bearer")) = authorization.split(' ').next() {
+            state.metrics.record_auth(scheme, credentials.is_ok());
+            if credentials.is_err() {
+                audit::record_auth_failure(state, authorization);
+            }
+        }
+        credentials
+    });
+    let result = state.auth_config.authorize(&request, credentials);
+    if let Ok(Authorization {
+        identity: Some(identity),
+        ..
+    }) = &result
+    {
+        logging::record_identity(identity);
+        audit::record_identity(identity);
+    }
+    result.map_err(|err| match err {
+        AuthError::InvalidAuth(scheme) => match scheme {
+            Some(scheme) => UploadError::InvalidAuth(scheme), // the authorization was partially correct, we do know the correct authorization scheme
+            None => UploadError::InvalidAuth(state.auth_config.default_auth_scheme.clone()), // we take the default authorization scheme
+        },
+    })
 }
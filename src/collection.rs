@@ -0,0 +1,668 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use axum::{
+    body::Body,
+    extract,
+    http::{header, HeaderMap, Response, StatusCode},
+    response::IntoResponse,
+    Form, Json,
+};
+use bcrypt::{hash, verify, DEFAULT_COST};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{read_dir, remove_file},
+    io,
+};
+use tracing::{error, info};
+
+use crate::{
+    archive::{archive_response, ArchiveFormat},
+    audit::{self, AuditAction, AuditEvent},
+    auth::{AuthRequest, Authorization},
+    auth_helper::authorize_by_headers,
+    download::check_download_permission,
+    error::UploadError,
+    file_data::{current_unix_timestamp, read_json, write_json, FileData, FileDataPermission},
+    filename::is_valid_stored_filename,
+    list::{stored_files, FileEntry, StoredFile},
+    pages::{self, accepts_html},
+    random::generate_random_characters,
+    state::State,
+    trash::remove_stored_file,
+    ttl_killer::Expiring,
+    update::changeable_file_data,
+    webhook::{self, WebhookEvent},
+};
+
+const COLLECTION_TITLE_HEADER_NAME: &str = "Collection-Title";
+const COLLECTION_PASSWORD_HEADER_NAME: &str = "Collection-Password";
+
+/// the length of the random ids of new collections
+const ID_LENGTH: usize = 16;
+
+/// a group of files that share one link, the files inherit the download permission, the ttl and the password
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Collection {
+    /// the title shown on the collection page
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub download_permission: Option<FileDataPermission>,
+    /// delete the collection and its files at this timestamp
+    #[serde(default)]
+    pub ttl: Option<u64>,
+    /// the bcrypt hash of the password that is required to download the files
+    #[serde(default)]
+    pub password: Option<String>,
+    /// the unix timestamp the collection was created at
+    #[serde(default)]
+    pub created_at: u64,
+    /// the identity of the user that created the collection
+    #[serde(default)]
+    pub creator: Option<String>,
+}
+
+/// collection ids are alphanumeric, so they can't escape the collection directory
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 64 && id.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// the name of the cookie that unlocks a password protected collection in the browser
+fn cookie_name(id: &str) -> String {
+    format!("collection_{id}")
+}
+
+/// returns the value of the cookie `name` of the request
+fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(cookie_name, _)| *cookie_name == name)
+        .map(|(_, value)| value)
+}
+
+impl Collection {
+    fn path(state: &State, id: &str) -> PathBuf {
+        state.collection_directory.join(id)
+    }
+
+    /// reads the collection, returns `None` if it doesn't exist
+    pub async fn read(state: &State, id: &str) -> Result<Option<Self>, UploadError> {
+        if !is_valid_id(id) {
+            return Ok(None);
+        }
+        read_json(&Self::path(state, id), state.encryption.as_deref()).await
+    }
+
+    async fn write(&self, state: &State, id: &str) -> Result<(), UploadError> {
+        write_json(self, &Self::path(state, id), state.encryption.as_deref()).await
+    }
+
+    /// parses the settings of the collection from the `Collection-*` and `File-Data-*` headers
+    fn from_headers(headers: &HeaderMap) -> Result<Self, UploadError> {
+        let file_data = FileData::try_from(headers.clone())?;
+        let title = headers
+            .get(COLLECTION_TITLE_HEADER_NAME)
+            .map(|value| {
+                String::from_utf8(value.as_bytes().to_vec()).map_err(|_| {
+                    UploadError::InvalidFileDataArgument(COLLECTION_TITLE_HEADER_NAME.to_string())
+                })
+            })
+            .transpose()?;
+        let password = headers
+            .get(COLLECTION_PASSWORD_HEADER_NAME)
+            .map(|value| {
+                value
+                    .to_str()
+                    .ok()
+                    .filter(|password| !password.is_empty())
+                    .and_then(|password| hash(password, DEFAULT_COST).ok())
+                    .ok_or_else(|| {
+                        UploadError::InvalidFileDataArgument(
+                            COLLECTION_PASSWORD_HEADER_NAME.to_string(),
+                        )
+                    })
+            })
+            .transpose()?;
+        Ok(Self {
+            title,
+            download_permission: file_data.download_permission,
+            ttl: file_data.ttl,
+            password,
+            ..Default::default()
+        })
+    }
+
+    /// checks if the collection should be dead
+    pub fn expired(&self) -> bool {
+        self.ttl.is_some_and(|ttl| ttl < current_unix_timestamp())
+    }
+
+    /// the file inherits the download permission (if it doesn't have its own) and the ttl (if it's earlier)
+    pub fn apply_to(&self, file_data: &mut FileData) {
+        if file_data.download_permission.is_none() {
+            file_data.download_permission = self.download_permission.clone();
+        }
+        file_data.ttl = match (file_data.ttl, self.ttl) {
+            (Some(file_ttl), Some(ttl)) => Some(file_ttl.min(ttl)),
+            (file_ttl, ttl) => file_ttl.or(ttl),
+        };
+    }
+
+    /// checks if the user may change the collection, admins may change every collection and anonymous collections only admins
+    pub fn may_change(&self, authorization: &Authorization) -> bool {
+        authorization.owns(&self.creator)
+    }
+
+    /// the value of the cookie that unlocks the collection, it's derived from the password hash,
+    /// so it can't be created without knowing the password
+    fn unlock_token(&self) -> Option<blake3::Hash> {
+        self.password
+            .as_ref()
+            .map(|password| blake3::hash(password.as_bytes()))
+    }
+
+    /// checks the password of the `Collection-Password` header or the cookie set by the collection page
+    pub fn check_password(&self, id: &str, headers: &HeaderMap) -> Result<(), UploadError> {
+        let Some(password_hash) = &self.password else {
+            return Ok(());
+        };
+        if let Some(password) = headers.get(COLLECTION_PASSWORD_HEADER_NAME) {
+            let password = password.to_str().unwrap_or_default();
+            return match verify(password, password_hash).unwrap_or_default() {
+                true => Ok(()),
+                false => Err(UploadError::InvalidCollectionPassword),
+            };
+        }
+        let token =
+            cookie(headers, &cookie_name(id)).and_then(|token| blake3::Hash::from_hex(token).ok());
+        // comparing hashes takes constant time
+        match token.is_some() && token == self.unlock_token() {
+            true => Ok(()),
+            false => Err(UploadError::InvalidCollectionPassword),
+        }
+    }
+}
+
+/// the files of the collection sorted by their filename
+pub async fn members(state: &State, id: &str) -> Result<Vec<StoredFile>, UploadError> {
+    let mut files = stored_files(state)
+        .await?
+        .into_iter()
+        .filter(|file| file.file_data.collection.as_deref() == Some(id))
+        .collect::<Vec<StoredFile>>();
+    files.sort_by(|a, b| a.filename().cmp(b.filename()).then(a.name.cmp(&b.name)));
+    Ok(files)
+}
+
+/// removes the collection, its files get removed too if `remove_files` is set, otherwise they leave the collection
+pub async fn remove_collection(
+    state: &State,
+    id: &str,
+    remove_files: bool,
+) -> Result<(), UploadError> {
+    // the files of an expired collection expire with it
+    let expired = Collection::read(state, id)
+        .await?
+        .is_some_and(|collection| collection.expired());
+    let (action, event) = if expired {
+        (AuditAction::Expire, WebhookEvent::Expired)
+    } else {
+        (AuditAction::Delete, WebhookEvent::Deleted)
+    };
+    for file in members(state, id).await? {
+        if remove_files {
+            remove_stored_file(state, &file.name, "removed with its collection").await?;
+            audit::record(
+                state,
+                AuditEvent::file(action, &file.name, &file.file_data)
+                    .with_detail("removed with its collection"),
+            );
+            webhook::notify(state, event, &file.name, &file.file_data);
+            info!(file = %file.name, collection = %id, "file got removed with its collection");
+        } else {
+            let _lock = state.file_data_lock.lock().await;
+            // the file data could have been changed since the members were read
+            let data_path = state.data_directory.join(&file.name);
+            let Some(mut file_data) =
+                FileData::read_from(&data_path, state.encryption.as_deref()).await?
+            else {
+                continue;
+            };
+            file_data.collection = None;
+            file_data
+                .write_to(&data_path, state.encryption.as_deref())
+                .await?;
+        }
+    }
+    match remove_file(Collection::path(state, id)).await {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// removes the collections whose ttl was reached with their files, the other collections with a ttl get scheduled
+pub async fn remove_expired_collections(state: &State) -> Result<(), UploadError> {
+    let mut content = read_dir(&state.collection_directory).await?;
+    while let Some(file) = content.next_entry().await? {
+        let Ok(id) = file.file_name().into_string() else {
+            continue;
+        };
+        match Collection::read(state, &id).await {
+            Ok(Some(collection)) if collection.expired() => {
+                remove_collection(state, &id, true).await?;
+                state.metrics.record_ttl_deletion("collection");
+                info!(collection = %id, "collection got removed because the ttl was reached");
+            }
+            Ok(Some(collection)) => {
+                state
+                    .expiry_queue
+                    .schedule(collection.ttl, Expiring::Collection(id));
+            }
+            Err(e) => {
+                error!(collection = %id, error = ?e, "error while reading collection");
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// checks if the request may download the files of the collection like `authorize_download` does for files
+async fn authorize_collection(
+    state: &State,
+    headers: &HeaderMap,
+    id: &str,
+) -> Result<Collection, UploadError> {
+    let authorize_error = authorize_by_headers(state, headers, AuthRequest::Download).err();
+    let collection = Collection::read(state, id)
+        .await?
+        .ok_or(UploadError::CollectionNotExists)?;
+    check_download_permission(collection.download_permission.as_ref(), authorize_error).map_err(
+        |e| match e {
+            UploadError::FileNotExists => UploadError::CollectionNotExists,
+            e => e,
+        },
+    )?;
+    if collection.expired() {
+        info!(collection = %id, "collection got removed because the ttl was reached");
+        remove_collection(state, id, true).await?;
+        state.metrics.record_ttl_deletion("collection");
+        return Err(UploadError::CollectionNotExists);
+    }
+    collection.check_password(id, headers)?;
+    Ok(collection)
+}
+
+/// reads the collection and checks if the user may change it
+async fn authorize_change(
+    state: &State,
+    authorization: &Authorization,
+    id: &str,
+) -> Result<Collection, UploadError> {
+    let collection = Collection::read(state, id)
+        .await?
+        .filter(|collection| !collection.expired())
+        .ok_or(UploadError::CollectionNotExists)?;
+    if !collection.may_change(authorization) {
+        return Err(UploadError::Forbidden);
+    }
+    Ok(collection)
+}
+
+/// checks if a file can be uploaded into the collection `id`
+pub async fn authorize_upload(
+    state: &State,
+    authorization: &Authorization,
+    id: &str,
+) -> Result<(), UploadError> {
+    authorize_change(state, authorization, id).await.map(|_| ())
+}
+
+/// the files that are shown in the collection, files that can't be downloaded are hidden
+fn visible_members(files: &[StoredFile]) -> impl Iterator<Item = &StoredFile> {
+    files.iter().filter(|file| {
+        !matches!(
+            file.file_data.download_permission,
+            Some(FileDataPermission::None)
+        )
+    })
+}
+
+#[derive(Serialize)]
+struct CollectionEntry<'a> {
+    id: &'a str,
+    title: Option<&'a str>,
+    link: String,
+    created_at: u64,
+    creator: Option<&'a str>,
+    /// the unix timestamp the collection and its files will be deleted at
+    expires_at: Option<u64>,
+    download_permission: Option<&'a FileDataPermission>,
+    /// a password is required to download the files
+    password: bool,
+}
+
+impl<'a> CollectionEntry<'a> {
+    fn new(id: &'a str, collection: &'a Collection) -> Self {
+        Self {
+            id,
+            title: collection.title.as_deref(),
+            link: format!("/c/{id}"),
+            created_at: collection.created_at,
+            creator: collection.creator.as_deref(),
+            expires_at: collection.ttl,
+            download_permission: collection.download_permission.as_ref(),
+            password: collection.password.is_some(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CollectionResponse<'a> {
+    #[serde(flatten)]
+    collection: CollectionEntry<'a>,
+    files: Vec<FileEntry<'a>>,
+}
+
+#[derive(Serialize)]
+struct CollectionListEntry<'a> {
+    #[serde(flatten)]
+    collection: CollectionEntry<'a>,
+    /// the amount of files in the collection
+    files: usize,
+}
+
+#[derive(Serialize)]
+struct CollectionListResponse<'a> {
+    collections: Vec<CollectionListEntry<'a>>,
+}
+
+/// creates an empty collection with the settings of the headers
+pub async fn create_collection(
+    extract::State(state): extract::State<State>,
+    headers: HeaderMap,
+) -> Result<Response<Body>, UploadError> {
+    let authorization = authorize_by_headers(&state, &headers, AuthRequest::Upload)?;
+    let mut collection = Collection::from_headers(&headers)?;
+    collection.created_at = current_unix_timestamp();
+    collection.creator = authorization.identity;
+
+    let id = generate_random_characters(ID_LENGTH);
+    collection.write(&state, &id).await?;
+    state
+        .expiry_queue
+        .schedule(collection.ttl, Expiring::Collection(id.clone()));
+    info!(collection = %id, "created collection");
+
+    let link = format!("/c/{id}");
+    Ok(Response::builder()
+        .header("Location", &link)
+        .header("Content-Type", "text/plain")
+        .status(StatusCode::CREATED)
+        .body(Body::from(link))
+        .unwrap())
+}
+
+/// lists the collections of the user, admins see every collection
+pub async fn list_collections(
+    extract::State(state): extract::State<State>,
+    headers: HeaderMap,
+) -> Result<Response<Body>, UploadError> {
+    let authorization = authorize_by_headers(&state, &headers, AuthRequest::List)?;
+    let mut collections = Vec::new();
+    let mut content = read_dir(&state.collection_directory).await?;
+    while let Some(file) = content.next_entry().await? {
+        let Ok(id) = file.file_name().into_string() else {
+            continue;
+        };
+        match Collection::read(&state, &id).await {
+            Ok(Some(collection)) => {
+                if !collection.expired() && collection.may_change(&authorization) {
+                    collections.push((id, collection));
+                }
+            }
+            Ok(None) => {}
+            Err(e) => error!(collection = %id, error = ?e, "error while reading collection"),
+        }
+    }
+    collections
+        .sort_by(|(a_id, a), (b_id, b)| a.created_at.cmp(&b.created_at).then(a_id.cmp(b_id)));
+
+    let mut file_counts = HashMap::new();
+    for file in stored_files(&state).await? {
+        if let Some(id) = file.file_data.collection {
+            *file_counts.entry(id).or_insert(0) += 1;
+        }
+    }
+    let response = CollectionListResponse {
+        collections: collections
+            .iter()
+            .map(|(id, collection)| CollectionListEntry {
+                collection: CollectionEntry::new(id, collection),
+                files: file_counts.get(id).copied().unwrap_or_default(),
+            })
+            .collect(),
+    };
+    Ok(Json(response).into_response())
+}
+
+/// the collection with its files as json, browsers get the collection page
+pub async fn collection(
+    extract::Path(id): extract::Path<String>,
+    extract::State(state): extract::State<State>,
+    headers: HeaderMap,
+) -> Result<Response<Body>, UploadError> {
+    let html = accepts_html(&headers);
+    let collection = match authorize_collection(&state, &headers, &id).await {
+        // browsers get a form to enter the password
+        Err(UploadError::InvalidCollectionPassword) if html => {
+            return Ok(pages::collection_password_page(&id, false));
+        }
+        result => result?,
+    };
+    let files = members(&state, &id).await?;
+    if html {
+        let files = visible_members(&files).collect::<Vec<&StoredFile>>();
+        return Ok(pages::collection_page(&state, &id, &collection, &files));
+    }
+
+    let response = CollectionResponse {
+        collection: CollectionEntry::new(&id, &collection),
+        files: visible_members(&files).map(FileEntry::from).collect(),
+    };
+    Ok(Json(response).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct UnlockForm {
+    password: String,
+}
+
+/// checks the password entered on the collection page and sets the cookie that unlocks the collection
+pub async fn unlock_collection(
+    extract::Path(id): extract::Path<String>,
+    extract::State(state): extract::State<State>,
+    Form(form): Form<UnlockForm>,
+) -> Result<Response<Body>, UploadError> {
+    let collection = Collection::read(&state, &id)
+        .await?
+        .filter(|collection| !collection.expired())
+        .ok_or(UploadError::CollectionNotExists)?;
+    let (Some(password_hash), Some(token)) = (&collection.password, collection.unlock_token())
+    else {
+        return Err(UploadError::InvalidBody);
+    };
+    if !verify(&form.password, password_hash).unwrap_or_default() {
+        return Ok(pages::collection_password_page(&id, true));
+    }
+    Ok(Response::builder()
+        .status(StatusCode::SEE_OTHER)
+        .header("Location", format!("/c/{id}"))
+        .header(
+            "Set-Cookie",
+            format!(
+                "{}={}; Path=/; HttpOnly; SameSite=Lax",
+                cookie_name(&id),
+                token.to_hex()
+            ),
+        )
+        .body(Body::empty())
+        .unwrap())
+}
+
+#[derive(Deserialize)]
+pub struct DeleteCollectionQuery {
+    /// remove the files of the collection too
+    #[serde(default)]
+    files: bool,
+}
+
+/// removes the collection, its files stay (without a collection) unless `?files=true` is set
+pub async fn delete_collection(
+    extract::Path(id): extract::Path<String>,
+    extract::State(state): extract::State<State>,
+    extract::Query(query): extract::Query<DeleteCollectionQuery>,
+    headers: HeaderMap,
+) -> Result<Response<Body>, UploadError> {
+    let authorization = authorize_by_headers(&state, &headers, AuthRequest::Upload)?;
+    authorize_change(&state, &authorization, &id).await?;
+    remove_collection(&state, &id, query.files).await?;
+    info!(collection = %id, "removed collection");
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// adds a stored file to the collection, a file can only belong to one collection
+pub async fn add_member(
+    extract::Path((id, filename)): extract::Path<(String, String)>,
+    extract::State(state): extract::State<State>,
+    headers: HeaderMap,
+) -> Result<Response<Body>, UploadError> {
+    let authorization = authorize_by_headers(&state, &headers, AuthRequest::Upload)?;
+    authorize_change(&state, &authorization, &id).await?;
+    let _lock = state.file_data_lock.lock().await;
+    let mut file_data = changeable_file_data(&state, &authorization, &filename).await?;
+    file_data.collection = Some(id);
+    file_data
+        .write_to(
+            &state.data_directory.join(&filename),
+            state.encryption.as_deref(),
+        )
+        .await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// removes a file from the collection, the file itself stays
+pub async fn remove_member(
+    extract::Path((id, filename)): extract::Path<(String, String)>,
+    extract::State(state): extract::State<State>,
+    headers: HeaderMap,
+) -> Result<Response<Body>, UploadError> {
+    let authorization = authorize_by_headers(&state, &headers, AuthRequest::Upload)?;
+    authorize_change(&state, &authorization, &id).await?;
+    if !is_valid_stored_filename(&filename) || !state.upload_directory.join(&filename).exists() {
+        return Err(UploadError::FileNotExists);
+    }
+    let _lock = state.file_data_lock.lock().await;
+    let data_path = state.data_directory.join(&filename);
+    let mut file_data = FileData::read_from(&data_path, state.encryption.as_deref())
+        .await?
+        .unwrap_or_default();
+    if file_data.collection.as_deref() != Some(id.as_str()) {
+        return Err(UploadError::FileNotExists);
+    }
+    file_data.collection = None;
+    file_data
+        .write_to(&data_path, state.encryption.as_deref())
+        .await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+#[derive(Deserialize)]
+pub struct CollectionArchiveQuery {
+    #[serde(default)]
+    format: ArchiveFormat,
+}
+
+/// downloads all files of the collection as archive
+pub async fn collection_archive(
+    extract::Path(id): extract::Path<String>,
+    extract::State(state): extract::State<State>,
+    extract::Query(query): extract::Query<CollectionArchiveQuery>,
+    headers: HeaderMap,
+) -> Result<Response<Body>, UploadError> {
+    let collection = authorize_collection(&state, &headers, &id).await?;
+    // the password was verified once, the files get unlocked by the token like in the browser
+    let mut headers = headers;
+    if let Some(token) = collection.unlock_token() {
+        headers.remove(COLLECTION_PASSWORD_HEADER_NAME);
+        let cookie = format!("{}={}", cookie_name(&id), token.to_hex());
+        headers.append(
+            header::COOKIE,
+            cookie
+                .parse()
+                .map_err(|_| UploadError::InternalServerError)?,
+        );
+    }
+    let files = members(&state, &id).await?;
+    let names = visible_members(&files)
+        .map(|file| file.name.clone())
+        .collect::<Vec<String>>();
+    let filename = format!(
+        "{}.{}",
+        collection.title.as_deref().unwrap_or(&id),
+        query.format.extension()
+    );
+    archive_response(state, &headers, names, query.format, &filename).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::TtlPolicy;
+
+    fn user(identity: Option<&str>, admin: bool) -> Authorization {
+        Authorization {
+            max_filesize: 0,
+            identity: identity.map(str::to_string),
+            admin,
+            ttl_policy: TtlPolicy::default(),
+        }
+    }
+
+    fn created_by(creator: Option<&str>) -> Collection {
+        Collection {
+            creator: creator.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn creator_may_change_the_collection() {
+        let collection = created_by(Some("alice"));
+        assert!(collection.may_change(&user(Some("alice"), false)));
+        assert!(!collection.may_change(&user(Some("bob"), false)));
+        assert!(!collection.may_change(&user(None, false)));
+        assert!(collection.may_change(&user(Some("admin"), true)));
+    }
+
+    #[test]
+    fn anonymous_collections_may_only_be_changed_by_admins() {
+        let collection = created_by(None);
+        assert!(!collection.may_change(&user(None, false)));
+        assert!(!collection.may_change(&user(Some("alice"), false)));
+        assert!(collection.may_change(&user(Some("admin"), true)));
+    }
+
+    #[test]
+    fn collection_ids_are_alphanumeric() {
+        assert!(is_valid_id("abc123"));
+        assert!(!is_valid_id(""));
+        assert!(!is_valid_id("../abc"));
+        assert!(!is_valid_id("abc/def"));
+        assert!(!is_valid_id(&"a".repeat(65)));
+    }
+}
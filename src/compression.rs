@@ -0,0 +1,302 @@
+use async_compression::{
+    tokio::bufread::{BrotliEncoder, GzipEncoder, ZstdEncoder},
+    Level,
+};
+use axum::{
+    body::Body,
+    http::{header, HeaderMap},
+};
+use serde::Deserialize;
+use tokio::io::{AsyncRead, BufReader};
+use tokio_util::io::ReaderStream;
+
+use crate::content_type::essence;
+
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    Zstd,
+    #[serde(alias = "br")]
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    /// the name of the encoding in the `Accept-Encoding` and `Content-Encoding` header
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Zstd => "zstd",
+            Self::Brotli => "br",
+            Self::Gzip => "gzip",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "zstd" => Some(Self::Zstd),
+            "br" => Some(Self::Brotli),
+            "gzip" | "x-gzip" => Some(Self::Gzip),
+            _ => None,
+        }
+    }
+
+    /// compresses the content of `reader` while it gets streamed as body
+    /// the levels are chosen to be fast enough for compressing on the fly
+    pub fn encode<R: AsyncRead + Send + 'static>(&self, reader: R) -> Body {
+        let reader = BufReader::new(reader);
+        match self {
+            Self::Zstd => Body::from_stream(ReaderStream::new(ZstdEncoder::with_quality(
+                reader,
+                Level::Default,
+            ))),
+            Self::Brotli => Body::from_stream(ReaderStream::new(BrotliEncoder::with_quality(
+                reader,
+                Level::Precise(4),
+            ))),
+            Self::Gzip => Body::from_stream(ReaderStream::new(GzipEncoder::with_quality(
+                reader,
+                Level::Default,
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct CompressionConfig {
+    /// Compress downloads on the fly if the client accepts it
+    pub enabled: bool,
+    /// Files smaller than this amount of bytes are never compressed
+    pub min_size: u64,
+    /// The encodings the server may use, the order is the preference of the server
+    pub encodings: Vec<Encoding>,
+    /// Content types that are compressible in addition to `text/*`, `*+json` and `*+xml`
+    pub compressible_types: Vec<String>,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_size: 1024,
+            encodings: vec![Encoding::Zstd, Encoding::Brotli, Encoding::Gzip],
+            compressible_types: [
+                "application/json",
+                "application/xml",
+                "application/javascript",
+                "application/x-ndjson",
+                "application/x-yaml",
+                "application/yaml",
+                "application/toml",
+                "application/sql",
+                "application/wasm",
+                "image/svg+xml",
+                "image/bmp",
+                "application/x-tar",
+            ]
+            .iter()
+            .map(|t| t.to_string())
+            .collect(),
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// checks if the content type is worth compressing, already compressed formats (images, videos, archives...) are not
+    pub fn is_compressible(&self, content_type: &str) -> bool {
+        let essence = essence(content_type).to_ascii_lowercase();
+        essence.starts_with("text/")
+            || essence.ends_with("+json")
+            || essence.ends_with("+xml")
+            || self
+                .compressible_types
+                .iter()
+                .any(|compressible| compressible.eq_ignore_ascii_case(&essence))
+    }
+
+    /// chooses the encoding the response should be compressed with
+    /// returns `None` if the response should not be compressed
+    pub fn negotiate(
+        &self,
+        headers: &HeaderMap,
+        content_type: &str,
+        size: Option<u64>,
+    ) -> Option<Encoding> {
+        // range requests refer to the bytes of the uncompressed file
+        if !self.enabled || headers.contains_key(header::RANGE) {
+            return None;
+        }
+        if size.unwrap_or_default() < self.min_size || !self.is_compressible(content_type) {
+            return None;
+        }
+        self.negotiate_encoding(headers)
+    }
+
+    /// chooses the encoding with the highest quality value of the `Accept-Encoding` header
+    /// the server preference decides if the quality values are equal
+    fn negotiate_encoding(&self, headers: &HeaderMap) -> Option<Encoding> {
+        let accept_encoding = headers.get(header::ACCEPT_ENCODING)?.to_str().ok()?;
+        let mut best: Option<(Encoding, f32)> = None;
+        for encoding in &self.encodings {
+            let quality = quality(accept_encoding, *encoding);
+            if quality > 0.0 && best.map(|(_, best)| quality > best).unwrap_or(true) {
+                best = Some((*encoding, quality));
+            }
+        }
+        best.map(|(encoding, _)| encoding)
+    }
+}
+
+/// returns the quality value the `Accept-Encoding` header value gives the `encoding`
+/// the header looks like this: `gzip;q=0.5, br, *;q=0.1`, the quality is 1 by default
+fn quality(accept_encoding: &str, encoding: Encoding) -> f32 {
+    let mut wildcard: Option<f32> = None;
+    for element in accept_encoding.split(',') {
+        let mut parts = element.split(';');
+        let name = parts.next().unwrap_or_default().trim();
+        let quality = parts
+            .map(str::trim)
+            .find(|parameter| {
+                parameter
+                    .get(..2)
+                    .is_some_and(|name| name.eq_ignore_ascii_case("q="))
+            })
+            .map_or(Some(1.0), |parameter| {
+                parameter[2..]
+                    .trim()
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|quality| (0.0..=1.0).contains(quality))
+            });
+        // elements with a malformed quality value are ignored
+        let Some(quality) = quality else {
+            continue;
+        };
+        if name == "*" {
+            wildcard = Some(quality);
+        } else if Encoding::from_name(name) == Some(encoding) {
+            return quality;
+        }
+    }
+    wildcard.unwrap_or_default()
+}
+
+/// checks if the client accepts a response body compressed with `encoding`
+pub fn accepts(headers: &HeaderMap, encoding: Encoding) -> bool {
+    headers
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .map(|accept_encoding| quality(accept_encoding, encoding) > 0.0)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(accept_encoding: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_ENCODING, accept_encoding.parse().unwrap());
+        headers
+    }
+
+    fn config() -> CompressionConfig {
+        CompressionConfig {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn quality_defaults_to_one() {
+        assert_eq!(quality("gzip, br", Encoding::Gzip), 1.0);
+        assert_eq!(quality("GZIP", Encoding::Gzip), 1.0);
+        assert_eq!(quality("x-gzip;q=0.5", Encoding::Gzip), 0.5);
+        assert_eq!(quality("gzip ; Q=0.3", Encoding::Gzip), 0.3);
+        assert_eq!(quality("br", Encoding::Gzip), 0.0);
+        assert_eq!(quality("", Encoding::Gzip), 0.0);
+    }
+
+    #[test]
+    fn quality_zero_refuses_the_encoding() {
+        assert_eq!(quality("gzip;q=0", Encoding::Gzip), 0.0);
+        assert!(!accepts(&headers("gzip;q=0, br"), Encoding::Gzip));
+        assert!(accepts(&headers("gzip;q=0, br"), Encoding::Brotli));
+        assert_eq!(
+            config().negotiate_encoding(&headers("zstd;q=0, gzip;q=0")),
+            None
+        );
+    }
+
+    #[test]
+    fn wildcard_applies_to_unlisted_encodings() {
+        assert_eq!(quality("*", Encoding::Zstd), 1.0);
+        assert_eq!(quality("gzip, *;q=0.2", Encoding::Zstd), 0.2);
+        // the explicit value wins over the wildcard
+        assert_eq!(quality("*;q=0.2, zstd;q=0.8", Encoding::Zstd), 0.8);
+        assert_eq!(quality("*;q=0, gzip", Encoding::Zstd), 0.0);
+        assert_eq!(quality("*;q=0, gzip", Encoding::Gzip), 1.0);
+    }
+
+    #[test]
+    fn malformed_quality_values_are_ignored() {
+        for accept_encoding in [
+            "gzip;q=abc",
+            "gzip;q=2",
+            "gzip;q=-1",
+            "gzip;q=NaN",
+            "gzip;q=",
+        ] {
+            assert_eq!(
+                quality(accept_encoding, Encoding::Gzip),
+                0.0,
+                "{accept_encoding}"
+            );
+        }
+        assert_eq!(quality("gzip;q=abc, *;q=0.4", Encoding::Gzip), 0.4);
+        assert_eq!(quality("gzip;level=1", Encoding::Gzip), 1.0);
+    }
+
+    #[test]
+    fn highest_quality_wins_and_ties_follow_the_server_order() {
+        let config = config();
+        assert_eq!(
+            config.negotiate_encoding(&headers("gzip, br;q=0.9, zstd;q=0.1")),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(
+            config.negotiate_encoding(&headers("gzip, br, zstd")),
+            Some(Encoding::Zstd)
+        );
+        let config = CompressionConfig {
+            encodings: vec![Encoding::Gzip, Encoding::Brotli],
+            ..config
+        };
+        assert_eq!(
+            config.negotiate_encoding(&headers("*")),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(config.negotiate_encoding(&headers("zstd")), None);
+        assert_eq!(config.negotiate_encoding(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn negotiate_skips_small_incompressible_and_range_requests() {
+        let config = config();
+        let accept = headers("gzip");
+        assert_eq!(
+            config.negotiate(&accept, "text/plain", Some(4096)),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(config.negotiate(&accept, "text/plain", Some(10)), None);
+        assert_eq!(config.negotiate(&accept, "image/png", Some(4096)), None);
+        let mut range = accept.clone();
+        range.insert(header::RANGE, "bytes=0-10".parse().unwrap());
+        assert_eq!(config.negotiate(&range, "text/plain", Some(4096)), None);
+        let disabled = CompressionConfig {
+            enabled: false,
+            ..config
+        };
+        assert_eq!(disabled.negotiate(&accept, "text/plain", Some(4096)), None);
+    }
+}
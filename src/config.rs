@@ -0,0 +1,72 @@
+use std::{env, fs::read_to_string, path::Path};
+
+use serde::Deserialize;
+
+use crate::{
+    audit::AuditConfig, compression::CompressionConfig, content_type::ContentTypeConfig,
+    encryption::EncryptionConfig, eviction::EvictionConfig, filename::FilenamePolicy,
+    metrics::MetricsConfig, pages::PagesConfig, recovery::RecoveryConfig,
+    retention::RetentionConfig, storage::StorageConfig, thumbnail::ThumbnailConfig,
+    trash::TrashConfig, ttl_killer::ExpiryConfig, webhook::WebhookConfig,
+};
+
+#[derive(Clone, Deserialize, Default)]
+pub struct Config {
+    /// The policy uploaded filenames are checked against
+    #[serde(default)]
+    pub filename: FilenamePolicy,
+    /// The detection of content types and how they are served
+    #[serde(default)]
+    pub content_type: ContentTypeConfig,
+    /// The on the fly compression of downloads
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    /// How uploaded files are stored
+    #[serde(default)]
+    pub storage: StorageConfig,
+    /// The encryption of stored files and their file data
+    #[serde(default)]
+    pub encryption: EncryptionConfig,
+    /// The html pages of files and the browsable index
+    #[serde(default)]
+    pub pages: PagesConfig,
+    /// The thumbnails of uploaded images
+    #[serde(default)]
+    pub thumbnails: ThumbnailConfig,
+    /// The removal of expired files and collections
+    #[serde(default)]
+    pub expiry: ExpiryConfig,
+    /// The lifetime of files depending on their size
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    /// The trash removed files are kept in for a while
+    #[serde(default)]
+    pub trash: TrashConfig,
+    /// The eviction of files when the disk is nearly full
+    #[serde(default)]
+    pub eviction: EvictionConfig,
+    /// The recovery at startup and the integrity scrubs
+    #[serde(default)]
+    pub recovery: RecoveryConfig,
+    /// The Prometheus metrics of the server
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    /// The audit log of file and auth events
+    #[serde(default)]
+    pub audit: AuditConfig,
+    /// The webhooks notified about uploads, downloads, expiries and deletions
+    #[serde(default)]
+    pub webhooks: WebhookConfig,
+}
+
+impl Config {
+    pub fn read_from_file() -> Self {
+        let path = env::var("CONFIG_PATH").unwrap_or_else(|_| "config.toml".to_string());
+        // the config file is optional, every value has a default
+        if !Path::new(&path).exists() {
+            return Self::default();
+        }
+        let toml_content = read_to_string(path).expect("Error while reading config file");
+        toml::from_str(&toml_content).expect("Error while parsing config file")
+    }
+}
@@ -0,0 +1,106 @@
+use serde::Deserialize;
+
+/// the amount of bytes at the beginning of a file that are used to detect the content type
+pub const SNIFF_LENGTH: usize = 8192;
+
+pub const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct ContentTypeConfig {
+    /// Detect the content type of uploaded files, otherwise every file is `application/octet-stream`
+    pub detect: bool,
+    /// Allow serving dangerous content types inline (this makes stored XSS possible)
+    pub allow_dangerous_inline: bool,
+    /// Content types that can execute scripts in the browser and will always be served as attachment
+    pub dangerous_types: Vec<String>,
+}
+
+impl Default for ContentTypeConfig {
+    fn default() -> Self {
+        Self {
+            detect: true,
+            allow_dangerous_inline: false,
+            dangerous_types: [
+                "text/html",
+                "image/svg+xml",
+                "application/xhtml+xml",
+                "application/xml",
+                "text/xml",
+                "application/javascript",
+                "text/javascript",
+            ]
+            .iter()
+            .map(|t| t.to_string())
+            .collect(),
+        }
+    }
+}
+
+impl ContentTypeConfig {
+    /// checks if a file with the `content_type` may be served with an `inline` disposition
+    pub fn may_be_inline(&self, content_type: &str) -> bool {
+        if self.allow_dangerous_inline {
+            return true;
+        }
+        let essence = essence(content_type);
+        !self
+            .dangerous_types
+            .iter()
+            .any(|dangerous| dangerous.eq_ignore_ascii_case(essence))
+    }
+}
+
+/// returns the content type without parameters (e.g. `text/plain` for `text/plain; charset=utf-8`)
+pub fn essence(content_type: &str) -> &str {
+    content_type.split(';').next().unwrap_or_default().trim()
+}
+
+/// checks if files with the `content_type` contain text that can be shown to the user
+pub fn is_text_type(content_type: &str) -> bool {
+    let essence = essence(content_type).to_ascii_lowercase();
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence.as_str(),
+            "application/json"
+                | "application/xml"
+                | "application/javascript"
+                | "application/x-ndjson"
+                | "application/x-yaml"
+                | "application/yaml"
+                | "application/toml"
+                | "application/sql"
+                | "application/x-sh"
+        )
+}
+
+/// checks if the bytes look like utf-8 text, `head` may end in the middle of a character
+fn is_text(head: &[u8]) -> bool {
+    if head.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        // the error is only caused by a character that got cut at the end
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+/// detects the content type by the magic bytes at the beginning of the file (`head`)
+/// and falls back to the extension of the `filename`
+pub fn detect(filename: &str, head: &[u8]) -> String {
+    if let Some(kind) = infer::get(head) {
+        return kind.mime_type().to_string();
+    }
+    let text = is_text(head);
+    match mime_guess::from_path(filename).first() {
+        Some(mime) if mime.type_() == mime_guess::mime::TEXT && text => {
+            format!("{}; charset=utf-8", mime.essence_str())
+        }
+        Some(mime) => mime.essence_str().to_string(),
+        None if text => "text/plain; charset=utf-8".to_string(),
+        None => DEFAULT_CONTENT_TYPE.to_string(),
+    }
+}
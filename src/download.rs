@@ -6,80 +6,388 @@ use std::os::windows::fs::MetadataExt;
 use axum::{
     body::Body,
     extract,
-    http::{HeaderMap, Response, StatusCode},
+    http::{header, HeaderMap, HeaderValue, Method, Response, StatusCode},
+    response::IntoResponse,
+    Json,
 };
-use tokio::fs::{remove_file, File};
+use serde::{Deserialize, Serialize};
+use tokio::{fs::File, io::AsyncReadExt};
 use tokio_util::io::ReaderStream;
+use tracing::{error, info};
 
 use crate::{
+    audit::{self, AuditAction, AuditEvent},
     auth::AuthRequest,
     auth_helper::authorize_by_headers,
+    collection::{remove_collection, Collection},
+    compression::{accepts, Encoding},
+    content_type::DEFAULT_CONTENT_TYPE,
+    encryption::{plaintext_size, Encryption},
     error::UploadError,
-    file_data::{FileData, FileDataPermission},
+    eviction::record_download,
+    file_data::{
+        FileData, FileDataPermission, FILE_DATA_ENCRYPTED_HEADER_NAME,
+        FILE_DATA_ENCRYPTION_CHUNK_SIZE_HEADER_NAME, FILE_DATA_ENCRYPTION_NONCE_HEADER_NAME,
+        FILE_DATA_EXPIRES_AT_HEADER_NAME, FILE_DATA_META_HEADER_PREFIX, FILE_DATA_TAGS_HEADER_NAME,
+    },
+    filename::{content_disposition, is_valid_stored_filename},
+    list::{FileEntry, StoredFile},
+    logging::record_bytes,
+    pages::{self, accepts_html},
+    range::parse_range,
     state::State,
+    storage,
+    trash::remove_stored_file,
+    webhook::{self, WebhookEvent},
 };
 
-pub async fn download(
-    extract::Path(filename): extract::Path<String>,
-    extract::State(state): extract::State<State>,
-    headers: HeaderMap,
-) -> Result<Response<Body>, UploadError> {
-    let download_path = state.upload_directory.join(&filename);
+#[derive(Deserialize)]
+pub struct DownloadQuery {
+    /// `?inline=1` requests an `inline` instead of an `attachment` disposition
+    #[serde(default)]
+    inline: Option<String>,
+    /// `?raw=1` requests the encrypted content of client side encrypted files instead of the decrypting page
+    #[serde(default)]
+    raw: Option<String>,
+}
+
+/// checks if a query flag is set (`1` or `true`)
+fn is_enabled(value: &str) -> bool {
+    value == "1" || value == "true"
+}
+
+/// checks the download permission of a file, files without a download permission can only be downloaded
+/// if the request was authorized (`authorize_error` is `None`)
+pub fn check_download_permission(
+    download_permission: Option<&FileDataPermission>,
+    authorize_error: Option<UploadError>,
+) -> Result<(), UploadError> {
+    match (download_permission, authorize_error) {
+        // it should be like the file wouldn't exist
+        (Some(FileDataPermission::None), None) => Err(UploadError::FileNotExists),
+        // the authorization was unsuccessful and the download permission doesn't allow unlimited access
+        (Some(FileDataPermission::None) | None, Some(authorize_error)) => Err(authorize_error),
+        _ => Ok(()),
+    }
+}
+
+/// checks if the request is allowed to download the file by its headers or the permission of the file data
+/// returns the file data of the file, expired files get removed
+pub async fn authorize_download(
+    state: &State,
+    headers: &HeaderMap,
+    filename: &str,
+) -> Result<FileData, UploadError> {
+    // the filename must not escape the upload directory
+    if !is_valid_stored_filename(filename) {
+        return Err(UploadError::FileNotExists);
+    }
+    let download_path = state.upload_directory.join(filename);
     if !download_path.exists() {
         return Err(UploadError::FileNotExists);
     }
 
     // save the authorize error because downloading the file could be allowed exceptional
-    let authorize_error = authorize_by_headers(&state, &headers, AuthRequest::Download).err();
+    let authorize_error = authorize_by_headers(state, headers, AuthRequest::Download).err();
 
     // read file data and parse it
-    let file_data_path = state.data_directory.join(&filename);
-    let file_data = FileData::read_from(&file_data_path)
+    let file_data_path = state.data_directory.join(filename);
+    let mut file_data = FileData::read_from(&file_data_path, state.encryption.as_deref())
         .await?
         .unwrap_or_default();
-    if let Some(download_permission) = &file_data.download_permission {
-        // check if the authorization was unsuccessfull and the download permission doesn't allow unlimited access
-        if let Some(authorize_error) = authorize_error {
-            if !matches!(download_permission, FileDataPermission::Unlimited) {
-                return Err(authorize_error);
-            }
-        }
+    // files of a collection inherit its download permission and ttl
+    let collection = match &file_data.collection {
+        Some(id) => Collection::read(state, id)
+            .await?
+            .map(|collection| (id.clone(), collection)),
+        None => None,
+    };
+    if let Some((_, collection)) = &collection {
+        collection.apply_to(&mut file_data);
+    }
+    check_download_permission(file_data.download_permission.as_ref(), authorize_error)?;
 
-        if matches!(download_permission, FileDataPermission::None) {
-            // it should be like the file wouldn't exist
+    if let Some((id, collection)) = &collection {
+        if collection.expired() {
+            info!(collection = %id, "collection got removed because the ttl was reached");
+            remove_collection(state, id, true).await?;
+            state.metrics.record_ttl_deletion("collection");
             return Err(UploadError::FileNotExists);
         }
-    } else if let Some(authorize_error) = authorize_error {
-        // file data doesn't set a download permission but the authorization was not successfull
-        return Err(authorize_error);
+        collection.check_password(id, headers)?;
     }
 
     if file_data.expired() {
-        println!("INFO: File {filename} got removed because the ttl was reached.");
-        remove_file(download_path).await?;
-        remove_file(file_data_path).await?;
+        info!(file = %filename, "file got removed because the ttl was reached");
+        remove_stored_file(state, filename, "expired").await?;
+        audit::record(
+            state,
+            AuditEvent::file(AuditAction::Expire, filename, &file_data),
+        );
+        webhook::notify(state, WebhookEvent::Expired, filename, &file_data);
+        state.metrics.record_ttl_deletion("file");
         return Err(UploadError::FileNotExists);
     }
 
+    Ok(file_data)
+}
+
+/// the encryption the blob of the file is stored with, `None` if it isn't encrypted at rest
+pub fn blob_encryption<'a>(
+    state: &'a State,
+    file_data: &FileData,
+    filename: &str,
+) -> Result<Option<&'a Encryption>, UploadError> {
+    if !file_data.encrypted_at_rest.unwrap_or_default() {
+        return Ok(None);
+    }
+    state.encryption.as_deref().map(Some).ok_or_else(|| {
+        error!(file = %filename, "file is encrypted but encryption is disabled");
+        UploadError::InternalServerError
+    })
+}
+
+/// the size of the original file, files uploaded before the size was saved are never stored compressed
+pub fn original_size(
+    state: &State,
+    file_data: &FileData,
+    filename: &str,
+) -> Result<u64, UploadError> {
+    if let Some(size) = file_data.size {
+        return Ok(size);
+    }
+    let stored_size = state.upload_directory.join(filename).metadata()?.len();
+    Ok(match blob_encryption(state, file_data, filename)? {
+        Some(_) => plaintext_size(stored_size),
+        None => stored_size,
+    })
+}
+
+/// the entity tag of the file is its hash, it's weak because the content may get encoded differently
+fn entity_tag(file_data: &FileData) -> Option<String> {
+    file_data.hash.as_ref().map(|hash| format!("W/\"{hash}\""))
+}
+
+/// checks if the `If-None-Match` header contains the entity tag (compared weakly)
+fn matches_entity_tag(headers: &HeaderMap, entity_tag: &str) -> bool {
+    let opaque_tag = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|tag| tag.trim() == "*" || opaque_tag(tag) == opaque_tag(entity_tag))
+}
+
+#[derive(Serialize)]
+struct FileInfo<'a> {
+    #[serde(flatten)]
+    file: FileEntry<'a>,
+    file_data: &'a FileData,
+}
+
+/// the information about a file as json, the same users that may download the file may get it
+pub async fn info(
+    extract::Path(filename): extract::Path<String>,
+    extract::State(state): extract::State<State>,
+    headers: HeaderMap,
+) -> Result<Response<Body>, UploadError> {
+    let mut file_data = authorize_download(&state, &headers, &filename).await?;
+    // only the file list shows who uploaded the file
+    file_data.uploader = None;
+    let file = StoredFile {
+        size: original_size(&state, &file_data, &filename)?,
+        uploaded_at: file_data.uploaded_at.unwrap_or_default(),
+        name: filename,
+        file_data,
+    };
+    Ok(Json(FileInfo {
+        file: FileEntry::from(&file),
+        file_data: &file.file_data,
+    })
+    .into_response())
+}
+
+pub async fn download(
+    extract::Path(filename): extract::Path<String>,
+    extract::State(state): extract::State<State>,
+    extract::Query(query): extract::Query<DownloadQuery>,
+    method: Method,
+    headers: HeaderMap,
+) -> Result<Response<Body>, UploadError> {
+    let file_data = authorize_download(&state, &headers, &filename).await?;
+    let download_path = state.upload_directory.join(&filename);
+
     // user seems to be authorized to download the file by it's header or the file data permissions at this point
 
+    let content_type = file_data
+        .content_type
+        .as_deref()
+        .unwrap_or(DEFAULT_CONTENT_TYPE);
+    // the query parameter overrides the inline setting of the file data
+    let inline = match query.inline.as_deref() {
+        Some(value) => is_enabled(value),
+        None => file_data.inline.unwrap_or_default(),
+    };
+    // dangerous content types are served as attachment unless the config allows it
+    let disposition = if inline && state.config.content_type.may_be_inline(content_type) {
+        "inline"
+    } else {
+        "attachment"
+    };
+
+    // browsers get a page that decrypts client side encrypted files with the key in the url fragment
+    let encrypted = file_data.encrypted.unwrap_or_default();
+    if encrypted && !query.raw.as_deref().is_some_and(is_enabled) && accepts_html(&headers) {
+        return Ok(pages::e2e_download());
+    }
+
+    // the client already has the file
+    let entity_tag = entity_tag(&file_data);
+    if let Some(entity_tag) = &entity_tag {
+        if matches_entity_tag(&headers, entity_tag) {
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_MODIFIED)
+                .header("ETag", entity_tag)
+                .body(Body::empty())
+                .unwrap());
+        }
+    }
+
     // prepare the request
     let mut response = Response::builder()
         .status(StatusCode::OK)
-        .header("Content-Type", "application/octet-stream")
+        .header("Content-Type", content_type)
+        .header("X-Content-Type-Options", "nosniff")
         .header(
             "Content-Disposition",
-            format!("attachment; filename=\"{filename}\""),
+            content_disposition(disposition, file_data.display_name(&filename)),
+        );
+
+    let encryption = blob_encryption(&state, &file_data, &filename)?;
+    // the client needs the encryption parameters to decrypt the file
+    if let Some(parameters) = file_data
+        .encryption_parameters
+        .as_ref()
+        .filter(|_| encrypted)
+    {
+        response = response
+            .header(FILE_DATA_ENCRYPTED_HEADER_NAME, "true")
+            .header(
+                FILE_DATA_ENCRYPTION_CHUNK_SIZE_HEADER_NAME,
+                parameters.chunk_size.to_string(),
+            )
+            .header(FILE_DATA_ENCRYPTION_NONCE_HEADER_NAME, &parameters.nonce);
+    }
+
+    if let Some(entity_tag) = &entity_tag {
+        response = response.header("ETag", entity_tag);
+    }
+    if let Some(ttl) = file_data.ttl {
+        response = response.header(FILE_DATA_EXPIRES_AT_HEADER_NAME, ttl.to_string());
+    }
+
+    // the custom metadata and the tags are returned like they were uploaded
+    for (key, value) in file_data.meta.iter().flatten() {
+        response = response.header(
+            format!("{FILE_DATA_META_HEADER_PREFIX}{key}"),
+            HeaderValue::from_bytes(value.as_bytes())
+                .map_err(|_| UploadError::InternalServerError)?,
         );
+    }
+    if let Some(tags) = &file_data.tags {
+        response = response.header(
+            FILE_DATA_TAGS_HEADER_NAME,
+            HeaderValue::from_bytes(tags.join(", ").as_bytes())
+                .map_err(|_| UploadError::InternalServerError)?,
+        );
+    }
 
-    // if the metadata is valid add the filesize as Content-Length header
-    if let Ok(metadata) = download_path.metadata() {
-        response = response.header("Content-Length", metadata.size().to_string());
+    let stored_size = download_path
+        .metadata()
+        .ok()
+        .map(|metadata| metadata.size());
+    // the size of the stored content without the encryption overhead
+    let blob_size = match encryption {
+        Some(_) => stored_size.map(plaintext_size),
+        None => stored_size,
+    };
+    let compressed = file_data.compressed.unwrap_or_default();
+    // the size of the original file, compressed files know it from their file data
+    let size = if compressed {
+        file_data.size
+    } else {
+        blob_size
+    };
+    let compression = &state.config.compression;
+    if compression.enabled || compressed {
+        response = response.header("Vary", "Accept-Encoding");
     }
 
+    // ranges are only supported if the stored content isn't compressed
+    let range = match (compressed, size) {
+        (false, Some(size)) => {
+            response = response.header("Accept-Ranges", "bytes");
+            parse_range(&headers, size)?
+        }
+        _ => None,
+    };
+
     // open the file and convert it to Body by getting the ReaderStream
     let file = File::open(&download_path).await?;
-    let stream = ReaderStream::new(file);
-    let body = Body::from_stream(stream);
+    let body = if let Some(range) = &range {
+        response = response
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header("Content-Range", range.content_range(size.unwrap()))
+            .header("Content-Length", range.len().to_string());
+        let reader = storage::raw_reader(file, encryption, range.start).await?;
+        Body::from_stream(ReaderStream::new(reader.take(range.len())))
+    } else if compressed
+        && !headers.contains_key(header::RANGE)
+        && accepts(&headers, Encoding::Zstd)
+    {
+        // the client accepts zstd, so the stored file can be passed through
+        response = response.header("Content-Encoding", Encoding::Zstd.name());
+        if let Some(blob_size) = blob_size {
+            response = response.header("Content-Length", blob_size.to_string());
+        }
+        Body::from_stream(ReaderStream::new(
+            storage::raw_reader(file, encryption, 0).await?,
+        ))
+    } else {
+        // decrypt and decompress the stored file if necessary
+        let reader = storage::reader(file, compressed, encryption).await?;
+        match compression.negotiate(&headers, content_type, size) {
+            // the compressed size is unknown, so the body will be chunked
+            Some(encoding) => {
+                response = response.header("Content-Encoding", encoding.name());
+                encoding.encode(reader)
+            }
+            None => {
+                // if the size is known add it as Content-Length header
+                if let Some(size) = size {
+                    response = response.header("Content-Length", size.to_string());
+                }
+                Body::from_stream(ReaderStream::new(reader))
+            }
+        }
+    };
+
+    // the body of HEAD requests isn't sent
+    match (&range, size) {
+        _ if method == Method::HEAD => {}
+        (Some(range), _) => record_bytes(range.len()),
+        (None, Some(size)) => record_bytes(size),
+        _ => {}
+    }
+    // only successful requests that get the content count as download, the least recently downloaded files are evicted first
+    if method != Method::HEAD {
+        record_download(&state, &filename, &file_data);
+        audit::record(
+            &state,
+            AuditEvent::file(AuditAction::Download, &filename, &file_data),
+        );
+        webhook::notify(&state, WebhookEvent::Downloaded, &filename, &file_data);
+    }
     Ok(response.body(body).unwrap()) // add the body to the response and finalize it
 }
@@ -0,0 +1,599 @@
+use std::{
+    fs::read_to_string,
+    io::{self, SeekFrom},
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use aes_gcm::{
+    aead::{self, Aead, KeyInit},
+    Aes256Gcm,
+};
+use bytes::Bytes;
+use chacha20poly1305::XChaCha20Poly1305;
+use futures_util::stream;
+use rand::RngCore;
+use serde::Deserialize;
+use tokio::{
+    fs::{read_dir, remove_file, rename, File},
+    io::{copy, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt},
+};
+use tokio_util::io::StreamReader;
+use tracing::{error, info};
+
+use crate::storage::BlobReader;
+
+/// the size of the plaintext of a chunk, the last chunk of a file may be smaller
+pub const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+const ENCRYPTED_CHUNK_SIZE: usize = CHUNK_SIZE + TAG_SIZE;
+
+/// encrypted files start with this magic, the last byte is the version of the format
+const MAGIC: &[u8; 8] = b"UPLDENC\x01";
+const KEY_SIZE: usize = 32;
+const KEY_ID_SIZE: usize = 16;
+const WRAP_NONCE_SIZE: usize = 24;
+const WRAPPED_KEY_SIZE: usize = KEY_SIZE + TAG_SIZE;
+/// magic | algorithm | master key id | nonce of the wrapped data key | wrapped data key
+pub const HEADER_SIZE: usize = MAGIC.len() + 1 + KEY_ID_SIZE + WRAP_NONCE_SIZE + WRAPPED_KEY_SIZE;
+
+fn encryption_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq)]
+pub enum Algorithm {
+    #[serde(rename = "aes-256-gcm")]
+    Aes256Gcm,
+    #[serde(rename = "xchacha20-poly1305")]
+    XChaCha20Poly1305,
+}
+
+impl Algorithm {
+    fn id(&self) -> u8 {
+        match self {
+            Self::Aes256Gcm => 1,
+            Self::XChaCha20Poly1305 => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Self::Aes256Gcm),
+            2 => Some(Self::XChaCha20Poly1305),
+            _ => None,
+        }
+    }
+
+    fn nonce_size(&self) -> usize {
+        match self {
+            Self::Aes256Gcm => 12,
+            Self::XChaCha20Poly1305 => 24,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct EncryptionConfig {
+    /// Encrypt uploaded files and their file data
+    pub enabled: bool,
+    /// The file containing the master keys, one `<key id> <hex encoded 32 byte key>` per line
+    /// the last key is used for new files, the others are only used for decrypting
+    pub key_file: PathBuf,
+    /// The algorithm new files are encrypted with
+    pub algorithm: Algorithm,
+    /// Wrap the data keys of all files with the current master key at startup
+    pub rewrap_on_startup: bool,
+}
+
+impl Default for EncryptionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            key_file: PathBuf::from("encryption.keys"),
+            algorithm: Algorithm::XChaCha20Poly1305,
+            rewrap_on_startup: false,
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Cipher {
+    Aes256Gcm(Box<Aes256Gcm>),
+    XChaCha20Poly1305(XChaCha20Poly1305),
+}
+
+impl Cipher {
+    fn new(algorithm: Algorithm, key: &[u8]) -> Self {
+        match algorithm {
+            Algorithm::Aes256Gcm => {
+                Self::Aes256Gcm(Box::new(Aes256Gcm::new_from_slice(key).unwrap()))
+            }
+            Algorithm::XChaCha20Poly1305 => {
+                Self::XChaCha20Poly1305(XChaCha20Poly1305::new_from_slice(key).unwrap())
+            }
+        }
+    }
+
+    fn algorithm(&self) -> Algorithm {
+        match self {
+            Self::Aes256Gcm(_) => Algorithm::Aes256Gcm,
+            Self::XChaCha20Poly1305(_) => Algorithm::XChaCha20Poly1305,
+        }
+    }
+
+    fn encrypt(&self, nonce: &[u8], plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        let nonce = &nonce[..self.algorithm().nonce_size()];
+        match self {
+            Self::Aes256Gcm(cipher) => cipher.encrypt(
+                &aead::Nonce::<Aes256Gcm>::try_from(nonce).unwrap(),
+                plaintext,
+            ),
+            Self::XChaCha20Poly1305(cipher) => cipher.encrypt(
+                &aead::Nonce::<XChaCha20Poly1305>::try_from(nonce).unwrap(),
+                plaintext,
+            ),
+        }
+        .map_err(|_| encryption_error("Error while encrypting"))
+    }
+
+    fn decrypt(&self, nonce: &[u8], ciphertext: &[u8]) -> io::Result<Vec<u8>> {
+        let nonce = &nonce[..self.algorithm().nonce_size()];
+        match self {
+            Self::Aes256Gcm(cipher) => cipher.decrypt(
+                &aead::Nonce::<Aes256Gcm>::try_from(nonce).unwrap(),
+                ciphertext,
+            ),
+            Self::XChaCha20Poly1305(cipher) => cipher.decrypt(
+                &aead::Nonce::<XChaCha20Poly1305>::try_from(nonce).unwrap(),
+                ciphertext,
+            ),
+        }
+        .map_err(|_| encryption_error("Error while decrypting, the data seems to be corrupted"))
+    }
+
+    /// every file has its own data key, so the nonce only has to be unique inside the file
+    /// the nonce ends with the big endian chunk counter and a flag that marks the last chunk,
+    /// this prevents reordering and truncating the chunks
+    fn chunk_nonce(&self, counter: u32, last: bool) -> Vec<u8> {
+        let mut nonce = vec![0; self.algorithm().nonce_size()];
+        let length = nonce.len();
+        nonce[length - 5..length - 1].copy_from_slice(&counter.to_be_bytes());
+        nonce[length - 1] = last as u8;
+        nonce
+    }
+
+    /// encrypts the whole `plaintext` chunk by chunk
+    fn seal_chunks(&self, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        let mut ciphertext = Vec::with_capacity(plaintext.len() + TAG_SIZE);
+        let chunks = plaintext.len().div_ceil(CHUNK_SIZE).max(1);
+        for counter in 0..chunks {
+            let chunk =
+                &plaintext[counter * CHUNK_SIZE..plaintext.len().min((counter + 1) * CHUNK_SIZE)];
+            let nonce = self.chunk_nonce(counter as u32, counter == chunks - 1);
+            ciphertext.extend_from_slice(&self.encrypt(&nonce, chunk)?);
+        }
+        Ok(ciphertext)
+    }
+}
+
+/// returns the size of the plaintext of an encrypted file with the size `stored_size`
+pub fn plaintext_size(stored_size: u64) -> u64 {
+    let body = stored_size.saturating_sub(HEADER_SIZE as u64);
+    let chunks = body.div_ceil(ENCRYPTED_CHUNK_SIZE as u64).max(1);
+    body.saturating_sub(chunks * TAG_SIZE as u64)
+}
+
+/// checks if the content starts like an encrypted file
+pub fn is_encrypted(content: &[u8]) -> bool {
+    content.starts_with(MAGIC)
+}
+
+/// the master keys loaded from the key file
+pub struct Encryption {
+    algorithm: Algorithm,
+    /// (key id, key), the last key is the current one
+    keys: Vec<([u8; KEY_ID_SIZE], [u8; KEY_SIZE])>,
+}
+
+impl Encryption {
+    /// loads the master keys from the configured key file, returns `None` if encryption is disabled
+    pub fn load(config: &EncryptionConfig) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        let content = read_to_string(&config.key_file).expect("Error while reading key file");
+        let mut keys = Vec::new();
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (id, key) = line
+                .split_once(' ')
+                .expect("Error while parsing key file: Every line must look like this: `<key id> <hex encoded key>`");
+            let id = id.as_bytes();
+            if id.len() > KEY_ID_SIZE {
+                panic!("Error while parsing key file: Key ids can't be longer than {KEY_ID_SIZE} bytes");
+            }
+            let mut padded_id = [0; KEY_ID_SIZE];
+            padded_id[..id.len()].copy_from_slice(id);
+            let mut decoded_key = [0; KEY_SIZE];
+            hex::decode_to_slice(key.trim(), &mut decoded_key)
+                .expect("Error while parsing key file: Keys must be 32 hex encoded bytes, create one with `openssl rand -hex 32`");
+            keys.push((padded_id, decoded_key));
+        }
+        if keys.is_empty() {
+            panic!("Error while parsing key file: The key file doesn't contain any key");
+        }
+        Some(Self {
+            algorithm: config.algorithm,
+            keys,
+        })
+    }
+
+    fn current_key(&self) -> &([u8; KEY_ID_SIZE], [u8; KEY_SIZE]) {
+        self.keys.last().unwrap()
+    }
+
+    fn master_key(&self, id: &[u8]) -> io::Result<&[u8; KEY_SIZE]> {
+        self.keys
+            .iter()
+            .find(|(key_id, _)| key_id == id)
+            .map(|(_, key)| key)
+            .ok_or_else(|| encryption_error("The master key of the file is not in the key file"))
+    }
+
+    /// wraps the `data_key` with the current master key and returns the file header
+    fn seal_header(&self, algorithm: Algorithm, data_key: &[u8; KEY_SIZE]) -> io::Result<Vec<u8>> {
+        let (key_id, master_key) = self.current_key();
+        let mut nonce = [0; WRAP_NONCE_SIZE];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let wrapped_key = Cipher::new(algorithm, master_key).encrypt(&nonce, data_key)?;
+
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        header.extend_from_slice(MAGIC);
+        header.push(algorithm.id());
+        header.extend_from_slice(key_id);
+        header.extend_from_slice(&nonce);
+        header.extend_from_slice(&wrapped_key);
+        Ok(header)
+    }
+
+    /// unwraps the data key of the file `header`
+    fn open_header(&self, header: &[u8]) -> io::Result<(Algorithm, [u8; KEY_SIZE])> {
+        if header.len() < HEADER_SIZE || !is_encrypted(header) {
+            return Err(encryption_error("The file is not encrypted"));
+        }
+        let algorithm = Algorithm::from_id(header[MAGIC.len()])
+            .ok_or_else(|| encryption_error("Unknown encryption algorithm"))?;
+        let key_id = &header[MAGIC.len() + 1..MAGIC.len() + 1 + KEY_ID_SIZE];
+        let nonce = &header[MAGIC.len() + 1 + KEY_ID_SIZE..HEADER_SIZE - WRAPPED_KEY_SIZE];
+        let wrapped_key = &header[HEADER_SIZE - WRAPPED_KEY_SIZE..HEADER_SIZE];
+        let data_key =
+            Cipher::new(algorithm, self.master_key(key_id)?).decrypt(nonce, wrapped_key)?;
+        Ok((algorithm, data_key.try_into().unwrap()))
+    }
+
+    /// creates a new data key and returns the header of the new file and the cipher of the data key
+    fn new_file(&self) -> io::Result<(Vec<u8>, Cipher)> {
+        let mut data_key = [0; KEY_SIZE];
+        rand::thread_rng().fill_bytes(&mut data_key);
+        let header = self.seal_header(self.algorithm, &data_key)?;
+        Ok((header, Cipher::new(self.algorithm, &data_key)))
+    }
+
+    /// wraps `inner` so everything written to the writer gets encrypted with a new data key
+    pub fn writer<W: AsyncWrite + Unpin>(&self, inner: W) -> io::Result<EncryptWriter<W>> {
+        let (header, cipher) = self.new_file()?;
+        Ok(EncryptWriter {
+            inner,
+            cipher,
+            counter: 0,
+            plaintext: Vec::with_capacity(CHUNK_SIZE),
+            pending: header,
+            written: 0,
+            finished: false,
+        })
+    }
+
+    /// returns a reader that decrypts the encrypted `file` starting at the plaintext byte `offset`
+    pub async fn reader(&self, mut file: File, offset: u64) -> io::Result<BlobReader> {
+        let stored_size = file.metadata().await?.len();
+        let mut header = [0; HEADER_SIZE];
+        file.read_exact(&mut header).await?;
+        let (algorithm, data_key) = self.open_header(&header)?;
+        let cipher = Cipher::new(algorithm, &data_key);
+
+        let body = stored_size - HEADER_SIZE as u64;
+        let chunks = body.div_ceil(ENCRYPTED_CHUNK_SIZE as u64).max(1);
+        // only the chunks containing the requested bytes have to be decrypted
+        let first_chunk = offset / CHUNK_SIZE as u64;
+        let skip = (offset % CHUNK_SIZE as u64) as usize;
+        file.seek(SeekFrom::Start(
+            HEADER_SIZE as u64 + first_chunk * ENCRYPTED_CHUNK_SIZE as u64,
+        ))
+        .await?;
+
+        let chunks = stream::try_unfold(
+            (file, first_chunk, skip),
+            move |(mut file, counter, skip)| {
+                let cipher = cipher.clone();
+                async move {
+                    if counter >= chunks {
+                        return Ok(None);
+                    }
+                    let length = (body - counter * ENCRYPTED_CHUNK_SIZE as u64)
+                        .min(ENCRYPTED_CHUNK_SIZE as u64) as usize;
+                    let mut ciphertext = vec![0; length];
+                    file.read_exact(&mut ciphertext).await?;
+                    let nonce = cipher.chunk_nonce(counter as u32, counter == chunks - 1);
+                    let plaintext = cipher.decrypt(&nonce, &ciphertext)?;
+                    let skip = skip.min(plaintext.len());
+                    let chunk = Bytes::from(plaintext).slice(skip..);
+                    Ok::<_, io::Error>(Some((chunk, (file, counter + 1, 0))))
+                }
+            },
+        );
+        Ok(Box::new(StreamReader::new(Box::pin(chunks))))
+    }
+
+    /// encrypts a small file (e.g. file data) in memory
+    pub fn encrypt_bytes(&self, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        let (mut content, cipher) = self.new_file()?;
+        content.extend_from_slice(&cipher.seal_chunks(plaintext)?);
+        Ok(content)
+    }
+
+    /// decrypts a small file (e.g. file data) in memory
+    pub fn decrypt_bytes(&self, content: &[u8]) -> io::Result<Vec<u8>> {
+        let (algorithm, data_key) = self.open_header(content)?;
+        let cipher = Cipher::new(algorithm, &data_key);
+        let body = &content[HEADER_SIZE..];
+        // even empty files have one chunk, without it nothing would be authenticated
+        if body.is_empty() {
+            return Err(encryption_error("The file is truncated"));
+        }
+        let chunks = body.len().div_ceil(ENCRYPTED_CHUNK_SIZE);
+        let mut plaintext = Vec::with_capacity(body.len());
+        for (counter, chunk) in body.chunks(ENCRYPTED_CHUNK_SIZE).enumerate() {
+            let nonce = cipher.chunk_nonce(counter as u32, counter == chunks - 1);
+            plaintext.extend_from_slice(&cipher.decrypt(&nonce, chunk)?);
+        }
+        Ok(plaintext)
+    }
+
+    /// wraps the data key of the encrypted file at `path` with the current master key
+    /// only the header changes, returns `false` if the file already uses the current master key
+    pub async fn rewrap(&self, path: &Path) -> io::Result<bool> {
+        let mut file = File::open(path).await?;
+        let mut header = [0; HEADER_SIZE];
+        file.read_exact(&mut header).await?;
+        let (key_id, _) = self.current_key();
+        if &header[MAGIC.len() + 1..MAGIC.len() + 1 + KEY_ID_SIZE] == key_id {
+            return Ok(false);
+        }
+        let (algorithm, data_key) = self.open_header(&header)?;
+        let header = self.seal_header(algorithm, &data_key)?;
+
+        // the file is written again under a temporary name, so a crash can't leave a file with a broken header
+        let mut temporary_path = path.as_os_str().to_owned();
+        temporary_path.push(".rewrap");
+        let temporary_path = PathBuf::from(temporary_path);
+        let result = async {
+            let mut temporary = File::create(&temporary_path).await?;
+            temporary.write_all(&header).await?;
+            copy(&mut file, &mut temporary).await?;
+            temporary.sync_all().await?;
+            rename(&temporary_path, path).await
+        }
+        .await;
+        if result.is_err() {
+            let _ = remove_file(&temporary_path).await;
+        }
+        result.map(|_| true)
+    }
+
+    /// wraps the data keys of all encrypted files in `directory` with the current master key
+    pub async fn rewrap_directory(&self, directory: &Path) -> io::Result<()> {
+        let mut content = read_dir(directory).await?;
+        while let Some(file) = content.next_entry().await? {
+            let mut magic = [0; MAGIC.len()];
+            let is_encrypted_file = match File::open(file.path()).await {
+                Ok(mut opened) => {
+                    opened.read_exact(&mut magic).await.is_ok() && is_encrypted(&magic)
+                }
+                Err(_) => false,
+            };
+            if !is_encrypted_file {
+                continue;
+            }
+            match self.rewrap(&file.path()).await {
+                Ok(true) => info!(
+                    path = %file.path().display(),
+                    "wrapped the data key with the current master key"
+                ),
+                Ok(false) => {}
+                Err(e) => error!(
+                    path = %file.path().display(),
+                    error = ?e,
+                    "error while wrapping the data key with the current master key"
+                ),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// encrypts everything that is written to it chunk by chunk and writes it to `inner`
+/// the writer must be shut down to write the last chunk
+pub struct EncryptWriter<W> {
+    inner: W,
+    cipher: Cipher,
+    counter: u32,
+    /// the plaintext of the current chunk
+    plaintext: Vec<u8>,
+    /// encrypted bytes that have to be written to `inner`
+    pending: Vec<u8>,
+    /// the amount of bytes of `pending` that are already written
+    written: usize,
+    finished: bool,
+}
+
+impl<W: AsyncWrite + Unpin> EncryptWriter<W> {
+    fn poll_write_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.pending.len() {
+            let n =
+                ready!(Pin::new(&mut self.inner).poll_write(cx, &self.pending[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        self.pending.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    fn seal_chunk(&mut self, last: bool) -> io::Result<()> {
+        let nonce = self.cipher.chunk_nonce(self.counter, last);
+        let ciphertext = self.cipher.encrypt(&nonce, &self.plaintext)?;
+        self.pending.extend_from_slice(&ciphertext);
+        self.plaintext.clear();
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| encryption_error("The file is too big to be encrypted"))?;
+        Ok(())
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for EncryptWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_pending(cx))?;
+        if this.plaintext.len() == CHUNK_SIZE {
+            // more data follows, so the full chunk isn't the last one
+            this.seal_chunk(false)?;
+            ready!(this.poll_write_pending(cx))?;
+        }
+        let n = buf.len().min(CHUNK_SIZE - this.plaintext.len());
+        this.plaintext.extend_from_slice(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_pending(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_pending(cx))?;
+        if !this.finished {
+            this.seal_chunk(true)?;
+            this.finished = true;
+            ready!(this.poll_write_pending(cx))?;
+        }
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+
+    use tokio::fs::{read, remove_file, write};
+
+    use super::*;
+    use crate::random::generate_random_characters;
+
+    fn key(id: &[u8], byte: u8) -> ([u8; KEY_ID_SIZE], [u8; KEY_SIZE]) {
+        let mut padded_id = [0; KEY_ID_SIZE];
+        padded_id[..id.len()].copy_from_slice(id);
+        (padded_id, [byte; KEY_SIZE])
+    }
+
+    fn encryption(algorithm: Algorithm, ids: &[&[u8]]) -> Encryption {
+        Encryption {
+            algorithm,
+            keys: ids
+                .iter()
+                .enumerate()
+                .map(|(index, id)| key(id, index as u8 + 1))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn encrypted_bytes_can_be_decrypted() {
+        for algorithm in [Algorithm::Aes256Gcm, Algorithm::XChaCha20Poly1305] {
+            let encryption = encryption(algorithm, &[b"current"]);
+            for size in [0, 1, CHUNK_SIZE, CHUNK_SIZE + 1, 3 * CHUNK_SIZE] {
+                let plaintext = (0..size).map(|i| i as u8).collect::<Vec<u8>>();
+                let encrypted = encryption.encrypt_bytes(&plaintext).unwrap();
+                assert!(is_encrypted(&encrypted));
+                assert_eq!(plaintext_size(encrypted.len() as u64), size as u64);
+                assert_eq!(encryption.decrypt_bytes(&encrypted).unwrap(), plaintext);
+            }
+        }
+    }
+
+    #[test]
+    fn changed_or_truncated_bytes_are_rejected() {
+        let encryption = encryption(Algorithm::XChaCha20Poly1305, &[b"current"]);
+        let plaintext = vec![7; 2 * CHUNK_SIZE];
+        let encrypted = encryption.encrypt_bytes(&plaintext).unwrap();
+
+        let mut changed = encrypted.clone();
+        *changed.last_mut().unwrap() ^= 1;
+        assert!(encryption.decrypt_bytes(&changed).is_err());
+        // the last chunk is missing
+        let truncated = &encrypted[..HEADER_SIZE + ENCRYPTED_CHUNK_SIZE];
+        assert!(encryption.decrypt_bytes(truncated).is_err());
+        // only the header
+        assert!(encryption.decrypt_bytes(&encrypted[..HEADER_SIZE]).is_err());
+    }
+
+    #[test]
+    fn files_of_unknown_master_keys_are_rejected() {
+        let encrypted = encryption(Algorithm::Aes256Gcm, &[b"old"])
+            .encrypt_bytes(b"content")
+            .unwrap();
+        let encryption = encryption(Algorithm::Aes256Gcm, &[b"other"]);
+        assert!(encryption.decrypt_bytes(&encrypted).is_err());
+    }
+
+    #[tokio::test]
+    async fn rewrapped_files_use_the_current_master_key() {
+        let path = temp_dir().join(format!("rewrap-{}", generate_random_characters(16)));
+        let plaintext = vec![3; CHUNK_SIZE + 10];
+        let old = encryption(Algorithm::XChaCha20Poly1305, &[b"old"]);
+        write(&path, old.encrypt_bytes(&plaintext).unwrap())
+            .await
+            .unwrap();
+
+        // the old key stays in the key file to decrypt the files that weren't rewrapped yet
+        let rotated = Encryption {
+            algorithm: Algorithm::XChaCha20Poly1305,
+            keys: vec![key(b"old", 1), key(b"new", 9)],
+        };
+        assert!(rotated.rewrap(&path).await.unwrap());
+        assert!(!rotated.rewrap(&path).await.unwrap());
+
+        let new = Encryption {
+            algorithm: Algorithm::XChaCha20Poly1305,
+            keys: vec![key(b"new", 9)],
+        };
+        let content = read(&path).await.unwrap();
+        remove_file(&path).await.unwrap();
+        assert_eq!(new.decrypt_bytes(&content).unwrap(), plaintext);
+        assert!(old.decrypt_bytes(&content).is_err());
+    }
+}
@@ -5,15 +5,7 @@ use axum::{
     http::{Response, StatusCode},
     response::IntoResponse,
 };
-
-macro_rules! error_while_request {
-    ($error:expr) => {
-        eprintln!(
-            "ERROR: There was an error while preparing the response: {:?}",
-            $error
-        );
-    };
-}
+use tracing::error;
 
 #[derive(Debug)]
 pub enum UploadError {
@@ -32,18 +24,43 @@ pub enum UploadError {
     InvalidAuth(String),
     /// A file data header value is invalid, the string contains the name of the argument
     InvalidFileDataArgument(String),
+    /// The json body is invalid, includes the reason
+    InvalidJson(String),
+    /// A query parameter is invalid, the string contains the name of the parameter
+    InvalidQueryArgument(String),
+    /// A file with the same content and filename already exists, includes the name of the stored file
+    FileAlreadyExists(String),
+    /// The uploaded archive couldn't be extracted, includes the reason
+    InvalidArchive(String),
+    /// The user tried to access a collection that does not exist
+    CollectionNotExists,
+    /// The collection is protected by a password and the given password is missing or wrong
+    InvalidCollectionPassword,
+    /// The user is authorized but not allowed to change the resource (e.g. a collection of another user)
+    Forbidden,
+    /// The requested range is outside of the file, includes the size of the file
+    RangeNotSatisfiable(u64),
+    /// The user has to set a ttl for the file
+    TtlRequired,
+    /// The disk is full
+    InsufficientStorage,
+    /// The ttl is longer than the user is allowed to keep files, includes the maximal ttl in seconds
+    TtlTooLong(u64),
 }
 
 impl From<io::Error> for UploadError {
     fn from(value: io::Error) -> Self {
-        error_while_request!(value);
+        error!(error = ?value, "error while preparing the response");
+        if value.kind() == io::ErrorKind::StorageFull {
+            return UploadError::InsufficientStorage;
+        }
         UploadError::InternalServerError
     }
 }
 
 impl From<serde_json::Error> for UploadError {
     fn from(value: serde_json::Error) -> Self {
-        error_while_request!(value);
+        error!(error = ?value, "error while preparing the response");
         UploadError::InternalServerError
     }
 }
@@ -65,7 +82,22 @@ impl IntoResponse for UploadError {
                 .header("WWW-Authenticate", scheme)
                 .body(Body::from("Unauthorized"))
                 .unwrap(),
-            Self::InvalidFileDataArgument(header) => (StatusCode::BAD_REQUEST, format!("Invalid file data header '{header}'")).into_response()
+            Self::InvalidFileDataArgument(header) => (StatusCode::BAD_REQUEST, format!("Invalid file data header '{header}'")).into_response(),
+            Self::InvalidJson(reason) => (StatusCode::BAD_REQUEST, format!("Invalid json body: {reason}")).into_response(),
+            Self::InvalidQueryArgument(parameter) => (StatusCode::BAD_REQUEST, format!("Invalid query parameter '{parameter}'")).into_response(),
+            Self::FileAlreadyExists(filename) => (StatusCode::CONFLICT, format!("The file {filename} already exists")).into_response(),
+            Self::InvalidArchive(reason) => (StatusCode::BAD_REQUEST, format!("Invalid archive: {reason}")).into_response(),
+            Self::CollectionNotExists => (StatusCode::NOT_FOUND, "Collection does not exist").into_response(),
+            Self::InvalidCollectionPassword => (StatusCode::FORBIDDEN, "The collection requires a valid password").into_response(),
+            Self::Forbidden => (StatusCode::FORBIDDEN, "Forbidden").into_response(),
+            Self::RangeNotSatisfiable(size) => Response::builder()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header("Content-Range", format!("bytes */{size}"))
+                .body(Body::from("Range Not Satisfiable"))
+                .unwrap(),
+            Self::InsufficientStorage => (StatusCode::INSUFFICIENT_STORAGE, "The server has no space left to store the file").into_response(),
+            Self::TtlRequired => (StatusCode::BAD_REQUEST, "The file needs a ttl. Set the File-Data-Delete-After or File-Data-Expires-At header.").into_response(),
+            Self::TtlTooLong(max_ttl) => (StatusCode::BAD_REQUEST, format!("The ttl is too long. Files can be kept for at most {max_ttl} seconds.")).into_response(),
         }
     }
 }
@@ -0,0 +1,168 @@
+use std::{path::Path, time::Duration};
+
+use serde::Deserialize;
+use tokio::{io, time::sleep};
+use tracing::{error, info, info_span, warn, Instrument};
+
+use crate::{
+    audit::{self, AuditAction, AuditEvent},
+    error::UploadError,
+    file_data::{current_unix_timestamp, deserialize_duration, FileData},
+    list::stored_files,
+    state::State,
+    trash::delete_stored_file,
+    webhook::{self, WebhookEvent},
+};
+
+/// the last download of a file is recorded at most once in this amount of seconds
+const LAST_DOWNLOAD_RESOLUTION: u64 = 60 * 60;
+
+#[derive(Clone, Copy, Deserialize, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionOrder {
+    /// the files that weren't downloaded for the longest time are evicted first
+    #[default]
+    LeastRecentlyDownloaded,
+    /// the oldest files are evicted first
+    Oldest,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct EvictionConfig {
+    /// Delete files when the disk of the upload directory is nearly full
+    pub enabled: bool,
+    /// Start evicting files when this percentage of the disk is used
+    pub high_watermark: u8,
+    /// Stop evicting files when the usage dropped to this percentage
+    pub low_watermark: u8,
+    pub order: EvictionOrder,
+    /// The time between two checks of the disk usage, it's also checked after every upload
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub check_interval: u64,
+}
+
+impl Default for EvictionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            high_watermark: 90,
+            low_watermark: 80,
+            order: EvictionOrder::default(),
+            check_interval: 60,
+        }
+    }
+}
+
+/// the percentage of the disk containing `path` that is used
+fn disk_usage(path: &Path) -> io::Result<f64> {
+    let stats = fs4::statvfs(path)?;
+    let used = stats.total_space().saturating_sub(stats.available_space());
+    Ok(used as f64 * 100.0 / stats.total_space().max(1) as f64)
+}
+
+/// records the download of the file, the file data is written in the background
+pub fn record_download(state: &State, filename: &str, file_data: &FileData) {
+    let now = current_unix_timestamp();
+    if file_data
+        .last_downloaded_at
+        .is_some_and(|last_downloaded_at| now < last_downloaded_at + LAST_DOWNLOAD_RESOLUTION)
+    {
+        return;
+    }
+    let state = state.clone();
+    let filename = filename.to_string();
+    tokio::spawn(
+        async move {
+            let _lock = state.file_data_lock.lock().await;
+            // the file could have been removed in the meantime, its file data must not be recreated
+            if !state.upload_directory.join(&filename).exists() {
+                return;
+            }
+            // the file data of the download could be changed by the collection of the file
+            let path = state.data_directory.join(&filename);
+            let result = match FileData::read_from(&path, state.encryption.as_deref()).await {
+                Ok(file_data) => {
+                    let mut file_data = file_data.unwrap_or_default();
+                    file_data.last_downloaded_at = Some(now);
+                    file_data.write_to(&path, state.encryption.as_deref()).await
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                error!(file = %filename, error = ?e, "error while recording the download");
+            }
+        }
+        .in_current_span(),
+    );
+}
+
+/// deletes files that aren't pinned until the usage of the disk is below the low watermark,
+/// nothing is deleted if the usage is below the high watermark
+async fn evict(state: &State) -> Result<(), UploadError> {
+    let config = &state.config.eviction;
+    let usage = disk_usage(&state.upload_directory)?;
+    if usage < config.high_watermark as f64 {
+        return Ok(());
+    }
+    warn!(
+        usage = format_args!("{usage:.1}"),
+        low_watermark = config.low_watermark,
+        "the disk is nearly full, evicting files until the usage dropped to the low watermark"
+    );
+
+    let mut files = stored_files(state).await?;
+    files.retain(|file| !file.file_data.pinned.unwrap_or_default());
+    files.sort_by_key(|file| match config.order {
+        EvictionOrder::LeastRecentlyDownloaded => file
+            .file_data
+            .last_downloaded_at
+            .unwrap_or(file.uploaded_at),
+        EvictionOrder::Oldest => file.uploaded_at,
+    });
+    for file in files {
+        if disk_usage(&state.upload_directory)? <= config.low_watermark as f64 {
+            return Ok(());
+        }
+        // evicted files are deleted permanently, the trash would keep the disk full
+        // the other files can still be evicted
+        if let Err(e) = delete_stored_file(state, &file.name).await {
+            error!(file = %file.name, error = ?e, "error while evicting file");
+            continue;
+        }
+        audit::record(
+            state,
+            AuditEvent::file(AuditAction::Delete, &file.name, &file.file_data)
+                .with_detail("evicted"),
+        );
+        webhook::notify(state, WebhookEvent::Deleted, &file.name, &file.file_data);
+        info!(file = %file.name, "file got evicted because the disk is nearly full");
+    }
+    warn!(
+        usage = format_args!("{:.1}", disk_usage(&state.upload_directory)?),
+        "the disk is still nearly full but there are no files left that can be evicted"
+    );
+    Ok(())
+}
+
+pub fn start_evictor(state: State) {
+    if !state.config.eviction.enabled {
+        return;
+    }
+    tokio::spawn(
+        async move {
+            info!("started evictor");
+            let check_interval = Duration::from_secs(state.config.eviction.check_interval.max(1));
+            loop {
+                if let Err(e) = evict(&state).await {
+                    error!(error = ?e, "error while evicting files");
+                }
+                tokio::select! {
+                    _ = sleep(check_interval) => {}
+                    _ = state.eviction_check.notified() => {}
+                }
+            }
+        }
+        .instrument(info_span!("evictor")),
+    );
+}
@@ -0,0 +1,309 @@
+use std::borrow::Cow;
+
+/// the header segments of a jpeg file are buffered until the first scan starts,
+/// files with a bigger header are stored unchanged
+const MAX_HEADER_SIZE: usize = 4 * 1024 * 1024;
+
+/// the APP1 segment contains the exif and xmp metadata (e.g. the gps location and the camera)
+const APP1: u8 = 0xE1;
+/// the APP13 segment contains the iptc metadata
+const APP13: u8 = 0xED;
+/// start of scan, the compressed image data follows this segment
+const SOS: u8 = 0xDA;
+
+/// the beginning of an APP1 segment that contains exif metadata
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+/// the exif tag of the orientation of the image
+const ORIENTATION_TAG: u16 = 0x0112;
+/// the exif type of 16 bit unsigned integers
+const SHORT_TYPE: u16 = 3;
+
+enum Header {
+    /// more bytes are needed to parse the header
+    Incomplete,
+    /// the content is not a jpeg file or it's malformed, it's stored unchanged
+    Invalid,
+    /// the header was parsed completely, it's the amount of bytes it was long
+    Complete(usize),
+}
+
+/// parses the segments at the beginning of a jpeg file and removes the segments containing metadata,
+/// it continues at the segment it stopped at when more bytes are available
+#[derive(Default)]
+struct HeaderParser {
+    /// the position of the next segment
+    position: usize,
+    /// the parsed segments without the metadata segments
+    stripped: Vec<u8>,
+}
+
+impl HeaderParser {
+    /// parses the segments of `content` that weren't parsed yet, `content` must start with the
+    /// bytes of the earlier calls
+    fn parse(&mut self, content: &[u8]) -> Header {
+        if self.position == 0 {
+            if content.len() < 2 {
+                return Header::Incomplete;
+            }
+            if content[..2] != [0xFF, 0xD8] {
+                return Header::Invalid;
+            }
+            self.stripped.extend_from_slice(&content[..2]);
+            self.position = 2;
+        }
+        loop {
+            let position = self.position;
+            let Some(&[prefix, marker]) = content.get(position..position + 2) else {
+                return Header::Incomplete;
+            };
+            if prefix != 0xFF {
+                return Header::Invalid;
+            }
+            match marker {
+                // markers can be padded with fill bytes
+                0xFF => {
+                    self.position += 1;
+                    continue;
+                }
+                // markers without a length
+                0x01 | 0xD0..=0xD7 => {
+                    self.stripped
+                        .extend_from_slice(&content[position..position + 2]);
+                    self.position += 2;
+                    continue;
+                }
+                _ => {}
+            }
+            let Some(&[high, low]) = content.get(position + 2..position + 4) else {
+                return Header::Incomplete;
+            };
+            // the length includes the two length bytes
+            let length = u16::from_be_bytes([high, low]) as usize;
+            if length < 2 {
+                return Header::Invalid;
+            }
+            let end = position + 2 + length;
+            if content.len() < end {
+                return Header::Incomplete;
+            }
+            match marker {
+                // the orientation is kept, otherwise rotated photos would be shown sideways
+                APP1 => {
+                    if let Some(orientation) = orientation(&content[position + 4..end]) {
+                        self.stripped
+                            .extend_from_slice(&orientation_segment(orientation));
+                    }
+                }
+                APP13 => {}
+                _ => self.stripped.extend_from_slice(&content[position..end]),
+            }
+            self.position = end;
+            if marker == SOS {
+                return Header::Complete(end);
+            }
+        }
+    }
+}
+
+/// returns the orientation of the exif metadata in the `payload` of an APP1 segment,
+/// `None` if the segment contains other metadata (e.g. xmp) or the image isn't rotated or mirrored
+fn orientation(payload: &[u8]) -> Option<u16> {
+    let tiff = payload.strip_prefix(EXIF_HEADER)?;
+    let big_endian = match tiff.get(..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let u16_at = |offset: usize| {
+        let bytes = [*tiff.get(offset)?, *tiff.get(offset + 1)?];
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let u32_at = |offset: usize| {
+        let bytes = tiff.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    };
+    if u16_at(2)? != 42 {
+        return None;
+    }
+    // the orientation is an entry of the first image file directory
+    let directory = u32_at(4)? as usize;
+    let entries = u16_at(directory)? as usize;
+    (0..entries)
+        .map(|index| directory + 2 + index * 12)
+        .find(|&entry| u16_at(entry) == Some(ORIENTATION_TAG))
+        .filter(|&entry| u16_at(entry + 2) == Some(SHORT_TYPE))
+        .and_then(|entry| u16_at(entry + 8))
+        // 1 is the default orientation, values above 8 are invalid
+        .filter(|orientation| (2..=8).contains(orientation))
+}
+
+/// an APP1 segment with exif metadata that only contains the `orientation`
+fn orientation_segment(orientation: u16) -> Vec<u8> {
+    let mut segment = vec![0xFF, APP1, 0x00, 0x22];
+    segment.extend_from_slice(EXIF_HEADER);
+    // big endian tiff header, the first directory follows it
+    segment.extend_from_slice(&[b'M', b'M', 0x00, 0x2A, 0x00, 0x00, 0x00, 0x08]);
+    // one entry with a single short value, the value is padded to 4 bytes
+    segment.extend_from_slice(&1u16.to_be_bytes());
+    segment.extend_from_slice(&ORIENTATION_TAG.to_be_bytes());
+    segment.extend_from_slice(&SHORT_TYPE.to_be_bytes());
+    segment.extend_from_slice(&1u32.to_be_bytes());
+    segment.extend_from_slice(&orientation.to_be_bytes());
+    segment.extend_from_slice(&[0x00, 0x00]);
+    // there is no next directory
+    segment.extend_from_slice(&0u32.to_be_bytes());
+    segment
+}
+
+/// removes the exif, xmp and iptc metadata of a jpeg file while it gets uploaded, only the orientation is kept
+/// other files are passed through unchanged
+#[derive(Default)]
+pub struct MetadataStripper {
+    /// the beginning of the file until the header was parsed completely
+    buffer: Vec<u8>,
+    parser: HeaderParser,
+    /// the header was parsed (or the file is no jpeg file), everything else is passed through
+    done: bool,
+}
+
+impl MetadataStripper {
+    /// returns the part of the file that can be written, it may be empty while the header is incomplete
+    pub fn push<'a>(&mut self, chunk: &'a [u8]) -> Cow<'a, [u8]> {
+        if self.done {
+            return Cow::Borrowed(chunk);
+        }
+        self.buffer.extend_from_slice(chunk);
+        match self.parser.parse(&self.buffer) {
+            Header::Incomplete if self.buffer.len() <= MAX_HEADER_SIZE => Cow::Owned(Vec::new()),
+            Header::Incomplete | Header::Invalid => {
+                self.done = true;
+                Cow::Owned(std::mem::take(&mut self.buffer))
+            }
+            Header::Complete(length) => {
+                self.done = true;
+                let mut stripped = std::mem::take(&mut self.parser.stripped);
+                stripped.extend_from_slice(&self.buffer[length..]);
+                self.buffer = Vec::new();
+                Cow::Owned(stripped)
+            }
+        }
+    }
+
+    /// returns the bytes that are still buffered at the end of the file
+    pub fn finish(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a jpeg file with an APP0 and an exif segment before the scan
+    fn jpeg() -> Vec<u8> {
+        let mut jpeg = vec![0xFF, 0xD8];
+        jpeg.extend_from_slice(&[0xFF, 0xE0, 0x00, 0x04, b'J', b'F']);
+        jpeg.extend_from_slice(&[0xFF, APP1, 0x00, 0x06, b'E', b'x', b'i', b'f']);
+        jpeg.extend_from_slice(&[0xFF, SOS, 0x00, 0x02, 1, 2, 3, 0xFF, 0xD9]);
+        jpeg
+    }
+
+    fn strip(content: &[u8], chunk_size: usize) -> Vec<u8> {
+        let mut stripper = MetadataStripper::default();
+        let mut stripped = Vec::new();
+        for chunk in content.chunks(chunk_size) {
+            stripped.extend_from_slice(&stripper.push(chunk));
+        }
+        stripped.extend(stripper.finish());
+        stripped
+    }
+
+    #[test]
+    fn removes_metadata_segments_in_any_chunks() {
+        let content = jpeg();
+        let mut expected = content[..8].to_vec();
+        expected.extend_from_slice(&content[16..]);
+        for chunk_size in [1, 3, 7, content.len()] {
+            assert_eq!(strip(&content, chunk_size), expected);
+        }
+    }
+
+    /// an exif segment in little endian with the camera model and the `orientation`
+    fn exif_segment(orientation: u16) -> Vec<u8> {
+        let mut payload = EXIF_HEADER.to_vec();
+        payload.extend_from_slice(&[b'I', b'I', 0x2A, 0x00, 0x08, 0x00, 0x00, 0x00]);
+        payload.extend_from_slice(&2u16.to_le_bytes());
+        // the model, its value is stored after the directory
+        payload.extend_from_slice(&[0x10, 0x01, 0x02, 0x00, 0x06, 0x00, 0x00, 0x00]);
+        payload.extend_from_slice(&38u32.to_le_bytes());
+        payload.extend_from_slice(&ORIENTATION_TAG.to_le_bytes());
+        payload.extend_from_slice(&SHORT_TYPE.to_le_bytes());
+        payload.extend_from_slice(&1u32.to_le_bytes());
+        payload.extend_from_slice(&orientation.to_le_bytes());
+        payload.extend_from_slice(&[0x00, 0x00]);
+        payload.extend_from_slice(&0u32.to_le_bytes());
+        payload.extend_from_slice(b"Phone\0");
+        let mut segment = vec![0xFF, APP1];
+        segment.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+        segment.extend_from_slice(&payload);
+        segment
+    }
+
+    #[test]
+    fn keeps_only_the_orientation() {
+        let mut content = vec![0xFF, 0xD8];
+        content.extend_from_slice(&exif_segment(6));
+        content.extend_from_slice(&[0xFF, SOS, 0x00, 0x02, 1, 2, 3, 0xFF, 0xD9]);
+        let mut expected = vec![0xFF, 0xD8];
+        expected.extend_from_slice(&orientation_segment(6));
+        expected.extend_from_slice(&[0xFF, SOS, 0x00, 0x02, 1, 2, 3, 0xFF, 0xD9]);
+        for chunk_size in [1, 5, content.len()] {
+            assert_eq!(strip(&content, chunk_size), expected);
+        }
+        assert!(!strip(&content, 5)
+            .windows(5)
+            .any(|window| window == b"Phone"));
+    }
+
+    #[test]
+    fn reads_the_orientation() {
+        assert_eq!(orientation(&exif_segment(8)[4..]), Some(8));
+        // the orientation segment is big endian
+        assert_eq!(orientation(&orientation_segment(3)[4..]), Some(3));
+        assert_eq!(
+            u16::from_be_bytes([orientation_segment(3)[2], orientation_segment(3)[3]]) as usize,
+            orientation_segment(3).len() - 2
+        );
+        // the default and invalid orientations aren't kept
+        assert_eq!(orientation(&exif_segment(1)[4..]), None);
+        assert_eq!(orientation(&exif_segment(9)[4..]), None);
+        // xmp and truncated segments
+        assert_eq!(
+            orientation(b"http://ns.adobe.com/xap/1.0/\0<x:xmpmeta>"),
+            None
+        );
+        assert_eq!(orientation(&exif_segment(6)[4..20]), None);
+    }
+
+    #[test]
+    fn keeps_other_files_unchanged() {
+        let content = b"not a jpeg file";
+        assert_eq!(strip(content, 4), content);
+    }
+
+    #[test]
+    fn keeps_malformed_jpeg_files_unchanged() {
+        let mut content = jpeg();
+        // a segment with an invalid length
+        content[5] = 0x01;
+        assert_eq!(strip(&content, 2), content);
+    }
+}
@@ -1,28 +1,195 @@
 use std::{
+    collections::{BTreeMap, HashSet},
+    io,
     path::Path,
     time::{SystemTime, UNIX_EPOCH},
 };
 
 use axum::http::HeaderMap;
-use serde::{Deserialize, Serialize};
+use base64::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
 use tokio::{
-    fs::{read_to_string, File},
+    fs::{read, File},
     io::AsyncWriteExt,
 };
 
-use crate::error::UploadError;
+use crate::{
+    auth::TtlPolicy,
+    encryption::{is_encrypted, Encryption},
+    error::UploadError,
+};
 
 const FILE_DATA_PERMISSION_HEADER_NAME: &str = "File-Data-Download-Permission";
 const FILE_DATA_DELETE_AFTER_HEADER_NAME: &str = "File-Data-Delete-After";
+const FILE_DATA_INLINE_HEADER_NAME: &str = "File-Data-Inline";
+const FILE_DATA_COMPRESS_HEADER_NAME: &str = "File-Data-Compress";
+const FILE_DATA_DISPLAY_NAME_HEADER_NAME: &str = "File-Data-Display-Name";
+/// the prefix of the custom metadata headers (header names are lowercase)
+pub const FILE_DATA_META_HEADER_PREFIX: &str = "file-data-meta-";
+pub const FILE_DATA_TAGS_HEADER_NAME: &str = "File-Data-Tags";
+const FILE_DATA_PINNED_HEADER_NAME: &str = "File-Data-Pinned";
+/// the unix timestamp or RFC 3339 date the file gets deleted at, an alternative to `File-Data-Delete-After`
+pub const FILE_DATA_EXPIRES_AT_HEADER_NAME: &str = "File-Data-Expires-At";
+const FILE_DATA_COLLECTION_HEADER_NAME: &str = "File-Data-Collection";
+pub const FILE_DATA_EXTRACT_HEADER_NAME: &str = "File-Data-Extract";
+pub const FILE_DATA_ENCRYPTED_HEADER_NAME: &str = "File-Data-Encrypted";
+pub const FILE_DATA_ENCRYPTION_CHUNK_SIZE_HEADER_NAME: &str = "File-Data-Encryption-Chunk-Size";
+pub const FILE_DATA_ENCRYPTION_NONCE_HEADER_NAME: &str = "File-Data-Encryption-Nonce";
+
+/// the headers that can only be set while uploading the file
+const IMMUTABLE_HEADER_NAMES: [&str; 6] = [
+    FILE_DATA_COMPRESS_HEADER_NAME,
+    FILE_DATA_COLLECTION_HEADER_NAME,
+    FILE_DATA_EXTRACT_HEADER_NAME,
+    FILE_DATA_ENCRYPTED_HEADER_NAME,
+    FILE_DATA_ENCRYPTION_CHUNK_SIZE_HEADER_NAME,
+    FILE_DATA_ENCRYPTION_NONCE_HEADER_NAME,
+];
+
+/// the limits of the custom metadata and the tags of a file
+const MAX_META_ENTRIES: usize = 32;
+const MAX_META_KEY_LENGTH: usize = 64;
+const MAX_META_VALUE_LENGTH: usize = 1024;
+const MAX_TAGS: usize = 32;
+const MAX_TAG_LENGTH: usize = 64;
 
-fn current_unix_timestamp() -> u64 {
+/// the chunk size of client side encrypted files if the client doesn't set one
+const DEFAULT_ENCRYPTION_CHUNK_SIZE: u32 = 64 * 1024;
+const MAX_ENCRYPTION_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+/// the length of the nonce prefix of client side encrypted files in bytes
+const ENCRYPTION_NONCE_LENGTH: usize = 8;
+
+pub fn current_unix_timestamp() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
         .as_secs()
 }
 
-#[derive(Serialize, Deserialize)]
+/// parses seconds (`3600`) or a human readable duration (`7d`, `1h 30m`)
+pub fn parse_duration(value: &str) -> Option<u64> {
+    value.parse::<u64>().ok().or_else(|| {
+        humantime::parse_duration(value)
+            .ok()
+            .map(|duration| duration.as_secs())
+    })
+}
+
+/// parses a unix timestamp (`1767225600`) or a RFC 3339 date in UTC (`2026-01-01T00:00:00Z`),
+/// the timestamp must be in the future
+fn parse_expires_at(value: &str) -> Option<u64> {
+    value
+        .parse::<u64>()
+        .ok()
+        .or_else(|| {
+            humantime::parse_rfc3339_weak(value)
+                .ok()
+                .and_then(|date| date.duration_since(UNIX_EPOCH).ok())
+                .map(|duration| duration.as_secs())
+        })
+        .filter(|timestamp| *timestamp > current_unix_timestamp())
+}
+
+/// the ttl of a file that gets deleted after `delete_after` seconds from now on
+fn ttl_after(delete_after: u64) -> u64 {
+    current_unix_timestamp().saturating_add(delete_after)
+}
+
+/// parses the header `name` with `parse` if it exists
+fn parse_header<T>(
+    headers: &HeaderMap,
+    name: &str,
+    parse: impl FnOnce(&str) -> Option<T>,
+) -> Result<Option<T>, UploadError> {
+    headers
+        .get(name)
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(parse)
+                .ok_or_else(|| UploadError::InvalidFileDataArgument(name.to_string()))
+        })
+        .transpose()
+}
+
+/// parses the header `name` as `true` or `false` if it exists
+pub fn parse_bool_header(headers: &HeaderMap, name: &str) -> Result<Option<bool>, UploadError> {
+    parse_header(headers, name, |value| value.parse::<bool>().ok())
+}
+
+/// the name of the header of a custom metadata entry
+fn meta_header_name(key: &str) -> String {
+    format!("File-Data-Meta-{key}")
+}
+
+/// keys are lowercase because header names are case insensitive, values must not contain control characters
+fn is_valid_meta_entry(key: &str, value: &str) -> bool {
+    !key.is_empty()
+        && key.len() <= MAX_META_KEY_LENGTH
+        && key
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+        && value.len() <= MAX_META_VALUE_LENGTH
+        && !value.chars().any(char::is_control)
+}
+
+fn is_valid_tag(tag: &str) -> bool {
+    !tag.is_empty()
+        && tag.chars().count() <= MAX_TAG_LENGTH
+        && tag
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+}
+
+/// parses the `File-Data-Meta-*` headers, an empty value is `None`
+fn parse_meta_headers(
+    headers: &HeaderMap,
+) -> Result<BTreeMap<String, Option<String>>, UploadError> {
+    let mut meta = BTreeMap::new();
+    for (name, value) in headers {
+        let Some(key) = name.as_str().strip_prefix(FILE_DATA_META_HEADER_PREFIX) else {
+            continue;
+        };
+        let value = String::from_utf8(value.as_bytes().to_vec())
+            .ok()
+            .filter(|value| is_valid_meta_entry(key, value))
+            .ok_or_else(|| UploadError::InvalidFileDataArgument(meta_header_name(key)))?;
+        meta.insert(
+            key.to_string(),
+            Some(value).filter(|value| !value.is_empty()),
+        );
+    }
+    Ok(meta)
+}
+
+/// trims the tags and removes empty tags and duplicates, returns `None` if a tag is invalid
+fn normalize_tags<'a>(tags: impl Iterator<Item = &'a str>) -> Option<Vec<String>> {
+    let mut seen = HashSet::new();
+    let tags = tags
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty() && seen.insert(*tag))
+        .map(|tag| tag.to_string())
+        .collect::<Vec<String>>();
+    (tags.len() <= MAX_TAGS && tags.iter().all(|tag| is_valid_tag(tag))).then_some(tags)
+}
+
+/// parses the comma separated tags of the `File-Data-Tags` header
+fn parse_tags_header(headers: &HeaderMap) -> Result<Option<Vec<String>>, UploadError> {
+    headers
+        .get(FILE_DATA_TAGS_HEADER_NAME)
+        .map(|value| {
+            std::str::from_utf8(value.as_bytes())
+                .ok()
+                .and_then(|value| normalize_tags(value.split(',')))
+                .ok_or_else(|| {
+                    UploadError::InvalidFileDataArgument(FILE_DATA_TAGS_HEADER_NAME.to_string())
+                })
+        })
+        .transpose()
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "lowercase")]
 pub enum FileDataPermission {
     /// It's not possible to download the file
@@ -31,13 +198,156 @@ pub enum FileDataPermission {
     Unlimited,
 }
 
-#[derive(Serialize, Deserialize, Default)]
+/// the parameters a client used to encrypt a file end-to-end (AES-256-GCM)
+#[derive(Serialize, Deserialize, Clone)]
+pub struct EncryptionParameters {
+    /// the size of the plaintext chunks in bytes
+    pub chunk_size: u32,
+    /// the random nonce prefix of the chunk nonces (base64url)
+    pub nonce: String,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
 pub struct FileData {
     #[serde(default)]
     pub download_permission: Option<FileDataPermission>,
     /// delete the file at this timestamp
     #[serde(default)]
     pub ttl: Option<u64>,
+    /// the content type that was detected while uploading the file
+    #[serde(default)]
+    pub content_type: Option<String>,
+    /// serve the file with an `inline` instead of an `attachment` disposition
+    #[serde(default)]
+    pub inline: Option<bool>,
+    /// the stored file is compressed with zstd
+    #[serde(default)]
+    pub compressed: Option<bool>,
+    /// the size of the original (uncompressed) file in bytes
+    #[serde(default)]
+    pub size: Option<u64>,
+    /// the stored file is encrypted by the server
+    #[serde(default)]
+    pub encrypted_at_rest: Option<bool>,
+    /// the file was encrypted by the client (end-to-end), the server doesn't know the key
+    #[serde(default)]
+    pub encrypted: Option<bool>,
+    #[serde(default)]
+    pub encryption_parameters: Option<EncryptionParameters>,
+    /// the blake3 hash (hex) of the original file
+    #[serde(default)]
+    pub hash: Option<String>,
+    /// the unix timestamp the upload was completed at
+    #[serde(default)]
+    pub uploaded_at: Option<u64>,
+    /// the identity (basic username or bearer `sub`) of the user that uploaded the file
+    #[serde(default)]
+    pub uploader: Option<String>,
+    /// the id of the collection the file belongs to
+    #[serde(default)]
+    pub collection: Option<String>,
+    /// the filename shown to users instead of the uploaded filename
+    #[serde(default)]
+    pub display_name: Option<String>,
+    /// custom metadata of the `File-Data-Meta-*` headers
+    #[serde(default)]
+    pub meta: Option<BTreeMap<String, String>>,
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+    /// pinned files are never evicted when the disk is nearly full
+    #[serde(default)]
+    pub pinned: Option<bool>,
+    /// the unix timestamp the file was downloaded at the last time
+    #[serde(default)]
+    pub last_downloaded_at: Option<u64>,
+}
+
+/// deserializes a field that can be missing (`None`), `null` (`Some(None)`) or set (`Some(Some(_))`)
+fn double_option<'de, D: Deserializer<'de>, T: Deserialize<'de>>(
+    deserializer: D,
+) -> Result<Option<Option<T>>, D::Error> {
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// a json value that can be a number or a string, e.g. `3600` or `"1h"`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumberOrString {
+    Number(u64),
+    String(String),
+}
+
+/// deserializes seconds (`3600`) or a human readable duration (`"7d"`) of a config file
+pub fn deserialize_duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    match NumberOrString::deserialize(deserializer)? {
+        NumberOrString::Number(seconds) => Ok(seconds),
+        NumberOrString::String(duration) => parse_duration(&duration)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid duration {duration}"))),
+    }
+}
+
+/// like `deserialize_duration` for optional values
+pub fn deserialize_optional_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u64>, D::Error> {
+    #[derive(Deserialize)]
+    struct Duration(#[serde(deserialize_with = "deserialize_duration")] u64);
+    Option::<Duration>::deserialize(deserializer)
+        .map(|duration| duration.map(|duration| duration.0))
+}
+
+/// like `double_option`, strings are parsed with `parse` and numbers are checked by it
+fn double_option_parsed<'de, D: Deserializer<'de>>(
+    deserializer: D,
+    parse: fn(&str) -> Option<u64>,
+) -> Result<Option<Option<u64>>, D::Error> {
+    Option::<NumberOrString>::deserialize(deserializer)?
+        .map(|value| {
+            let value = match value {
+                NumberOrString::Number(number) => number.to_string(),
+                NumberOrString::String(string) => string,
+            };
+            parse(&value).ok_or_else(|| serde::de::Error::custom(format!("invalid value {value}")))
+        })
+        .transpose()
+        .map(Some)
+}
+
+fn double_option_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Option<u64>>, D::Error> {
+    double_option_parsed(deserializer, parse_duration)
+}
+
+fn double_option_expires_at<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Option<u64>>, D::Error> {
+    double_option_parsed(deserializer, parse_expires_at)
+}
+
+/// the changes of the file data after the upload, missing fields are kept and `null` resets a field
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct FileDataUpdate {
+    #[serde(default, deserialize_with = "double_option")]
+    pub download_permission: Option<Option<FileDataPermission>>,
+    /// delete the file after this amount of seconds (or human readable duration) from now on
+    #[serde(default, deserialize_with = "double_option_duration")]
+    pub delete_after: Option<Option<u64>>,
+    /// delete the file at this unix timestamp or RFC 3339 date
+    #[serde(default, deserialize_with = "double_option_expires_at")]
+    pub expires_at: Option<Option<u64>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub display_name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub inline: Option<Option<bool>>,
+    /// the entries are merged into the existing metadata, `null` values remove an entry
+    #[serde(default, deserialize_with = "double_option")]
+    pub meta: Option<Option<BTreeMap<String, Option<String>>>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub tags: Option<Option<Vec<String>>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub pinned: Option<Option<bool>>,
 }
 
 impl TryFrom<&str> for FileDataPermission {
@@ -70,23 +380,251 @@ impl TryFrom<HeaderMap> for FileData {
             );
         }
 
-        if let Some(value) = headers.get(FILE_DATA_DELETE_AFTER_HEADER_NAME) {
-            let string = value.to_str().map_err(|_| {
-                UploadError::InvalidFileDataArgument(FILE_DATA_DELETE_AFTER_HEADER_NAME.to_string())
-            })?;
-            let delete_after = string.parse::<u64>().map_err(|_| {
-                UploadError::InvalidFileDataArgument(FILE_DATA_DELETE_AFTER_HEADER_NAME.to_string())
+        let delete_after =
+            parse_header(&headers, FILE_DATA_DELETE_AFTER_HEADER_NAME, parse_duration)?;
+        let expires_at =
+            parse_header(&headers, FILE_DATA_EXPIRES_AT_HEADER_NAME, parse_expires_at)?;
+        file_data.ttl = match (delete_after, expires_at) {
+            (Some(_), Some(_)) => {
+                return Err(UploadError::InvalidFileDataArgument(
+                    FILE_DATA_EXPIRES_AT_HEADER_NAME.to_string(),
+                ))
+            }
+            (delete_after, expires_at) => expires_at.or(delete_after.map(ttl_after)),
+        };
+
+        file_data.inline = parse_bool_header(&headers, FILE_DATA_INLINE_HEADER_NAME)?;
+        file_data.update_meta(parse_meta_headers(&headers)?)?;
+        file_data.tags = parse_tags_header(&headers)?.filter(|tags| !tags.is_empty());
+        file_data.pinned = parse_bool_header(&headers, FILE_DATA_PINNED_HEADER_NAME)?;
+        file_data.collection = parse_header(&headers, FILE_DATA_COLLECTION_HEADER_NAME, |value| {
+            Some(value.to_string())
+        })?;
+        file_data.compressed = parse_bool_header(&headers, FILE_DATA_COMPRESS_HEADER_NAME)?;
+
+        file_data.encrypted = parse_bool_header(&headers, FILE_DATA_ENCRYPTED_HEADER_NAME)?;
+        if file_data.encrypted.unwrap_or_default() {
+            let chunk_size = parse_header(
+                &headers,
+                FILE_DATA_ENCRYPTION_CHUNK_SIZE_HEADER_NAME,
+                |value| {
+                    value
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|size| *size > 0 && *size <= MAX_ENCRYPTION_CHUNK_SIZE)
+                },
+            )?
+            .unwrap_or(DEFAULT_ENCRYPTION_CHUNK_SIZE);
+            let nonce = parse_header(&headers, FILE_DATA_ENCRYPTION_NONCE_HEADER_NAME, |value| {
+                BASE64_URL_SAFE_NO_PAD
+                    .decode(value)
+                    .ok()
+                    .filter(|nonce| nonce.len() == ENCRYPTION_NONCE_LENGTH)
+                    .map(|_| value.to_string())
+            })?
+            .ok_or_else(|| {
+                UploadError::InvalidFileDataArgument(
+                    FILE_DATA_ENCRYPTION_NONCE_HEADER_NAME.to_string(),
+                )
             })?;
-            file_data.ttl = Some(current_unix_timestamp() + delete_after);
+            file_data.encryption_parameters = Some(EncryptionParameters { chunk_size, nonce });
         }
         Ok(file_data)
     }
 }
 
+impl TryFrom<&HeaderMap> for FileDataUpdate {
+    type Error = UploadError;
+    /// parses the `File-Data-*` headers, `default` resets a value and `never` removes the ttl
+    fn try_from(headers: &HeaderMap) -> Result<Self, Self::Error> {
+        if let Some(name) = IMMUTABLE_HEADER_NAMES
+            .into_iter()
+            .find(|name| headers.contains_key(*name))
+        {
+            return Err(UploadError::InvalidFileDataArgument(name.to_string()));
+        }
+        Ok(Self {
+            download_permission: parse_header(
+                headers,
+                FILE_DATA_PERMISSION_HEADER_NAME,
+                |value| match value {
+                    "default" => Some(None),
+                    value => FileDataPermission::try_from(value).ok().map(Some),
+                },
+            )?,
+            delete_after: parse_header(headers, FILE_DATA_DELETE_AFTER_HEADER_NAME, |value| {
+                match value {
+                    "never" => Some(None),
+                    value => parse_duration(value).map(Some),
+                }
+            })?,
+            expires_at: parse_header(
+                headers,
+                FILE_DATA_EXPIRES_AT_HEADER_NAME,
+                |value| match value {
+                    "never" => Some(None),
+                    value => parse_expires_at(value).map(Some),
+                },
+            )?,
+            // an empty display name resets it, the name may contain any utf-8 characters
+            display_name: headers
+                .get(FILE_DATA_DISPLAY_NAME_HEADER_NAME)
+                .map(|value| {
+                    String::from_utf8(value.as_bytes().to_vec())
+                        .map(|name| Some(name).filter(|name| !name.is_empty()))
+                        .map_err(|_| {
+                            UploadError::InvalidFileDataArgument(
+                                FILE_DATA_DISPLAY_NAME_HEADER_NAME.to_string(),
+                            )
+                        })
+                })
+                .transpose()?,
+            inline: parse_header(headers, FILE_DATA_INLINE_HEADER_NAME, |value| match value {
+                "default" => Some(None),
+                value => value.parse::<bool>().ok().map(Some),
+            })?,
+            // empty values remove an entry
+            meta: Some(parse_meta_headers(headers)?)
+                .filter(|meta| !meta.is_empty())
+                .map(Some),
+            // an empty header removes all tags
+            tags: parse_tags_header(headers)?
+                .map(|tags| Some(tags).filter(|tags| !tags.is_empty())),
+            pinned: parse_header(headers, FILE_DATA_PINNED_HEADER_NAME, |value| match value {
+                "default" => Some(None),
+                value => value.parse::<bool>().ok().map(Some),
+            })?,
+        })
+    }
+}
+
 impl FileData {
     /// this functions returns true if there is no file data (all values are the default ones)
     pub fn is_empty(&self) -> bool {
         self.download_permission.is_none()
+            && self.ttl.is_none()
+            && self.content_type.is_none()
+            && self.inline.is_none()
+            && self.compressed.is_none()
+            && self.size.is_none()
+            && self.encrypted_at_rest.is_none()
+            && self.encrypted.is_none()
+            && self.encryption_parameters.is_none()
+            && self.hash.is_none()
+            && self.uploaded_at.is_none()
+            && self.uploader.is_none()
+            && self.collection.is_none()
+            && self.display_name.is_none()
+            && self.meta.is_none()
+            && self.tags.is_none()
+            && self.pinned.is_none()
+            && self.last_downloaded_at.is_none()
+    }
+
+    /// merges the entries into the custom metadata, `None` values remove an entry
+    fn update_meta(
+        &mut self,
+        entries: BTreeMap<String, Option<String>>,
+    ) -> Result<(), UploadError> {
+        let mut meta = self.meta.take().unwrap_or_default();
+        for (key, value) in entries {
+            match value {
+                Some(value) if !is_valid_meta_entry(&key, &value) => {
+                    return Err(UploadError::InvalidFileDataArgument(meta_header_name(&key)))
+                }
+                Some(value) => meta.insert(key, value),
+                None => meta.remove(&key),
+            };
+        }
+        if meta.len() > MAX_META_ENTRIES {
+            return Err(UploadError::InvalidFileDataArgument(meta_header_name("*")));
+        }
+        self.meta = Some(meta).filter(|meta| !meta.is_empty());
+        Ok(())
+    }
+
+    /// applies the changes of the update, the ttl is calculated from now on,
+    /// a changed ttl must follow the policy, the lifetime of the file is counted from its upload
+    pub fn update(
+        &mut self,
+        update: FileDataUpdate,
+        policy: &TtlPolicy,
+    ) -> Result<(), UploadError> {
+        if let Some(download_permission) = update.download_permission {
+            self.download_permission = download_permission;
+        }
+        match (update.delete_after, update.expires_at) {
+            (Some(_), Some(_)) => {
+                return Err(UploadError::InvalidFileDataArgument(
+                    FILE_DATA_EXPIRES_AT_HEADER_NAME.to_string(),
+                ))
+            }
+            (Some(delete_after), None) => self.ttl = delete_after.map(ttl_after),
+            (None, Some(expires_at)) => self.ttl = expires_at,
+            (None, None) => {}
+        }
+        // files without a ttl that were uploaded before the policy can still be changed otherwise
+        if update.delete_after.is_some() || update.expires_at.is_some() {
+            let uploaded_at = self.uploaded_at.unwrap_or_else(current_unix_timestamp);
+            self.check_ttl_policy(policy, uploaded_at)?;
+        }
+        if let Some(display_name) = update.display_name {
+            self.display_name = display_name;
+        }
+        if let Some(inline) = update.inline {
+            self.inline = inline;
+        }
+        match update.meta {
+            Some(Some(entries)) => self.update_meta(entries)?,
+            Some(None) => self.meta = None,
+            None => {}
+        }
+        if let Some(pinned) = update.pinned {
+            self.pinned = pinned;
+        }
+        if let Some(tags) = update.tags {
+            // the tags of the json body are checked like the ones of the header
+            self.tags = match tags {
+                Some(tags) => Some(normalize_tags(tags.iter().map(String::as_str)).ok_or_else(
+                    || UploadError::InvalidFileDataArgument(FILE_DATA_TAGS_HEADER_NAME.to_string()),
+                )?)
+                .filter(|tags| !tags.is_empty()),
+                None => None,
+            };
+        }
+        Ok(())
+    }
+
+    /// sets the default ttl of the policy if the uploaded file doesn't have one and checks the ttl
+    pub fn apply_ttl_policy(&mut self, policy: &TtlPolicy) -> Result<(), UploadError> {
+        if self.ttl.is_none() && !policy.require_ttl.unwrap_or_default() {
+            self.ttl = policy.default_ttl.map(ttl_after);
+        }
+        self.check_ttl_policy(policy, current_unix_timestamp())
+    }
+
+    /// checks if the ttl follows the policy, the lifetime of the file is counted from `start`
+    pub fn check_ttl_policy(&self, policy: &TtlPolicy, start: u64) -> Result<(), UploadError> {
+        let Some(ttl) = self.ttl else {
+            if policy.require_ttl.unwrap_or_default() {
+                return Err(UploadError::TtlRequired);
+            }
+            return match policy.max_ttl {
+                Some(max_ttl) => Err(UploadError::TtlTooLong(max_ttl)),
+                None => Ok(()),
+            };
+        };
+        match policy.max_ttl {
+            Some(max_ttl) if ttl.saturating_sub(start) > max_ttl => {
+                Err(UploadError::TtlTooLong(max_ttl))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// the filename shown to users, the display name or `filename` if the file doesn't have one
+    pub fn display_name<'a>(&'a self, filename: &'a str) -> &'a str {
+        self.display_name.as_deref().unwrap_or(filename)
     }
 
     /// checks if the file should be dead
@@ -101,21 +639,189 @@ impl FileData {
         false
     }
 
-    pub async fn write_to(&self, path: &Path) -> Result<(), UploadError> {
-        let serialized = serde_json::to_string(self)?;
+    /// writes the file data as json, the json gets encrypted if `encryption` is set
+    pub async fn write_to(
+        &self,
+        path: &Path,
+        encryption: Option<&Encryption>,
+    ) -> Result<(), UploadError> {
+        write_json(self, path, encryption).await
+    }
 
-        let mut file = File::create(path).await?;
-        file.write_all(serialized.as_bytes()).await?;
+    /// reads the file data, encrypted file data can only be read if `encryption` is set
+    pub async fn read_from(
+        path: &Path,
+        encryption: Option<&Encryption>,
+    ) -> Result<Option<Self>, UploadError> {
+        read_json(path, encryption).await
+    }
+}
 
-        Ok(())
+/// writes the value as json, the json gets encrypted if `encryption` is set
+pub async fn write_json<T: Serialize>(
+    value: &T,
+    path: &Path,
+    encryption: Option<&Encryption>,
+) -> Result<(), UploadError> {
+    let mut serialized = serde_json::to_vec(value)?;
+    if let Some(encryption) = encryption {
+        serialized = encryption.encrypt_bytes(&serialized)?;
+    }
+
+    let mut file = File::create(path).await?;
+    file.write_all(&serialized).await?;
+    file.flush().await?;
+
+    Ok(())
+}
+
+/// reads a json file written by `write_json`, encrypted json can only be read if `encryption` is set
+pub async fn read_json<T: DeserializeOwned>(
+    path: &Path,
+    encryption: Option<&Encryption>,
+) -> Result<Option<T>, UploadError> {
+    if !path.exists() {
+        return Ok(None);
     }
 
-    pub async fn read_from(path: &Path) -> Result<Option<Self>, UploadError> {
-        if !path.exists() {
-            return Ok(None);
+    let mut file_content = read(path).await?;
+    if is_encrypted(&file_content) {
+        let encryption = encryption.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The file is encrypted but encryption is disabled",
+            )
+        })?;
+        file_content = encryption.decrypt_bytes(&file_content)?;
+    }
+    Ok(Some(serde_json::from_slice(&file_content)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(default_ttl: Option<u64>, max_ttl: Option<u64>, require_ttl: bool) -> TtlPolicy {
+        TtlPolicy {
+            default_ttl,
+            max_ttl,
+            require_ttl: Some(require_ttl),
         }
+    }
+
+    fn with_ttl(ttl: Option<u64>) -> FileData {
+        FileData {
+            ttl,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn ttl_within_max_ttl_is_accepted() {
+        let policy = policy(None, Some(100), false);
+        assert!(with_ttl(Some(1100)).check_ttl_policy(&policy, 1000).is_ok());
+        assert!(with_ttl(Some(500)).check_ttl_policy(&policy, 1000).is_ok());
+    }
+
+    #[test]
+    fn ttl_over_max_ttl_is_rejected() {
+        let policy = policy(None, Some(100), false);
+        assert!(matches!(
+            with_ttl(Some(1101)).check_ttl_policy(&policy, 1000),
+            Err(UploadError::TtlTooLong(100))
+        ));
+    }
+
+    #[test]
+    fn missing_ttl_is_rejected_with_a_max_ttl() {
+        assert!(matches!(
+            with_ttl(None).check_ttl_policy(&policy(None, Some(100), false), 1000),
+            Err(UploadError::TtlTooLong(100))
+        ));
+        assert!(with_ttl(None)
+            .check_ttl_policy(&policy(None, None, false), 1000)
+            .is_ok());
+    }
+
+    #[test]
+    fn missing_ttl_is_rejected_when_required() {
+        assert!(matches!(
+            with_ttl(None).check_ttl_policy(&policy(None, Some(100), true), 1000),
+            Err(UploadError::TtlRequired)
+        ));
+    }
+
+    #[test]
+    fn default_ttl_is_applied() {
+        let mut file_data = with_ttl(None);
+        file_data
+            .apply_ttl_policy(&policy(Some(60), Some(100), false))
+            .unwrap();
+        let ttl = file_data
+            .ttl
+            .unwrap()
+            .saturating_sub(current_unix_timestamp());
+        assert!((59..=60).contains(&ttl));
+    }
+
+    #[test]
+    fn default_ttl_is_not_applied_when_required() {
+        let mut file_data = with_ttl(None);
+        assert!(matches!(
+            file_data.apply_ttl_policy(&policy(Some(60), None, true)),
+            Err(UploadError::TtlRequired)
+        ));
+        assert_eq!(file_data.ttl, None);
+    }
+
+    #[test]
+    fn default_ttl_is_checked_against_max_ttl() {
+        let mut file_data = with_ttl(None);
+        assert!(matches!(
+            file_data.apply_ttl_policy(&policy(Some(200), Some(100), false)),
+            Err(UploadError::TtlTooLong(100))
+        ));
+    }
+
+    #[test]
+    fn update_without_ttl_change_ignores_the_policy() {
+        let policy = policy(None, Some(100), true);
+        let mut file_data = with_ttl(None);
+        file_data
+            .update(
+                FileDataUpdate {
+                    display_name: Some(Some("name.txt".to_string())),
+                    tags: Some(Some(vec!["tag".to_string()])),
+                    ..Default::default()
+                },
+                &policy,
+            )
+            .unwrap();
+        assert_eq!(file_data.display_name.as_deref(), Some("name.txt"));
+        assert_eq!(file_data.ttl, None);
+    }
 
-        let file_content = read_to_string(path).await?;
-        Ok(Some(serde_json::from_str(&file_content)?))
+    #[test]
+    fn update_of_the_ttl_follows_the_policy() {
+        let policy = policy(None, Some(100), false);
+        let uploaded_at = current_unix_timestamp();
+        let mut file_data = FileData {
+            uploaded_at: Some(uploaded_at),
+            ..Default::default()
+        };
+        let update = |delete_after| FileDataUpdate {
+            delete_after: Some(delete_after),
+            ..Default::default()
+        };
+        assert!(matches!(
+            file_data.update(update(None), &policy),
+            Err(UploadError::TtlTooLong(100))
+        ));
+        assert!(matches!(
+            file_data.update(update(Some(1000)), &policy),
+            Err(UploadError::TtlTooLong(100))
+        ));
+        file_data.update(update(Some(10)), &policy).unwrap();
+        assert!(file_data.ttl.is_some_and(|ttl| ttl >= uploaded_at + 10));
     }
 }
@@ -0,0 +1,353 @@
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::Deserialize;
+
+use crate::error::UploadError;
+
+/// characters that are allowed unencoded in a RFC 5987 `ext-value` (`attr-char`)
+const ATTR_CHAR: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+/// characters that are allowed unencoded in a single url path segment (RFC 3986 `unreserved`)
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// device names that are reserved on windows (independent of the extension)
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// the character invalid characters are replaced with in `FilenameMode::Sanitize`
+const REPLACEMENT_CHARACTER: char = '_';
+
+#[derive(Clone, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilenameMode {
+    /// Filenames that violate the policy are rejected with an invalid filename error
+    Reject,
+    /// Filenames that violate the policy are rewritten until they match the policy
+    Sanitize,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct FilenamePolicy {
+    pub mode: FilenameMode,
+    /// The maximal length of a filename in bytes
+    pub max_length: usize,
+    /// Allow filenames that start with a dot (hidden files)
+    pub allow_leading_dot: bool,
+    /// Allow non-ascii characters in filenames
+    pub allow_unicode: bool,
+    /// Allow the windows device names (e.g. `CON` or `NUL.txt`)
+    pub allow_reserved_names: bool,
+    /// Characters that are not allowed in addition to `/`, `\` and control characters
+    pub forbidden_characters: String,
+}
+
+impl Default for FilenamePolicy {
+    fn default() -> Self {
+        Self {
+            mode: FilenameMode::Reject,
+            max_length: 200,
+            allow_leading_dot: false,
+            allow_unicode: true,
+            allow_reserved_names: false,
+            forbidden_characters: "<>:\"|?*".to_string(),
+        }
+    }
+}
+
+impl FilenamePolicy {
+    fn is_forbidden_character(&self, c: char) -> bool {
+        c == '/'
+            || c == '\\'
+            || c.is_control()
+            || (!self.allow_unicode && !c.is_ascii())
+            || self.forbidden_characters.contains(c)
+    }
+
+    /// checks the filename against the policy and returns the filename that should be used
+    /// returns `Err(UploadError::InvalidFilename)` if the filename violates the policy in `FilenameMode::Reject`
+    /// or if there is nothing left of the filename after sanitizing it
+    pub fn apply(&self, filename: &str) -> Result<String, UploadError> {
+        let reject = self.mode == FilenameMode::Reject;
+
+        let mut filename = if filename.chars().any(|c| self.is_forbidden_character(c)) {
+            if reject {
+                return Err(UploadError::InvalidFilename);
+            }
+            filename
+                .chars()
+                .map(|c| {
+                    if self.is_forbidden_character(c) {
+                        REPLACEMENT_CHARACTER
+                    } else {
+                        c
+                    }
+                })
+                .collect()
+        } else {
+            filename.to_string()
+        };
+
+        // windows strips trailing dots and spaces, so `file.txt.` would become `file.txt`
+        let trimmed = filename.trim_end_matches(['.', ' ']);
+        if trimmed.len() != filename.len() {
+            if reject {
+                return Err(UploadError::InvalidFilename);
+            }
+            filename = trimmed.to_string();
+        }
+
+        if !self.allow_leading_dot && filename.starts_with('.') {
+            if reject {
+                return Err(UploadError::InvalidFilename);
+            }
+            filename = filename.trim_start_matches('.').to_string();
+        }
+
+        if !self.allow_reserved_names && is_reserved_name(&filename) {
+            if reject {
+                return Err(UploadError::InvalidFilename);
+            }
+            filename.insert(0, REPLACEMENT_CHARACTER);
+        }
+
+        if filename.len() > self.max_length {
+            if reject {
+                return Err(UploadError::InvalidFilename);
+            }
+            filename = truncate(&filename, self.max_length);
+            // the truncated filename could end with a dot or be a reserved name
+            filename = filename.trim_end_matches(['.', ' ']).to_string();
+            if !self.allow_reserved_names && is_reserved_name(&filename) {
+                filename.insert(0, REPLACEMENT_CHARACTER);
+                filename = truncate(&filename, self.max_length)
+                    .trim_end_matches(['.', ' '])
+                    .to_string();
+            }
+        }
+
+        if !is_valid_stored_filename(&filename) {
+            return Err(UploadError::InvalidFilename);
+        }
+        Ok(filename)
+    }
+}
+
+/// checks if the part before the first dot is a windows device name
+fn is_reserved_name(filename: &str) -> bool {
+    let stem = filename.split('.').next().unwrap_or_default().trim_end();
+    RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+}
+
+/// truncates the filename to `max_length` bytes but keeps the extension if possible
+fn truncate(filename: &str, max_length: usize) -> String {
+    let (stem, extension) = match filename.rfind('.') {
+        // only keep extensions that leave some space for the stem
+        Some(index) if index > 0 && filename.len() - index < max_length / 2 => {
+            filename.split_at(index)
+        }
+        _ => (filename, ""),
+    };
+    let mut end = max_length - extension.len();
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{extension}", &stem[..end])
+}
+
+/// checks if the filename is a single path component that can safely be joined to a directory
+/// this is used for filenames that are requested by users (e.g. while downloading)
+pub fn is_valid_stored_filename(filename: &str) -> bool {
+    !filename.is_empty()
+        && filename != "."
+        && filename != ".."
+        && !filename
+            .chars()
+            .any(|c| c == '/' || c == '\\' || c.is_control())
+}
+
+/// returns the filename the file was uploaded with (stored files are named `{hash}_{filename}`)
+pub fn original_filename(stored_filename: &str) -> &str {
+    stored_filename
+        .split_once('_')
+        .map(|(_, filename)| filename)
+        .unwrap_or(stored_filename)
+}
+
+/// percent encodes the filename so it can be used as a path segment of an url
+pub fn url_encode(filename: &str) -> String {
+    utf8_percent_encode(filename, PATH_SEGMENT).to_string()
+}
+
+/// builds a `Content-Disposition` header value (RFC 6266) with the `disposition` type (`attachment` or `inline`)
+/// the `filename` parameter contains an ascii only fallback, the `filename*` parameter contains the utf-8 encoded filename (RFC 5987)
+pub fn content_disposition(disposition: &str, filename: &str) -> String {
+    let fallback = filename
+        .chars()
+        .map(|c| {
+            if c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\' {
+                c
+            } else {
+                REPLACEMENT_CHARACTER
+            }
+        })
+        .collect::<String>();
+    format!(
+        "{disposition}; filename=\"{fallback}\"; filename*=UTF-8''{}",
+        utf8_percent_encode(filename, ATTR_CHAR)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(mode: FilenameMode) -> FilenamePolicy {
+        FilenamePolicy {
+            mode,
+            ..Default::default()
+        }
+    }
+
+    fn is_rejected(policy: &FilenamePolicy, filename: &str) -> bool {
+        matches!(policy.apply(filename), Err(UploadError::InvalidFilename))
+    }
+
+    #[test]
+    fn reject_mode_accepts_valid_filenames() {
+        let policy = policy(FilenameMode::Reject);
+        for filename in [
+            "report.pdf",
+            "photo 2024.jpg",
+            "naïve.txt",
+            "a.b.c",
+            "CONSOLE.txt",
+        ] {
+            assert_eq!(policy.apply(filename).unwrap(), filename);
+        }
+    }
+
+    #[test]
+    fn reject_mode_rejects_invalid_filenames() {
+        let policy = policy(FilenameMode::Reject);
+        let too_long = "a".repeat(201);
+        for filename in [
+            "",
+            ".",
+            "..",
+            "../etc/passwd",
+            "a\\b",
+            "a\nb",
+            "a<b",
+            ".hidden",
+            "file.txt.",
+            "file.txt ",
+            "CON",
+            "nul.txt",
+            "Com1.tar.gz",
+            &too_long,
+        ] {
+            assert!(is_rejected(&policy, filename), "{filename:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn sanitize_mode_rewrites_invalid_filenames() {
+        let policy = policy(FilenameMode::Sanitize);
+        for (filename, sanitized) in [
+            ("a/b<c>.txt", "a_b_c_.txt"),
+            (".hidden", "hidden"),
+            ("...hidden", "hidden"),
+            ("file.txt. . ", "file.txt"),
+            ("NUL.txt", "_NUL.txt"),
+            ("../..", "_"),
+            ("tab\there", "tab_here"),
+        ] {
+            assert_eq!(policy.apply(filename).unwrap(), sanitized);
+        }
+        // nothing is left of these filenames
+        for filename in ["", ".", "..", " . "] {
+            assert!(is_rejected(&policy, filename), "{filename:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn sanitize_mode_truncates_long_filenames_but_keeps_the_extension() {
+        let policy = FilenamePolicy {
+            max_length: 10,
+            ..policy(FilenameMode::Sanitize)
+        };
+        assert_eq!(policy.apply("abcdefghijkl.txt").unwrap(), "abcdef.txt");
+        // the extension is too long to be kept
+        assert_eq!(policy.apply("a.abcdefghijkl").unwrap(), "a.abcdefgh");
+        // multibyte characters aren't split
+        assert_eq!(policy.apply("ääääää.txt.gz").unwrap(), "äää.gz");
+        // the truncated filename doesn't end with a dot or space
+        assert_eq!(policy.apply("abcdefgh..extension").unwrap(), "abcdefgh");
+        assert_eq!(policy.apply("abcdefghi xyz").unwrap(), "abcdefghi");
+        // the truncated filename isn't a reserved name
+        let policy = FilenamePolicy {
+            max_length: 3,
+            ..policy
+        };
+        assert_eq!(policy.apply("CONSOLE").unwrap(), "_CO");
+        assert_eq!(policy.apply("NULL").unwrap(), "_NU");
+    }
+
+    #[test]
+    fn unicode_can_be_forbidden() {
+        let reject = FilenamePolicy {
+            allow_unicode: false,
+            ..policy(FilenameMode::Reject)
+        };
+        assert!(is_rejected(&reject, "héllo.txt"));
+        let sanitize = FilenamePolicy {
+            allow_unicode: false,
+            ..policy(FilenameMode::Sanitize)
+        };
+        assert_eq!(sanitize.apply("héllo.txt").unwrap(), "h_llo.txt");
+    }
+
+    #[test]
+    fn stored_filenames_are_single_path_components() {
+        assert!(is_valid_stored_filename("d74981ef_x.txt"));
+        assert!(is_valid_stored_filename(".hidden"));
+        for filename in ["", ".", "..", "a/b", "..\\a", "a\0b"] {
+            assert!(!is_valid_stored_filename(filename));
+        }
+    }
+
+    #[test]
+    fn original_filenames_lose_the_hash_prefix() {
+        assert_eq!(original_filename("d74981ef_my_file.txt"), "my_file.txt");
+        assert_eq!(original_filename("nohash"), "nohash");
+    }
+
+    #[test]
+    fn filenames_are_encoded_for_urls_and_headers() {
+        assert_eq!(url_encode("a b/ü.txt"), "a%20b%2F%C3%BC.txt");
+        assert_eq!(
+            content_disposition("attachment", "naïve \"x\".txt"),
+            "attachment; filename=\"na_ve _x_.txt\"; filename*=UTF-8''na%C3%AFve%20%22x%22.txt"
+        );
+    }
+}
@@ -0,0 +1,443 @@
+use std::{cmp::Ordering, collections::BTreeMap, time::UNIX_EPOCH};
+
+use axum::{
+    extract,
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::prelude::*;
+use serde::{Deserialize, Serialize};
+use tokio::fs::read_dir;
+use tracing::error;
+
+use crate::{
+    auth::{AuthRequest, Authorization},
+    auth_helper::authorize_by_headers,
+    error::UploadError,
+    file_data::{FileData, FileDataPermission},
+    filename::{original_filename, url_encode},
+    state::State,
+};
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+/// a stored file and its file data
+pub struct StoredFile {
+    /// the name of the stored file (`{hash}_{filename}`)
+    pub name: String,
+    pub file_data: FileData,
+    /// the size of the original file in bytes
+    pub size: u64,
+    pub uploaded_at: u64,
+}
+
+impl StoredFile {
+    /// the display name of the file or the filename the user uploaded the file with (without the hash prefix)
+    pub fn filename(&self) -> &str {
+        self.file_data.display_name(original_filename(&self.name))
+    }
+
+    /// checks if the user is allowed to see the file, admins can see every file, other users only their own files
+    pub fn visible_for(&self, authorization: &Authorization) -> bool {
+        authorization.owns(&self.file_data.uploader)
+    }
+}
+
+/// reads all stored files and their file data, files that are still uploading or expired are skipped
+pub async fn stored_files(state: &State) -> Result<Vec<StoredFile>, UploadError> {
+    let mut files = Vec::new();
+    let mut content = read_dir(&state.upload_directory).await?;
+    while let Some(file) = content.next_entry().await? {
+        let Ok(name) = file.file_name().into_string() else {
+            continue;
+        };
+        if name.ends_with(".upload") {
+            continue;
+        }
+        let file_data = match FileData::read_from(
+            &state.data_directory.join(&name),
+            state.encryption.as_deref(),
+        )
+        .await
+        {
+            Ok(file_data) => file_data.unwrap_or_default(),
+            Err(e) => {
+                error!(file = %name, error = ?e, "error while reading file data");
+                continue;
+            }
+        };
+        if file_data.expired() {
+            continue;
+        }
+        // the file may have been removed in the meantime
+        let Ok(metadata) = file.metadata().await else {
+            continue;
+        };
+        let uploaded_at = file_data.uploaded_at.unwrap_or_else(|| {
+            metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default()
+        });
+        files.push(StoredFile {
+            name,
+            size: file_data.size.unwrap_or(metadata.len()),
+            uploaded_at,
+            file_data,
+        });
+    }
+    Ok(files)
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    #[default]
+    Name,
+    Size,
+    UploadedAt,
+    ExpiresAt,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Deserialize)]
+pub struct ListQuery {
+    /// only files whose name or original filename starts with the prefix
+    pub prefix: Option<String>,
+    /// only files uploaded by this identity
+    uploader: Option<String>,
+    /// only files that expire before this unix timestamp
+    expires_before: Option<u64>,
+    /// only files that expire after this unix timestamp (or never)
+    expires_after: Option<u64>,
+    /// only files that have (`true`) or don't have (`false`) a ttl
+    expires: Option<bool>,
+    /// only files that have all of these comma separated tags
+    tags: Option<String>,
+    #[serde(default)]
+    pub sort: SortField,
+    #[serde(default)]
+    pub order: SortOrder,
+    limit: Option<usize>,
+    /// the `next_cursor` of the previous page
+    cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct FileEntry<'a> {
+    pub name: &'a str,
+    pub filename: &'a str,
+    pub link: String,
+    pub size: u64,
+    pub hash: Option<&'a str>,
+    pub uploaded_at: u64,
+    pub uploader: Option<&'a str>,
+    pub content_type: Option<&'a str>,
+    /// the unix timestamp the file will be deleted at
+    pub expires_at: Option<u64>,
+    pub download_permission: Option<&'a FileDataPermission>,
+    /// the file was encrypted by the client
+    pub encrypted: bool,
+    /// the id of the collection the file belongs to
+    pub collection: Option<&'a str>,
+    pub meta: Option<&'a BTreeMap<String, String>>,
+    pub tags: Option<&'a [String]>,
+    pub pinned: bool,
+    pub last_downloaded_at: Option<u64>,
+}
+
+impl<'a> From<&'a StoredFile> for FileEntry<'a> {
+    fn from(file: &'a StoredFile) -> Self {
+        Self {
+            name: &file.name,
+            filename: file.filename(),
+            link: format!("/d/{}", url_encode(&file.name)),
+            size: file.size,
+            hash: file.file_data.hash.as_deref(),
+            uploaded_at: file.uploaded_at,
+            uploader: file.file_data.uploader.as_deref(),
+            content_type: file.file_data.content_type.as_deref(),
+            expires_at: file.file_data.ttl,
+            download_permission: file.file_data.download_permission.as_ref(),
+            encrypted: file.file_data.encrypted.unwrap_or_default(),
+            collection: file.file_data.collection.as_deref(),
+            meta: file.file_data.meta.as_ref(),
+            tags: file.file_data.tags.as_deref(),
+            pinned: file.file_data.pinned.unwrap_or_default(),
+            last_downloaded_at: file.file_data.last_downloaded_at,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ListResponse<'a> {
+    pub files: Vec<FileEntry<'a>>,
+    /// pass this as `cursor` to get the next page, `None` if this is the last page
+    pub next_cursor: Option<String>,
+}
+
+/// the value the files are sorted by, numbers are padded so they can be compared as strings
+fn sort_key(file: &StoredFile, sort: SortField) -> String {
+    match sort {
+        SortField::Name => file.name.clone(),
+        SortField::Size => format!("{:020}", file.size),
+        SortField::UploadedAt => format!("{:020}", file.uploaded_at),
+        // files that never expire are sorted last
+        SortField::ExpiresAt => format!("{:020}", file.file_data.ttl.unwrap_or(u64::MAX)),
+    }
+}
+
+/// the cursor contains the sort key and the name of the last file of the page
+fn encode_cursor(key: &str, name: &str) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(format!("{key}\n{name}"))
+}
+
+fn decode_cursor(cursor: &str) -> Result<(String, String), UploadError> {
+    BASE64_URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .and_then(|decoded| {
+            decoded
+                .split_once('\n')
+                .map(|(key, name)| (key.to_string(), name.to_string()))
+        })
+        .ok_or_else(|| UploadError::InvalidQueryArgument("cursor".to_string()))
+}
+
+impl ListQuery {
+    fn matches(&self, file: &StoredFile) -> bool {
+        if let Some(prefix) = &self.prefix {
+            if !file.name.starts_with(prefix) && !file.filename().starts_with(prefix) {
+                return false;
+            }
+        }
+        if self.uploader.is_some() && file.file_data.uploader != self.uploader {
+            return false;
+        }
+        let ttl = file.file_data.ttl;
+        if let Some(expires) = self.expires {
+            if ttl.is_some() != expires {
+                return false;
+            }
+        }
+        if let Some(expires_before) = self.expires_before {
+            if ttl.is_none_or(|ttl| ttl >= expires_before) {
+                return false;
+            }
+        }
+        if let Some(expires_after) = self.expires_after {
+            if ttl.is_some_and(|ttl| ttl <= expires_after) {
+                return false;
+            }
+        }
+        if let Some(tags) = &self.tags {
+            let file_tags = file.file_data.tags.as_deref().unwrap_or_default();
+            if !tags
+                .split(',')
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .all(|tag| file_tags.iter().any(|file_tag| file_tag == tag))
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// filters and sorts the files the user is allowed to see and returns the requested page of them
+/// and the cursor of the next page
+pub fn query_files<'a>(
+    files: &'a [StoredFile],
+    authorization: &Authorization,
+    query: &ListQuery,
+) -> Result<(Vec<&'a StoredFile>, Option<String>), UploadError> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let cursor = query.cursor.as_deref().map(decode_cursor).transpose()?;
+
+    let mut files = files
+        .iter()
+        .filter(|file| file.visible_for(authorization) && query.matches(file))
+        .map(|file| (sort_key(file, query.sort), file))
+        .collect::<Vec<(String, &StoredFile)>>();
+
+    let compare = |(a_key, a): &(String, &StoredFile), b_key: &str, b_name: &str| {
+        let ordering = a_key
+            .as_str()
+            .cmp(b_key)
+            .then_with(|| a.name.as_str().cmp(b_name));
+        match query.order {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
+        }
+    };
+    files.sort_by(|a, b| compare(a, &b.0, &b.1.name));
+
+    // skip the files of the previous pages
+    let start = match &cursor {
+        Some((key, name)) => {
+            files.partition_point(|file| compare(file, key, name) != Ordering::Greater)
+        }
+        None => 0,
+    };
+    let page = &files[start..files.len().min(start + limit)];
+    let next_cursor = if start + limit < files.len() {
+        page.last()
+            .map(|(key, file)| encode_cursor(key, &file.name))
+    } else {
+        None
+    };
+    Ok((page.iter().map(|(_, file)| *file).collect(), next_cursor))
+}
+
+pub async fn list(
+    extract::State(state): extract::State<State>,
+    extract::Query(query): extract::Query<ListQuery>,
+    headers: HeaderMap,
+) -> Result<Response, UploadError> {
+    let authorization = authorize_by_headers(&state, &headers, AuthRequest::List)?;
+    let files = stored_files(&state).await?;
+    let (page, next_cursor) = query_files(&files, &authorization, &query)?;
+
+    let response = ListResponse {
+        files: page.into_iter().map(FileEntry::from).collect(),
+        next_cursor,
+    };
+    Ok(Json(response).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::TtlPolicy;
+
+    fn file(name: &str, uploader: Option<&str>, size: u64) -> StoredFile {
+        StoredFile {
+            name: name.to_string(),
+            file_data: FileData {
+                uploader: uploader.map(str::to_string),
+                ..Default::default()
+            },
+            size,
+            uploaded_at: 0,
+        }
+    }
+
+    fn admin() -> Authorization {
+        Authorization {
+            max_filesize: 0,
+            identity: Some("admin".to_string()),
+            admin: true,
+            ttl_policy: TtlPolicy::default(),
+        }
+    }
+
+    fn query(query: serde_json::Value) -> ListQuery {
+        serde_json::from_value(query).unwrap()
+    }
+
+    /// follows the cursors until the last page and returns the names of all pages
+    fn all_pages(
+        files: &[StoredFile],
+        authorization: &Authorization,
+        mut query: ListQuery,
+    ) -> Vec<Vec<String>> {
+        let mut pages = Vec::new();
+        loop {
+            let (page, next_cursor) = query_files(files, authorization, &query).unwrap();
+            pages.push(page.iter().map(|file| file.name.clone()).collect());
+            match next_cursor {
+                Some(cursor) => query.cursor = Some(cursor),
+                None => return pages,
+            }
+        }
+    }
+
+    #[test]
+    fn cursor_pages_through_all_files() {
+        let files = ["c", "a", "e", "b", "d"].map(|name| file(name, None, 0));
+        let pages = all_pages(&files, &admin(), query(serde_json::json!({ "limit": 2 })));
+        assert_eq!(pages, [vec!["a", "b"], vec!["c", "d"], vec!["e"]]);
+    }
+
+    #[test]
+    fn cursor_pages_descending_with_equal_sort_keys() {
+        let files = [
+            file("a", None, 1),
+            file("b", None, 2),
+            file("c", None, 1),
+            file("d", None, 2),
+        ];
+        let pages = all_pages(
+            &files,
+            &admin(),
+            query(serde_json::json!({ "limit": 1, "sort": "size", "order": "desc" })),
+        );
+        assert_eq!(pages, [["d"], ["b"], ["c"], ["a"]]);
+    }
+
+    #[test]
+    fn last_full_page_has_no_cursor() {
+        let files = ["a", "b"].map(|name| file(name, None, 0));
+        let (page, next_cursor) =
+            query_files(&files, &admin(), &query(serde_json::json!({ "limit": 2 }))).unwrap();
+        assert_eq!(page.len(), 2);
+        assert!(next_cursor.is_none());
+    }
+
+    #[test]
+    fn invalid_cursor_is_rejected() {
+        let files = [file("a", None, 0)];
+        for cursor in [
+            "not base64!",
+            &BASE64_URL_SAFE_NO_PAD.encode("no separator"),
+        ] {
+            assert!(matches!(
+                query_files(
+                    &files,
+                    &admin(),
+                    &query(serde_json::json!({ "cursor": cursor }))
+                ),
+                Err(UploadError::InvalidQueryArgument(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn cursor_round_trip() {
+        let cursor = encode_cursor("00000000000000000042", "hash_file\nname");
+        let (key, name) = decode_cursor(&cursor).unwrap();
+        assert_eq!(key, "00000000000000000042");
+        assert_eq!(name, "hash_file\nname");
+    }
+
+    #[test]
+    fn files_are_only_visible_for_their_uploader() {
+        let files = [file("a", Some("alice"), 0), file("b", None, 0)];
+        let user = |identity: Option<&str>| Authorization {
+            identity: identity.map(str::to_string),
+            admin: false,
+            ..admin()
+        };
+        let names = |authorization: &Authorization| {
+            all_pages(&files, authorization, query(serde_json::json!({})))
+        };
+        assert_eq!(names(&user(Some("alice"))), [["a"]]);
+        assert_eq!(names(&user(Some("bob"))), [Vec::<String>::new()]);
+        // anonymous users don't own the files of other anonymous users
+        assert_eq!(names(&user(None)), [Vec::<String>::new()]);
+        assert_eq!(names(&admin()), [["a", "b"]]);
+    }
+}
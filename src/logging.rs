@@ -0,0 +1,153 @@
+use std::{
+    env,
+    io::{stdout, IsTerminal},
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::Request,
+    http::{header, HeaderValue, Method},
+    middleware::Next,
+    response::Response,
+};
+use http_body::{Frame, SizeHint};
+use tracing::{field, info, info_span, Instrument, Span};
+use tracing_subscriber::EnvFilter;
+
+use crate::random::generate_random_characters;
+
+/// the header of responses containing the generated id of the request
+pub const REQUEST_ID_HEADER_NAME: &str = "X-Request-Id";
+
+/// the length of the generated request ids
+const REQUEST_ID_LENGTH: usize = 16;
+
+/// sets up the logging, `LOG_LEVEL` filters the events (e.g. `debug` or `upload=debug,info`)
+/// and `LOG_FORMAT=json` prints the events as json lines
+pub fn init() {
+    let filter = EnvFilter::try_from_env("LOG_LEVEL").unwrap_or_else(|_| EnvFilter::new("info"));
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        // colors would end up in log files
+        .with_ansi(stdout().is_terminal());
+    match env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => subscriber.json().init(),
+        Ok("text") | Err(_) => subscriber.init(),
+        Ok(format) => panic!("Invalid log format '{format}', use 'text' or 'json'."),
+    }
+}
+
+/// adds the identity of the authorized user to the span of the request
+pub fn record_identity(identity: &str) {
+    Span::current().record("identity", identity);
+}
+
+/// adds the size of the transferred file to the span of the request
+pub fn record_bytes(bytes: u64) {
+    Span::current().record("bytes", bytes);
+}
+
+/// the body of a response, the request is logged as finished when the body was sent or dropped
+struct LoggedBody {
+    inner: Body,
+    span: Span,
+    status: u16,
+    start: Instant,
+    /// the bytes of the body that were sent
+    sent_bytes: u64,
+    /// the length of the body if it's known, the body isn't polled until its end if the length was reached
+    expected_bytes: Option<u64>,
+    completed: bool,
+}
+
+impl http_body::Body for LoggedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(frame))) => {
+                self.sent_bytes += frame.data_ref().map_or(0, |data| data.len() as u64);
+            }
+            Poll::Ready(None) => self.completed = true,
+            _ => {}
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        let completed = self.completed
+            || self.expected_bytes == Some(self.sent_bytes)
+            || http_body::Body::is_end_stream(&self.inner);
+        info!(
+            parent: &self.span,
+            status = self.status,
+            latency_ms = self.start.elapsed().as_millis() as u64,
+            sent_bytes = self.sent_bytes,
+            completed,
+            "finished request"
+        );
+    }
+}
+
+/// middleware that runs every request in its own span with a generated id,
+/// the id is returned in the `X-Request-Id` header
+pub async fn trace_request(request: Request, next: Next) -> Response {
+    let request_id = generate_random_characters(REQUEST_ID_LENGTH);
+    let span = info_span!(
+        "request",
+        id = %request_id,
+        method = %request.method(),
+        path = %request.uri().path(),
+        identity = field::Empty,
+        bytes = field::Empty,
+    );
+    let start = Instant::now();
+    // the body of the response to a HEAD request isn't sent
+    let head = request.method() == Method::HEAD;
+    let response = next.run(request).instrument(span.clone()).await;
+    // the request is finished when the body was sent, streamed downloads take longer than the handler
+    let status = response.status().as_u16();
+    let expected_bytes = if head {
+        Some(0)
+    } else {
+        response
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+    };
+    let mut response = response.map(|body| {
+        Body::new(LoggedBody {
+            inner: body,
+            span,
+            status,
+            start,
+            sent_bytes: 0,
+            expected_bytes,
+            completed: false,
+        })
+    });
+    response.headers_mut().insert(
+        REQUEST_ID_HEADER_NAME,
+        HeaderValue::from_str(&request_id).unwrap(),
+    );
+    response
+}
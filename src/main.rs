@@ -1,52 +1,212 @@
-use std::{env, path::PathBuf};
+use std::{env, net::SocketAddr, path::PathBuf};
 
-use axum::{routing, serve, Router};
+use axum::{middleware, routing, serve, Router};
 use tokio::net::TcpListener;
+use tracing::info;
 
-use crate::{auth::AuthConfig, state::State};
+use crate::{auth::AuthConfig, config::Config, state::State};
 
+mod archive;
+mod audit;
 mod auth;
 mod auth_helper;
+mod collection;
+mod compression;
+mod config;
+mod content_type;
 mod download;
+mod encryption;
 mod error;
+mod eviction;
+mod exif;
 mod file_data;
+mod filename;
+mod list;
+mod logging;
+mod metrics;
+mod pages;
 mod random;
+mod range;
+mod recovery;
+mod retention;
 mod state;
+mod storage;
+mod thumbnail;
+mod trash;
 mod ttl_killer;
+mod unpack;
+mod update;
 mod upload;
+mod webhook;
 
 #[tokio::main]
 async fn main() {
+    logging::init();
     let auth_config = AuthConfig::read_from_file();
+    let config = Config::read_from_file();
     let upload_directory = env::var("UPLOAD_DIRECTORY").unwrap_or_else(|_| "upload".to_string());
     let data_directory = env::var("DATA_DIRECTORY").unwrap_or_else(|_| "data".to_string());
+    let thumbnail_directory =
+        env::var("THUMBNAIL_DIRECTORY").unwrap_or_else(|_| "thumbnails".to_string());
+    let collection_directory =
+        env::var("COLLECTION_DIRECTORY").unwrap_or_else(|_| "collections".to_string());
+    let trash_directory = env::var("TRASH_DIRECTORY").unwrap_or_else(|_| "trash".to_string());
+    let quarantine_directory =
+        env::var("QUARANTINE_DIRECTORY").unwrap_or_else(|_| "quarantine".to_string());
+    let webhook_directory = webhook::directory();
     // check upload and data directory values
     if upload_directory == data_directory
         || PathBuf::from(&data_directory).starts_with(&upload_directory)
     {
         panic!("Data directory cannot be the same directory as the upload directory or be a subdirectory of it.\nChange the 'UPLOAD_DIRECTORY' or 'DATA_DIRECTORY' environment variable to another one.");
     }
+    if [&upload_directory, &data_directory].contains(&&thumbnail_directory)
+        || PathBuf::from(&thumbnail_directory).starts_with(&upload_directory)
+        || PathBuf::from(&thumbnail_directory).starts_with(&data_directory)
+    {
+        panic!("Thumbnail directory cannot be the same directory as the upload or data directory or be a subdirectory of the upload or data directory.\nChange the 'THUMBNAIL_DIRECTORY' environment variable to another one.");
+    }
+    if [&upload_directory, &data_directory, &thumbnail_directory].contains(&&collection_directory)
+        || PathBuf::from(&collection_directory).starts_with(&upload_directory)
+        || PathBuf::from(&collection_directory).starts_with(&data_directory)
+    {
+        panic!("Collection directory cannot be the same directory as the upload, data or thumbnail directory or be a subdirectory of the upload or data directory.\nChange the 'COLLECTION_DIRECTORY' environment variable to another one.");
+    }
+    if [
+        &upload_directory,
+        &data_directory,
+        &thumbnail_directory,
+        &collection_directory,
+    ]
+    .contains(&&trash_directory)
+        || PathBuf::from(&trash_directory).starts_with(&upload_directory)
+        || PathBuf::from(&trash_directory).starts_with(&data_directory)
+    {
+        panic!("Trash directory cannot be the same directory as the upload, data, thumbnail or collection directory or be a subdirectory of the upload or data directory.\nChange the 'TRASH_DIRECTORY' environment variable to another one.");
+    }
+    if [
+        &upload_directory,
+        &data_directory,
+        &thumbnail_directory,
+        &collection_directory,
+        &trash_directory,
+    ]
+    .contains(&&quarantine_directory)
+        || PathBuf::from(&quarantine_directory).starts_with(&upload_directory)
+        || PathBuf::from(&quarantine_directory).starts_with(&data_directory)
+    {
+        panic!("Quarantine directory cannot be the same directory as the upload, data, thumbnail, collection or trash directory or be a subdirectory of the upload or data directory.\nChange the 'QUARANTINE_DIRECTORY' environment variable to another one.");
+    }
+    if [
+        &upload_directory,
+        &data_directory,
+        &thumbnail_directory,
+        &collection_directory,
+        &trash_directory,
+        &quarantine_directory,
+    ]
+    .iter()
+    .any(|directory| webhook_directory == PathBuf::from(directory))
+        || webhook_directory.starts_with(&upload_directory)
+        || webhook_directory.starts_with(&data_directory)
+    {
+        panic!("Webhook directory cannot be the same directory as the upload, data, thumbnail, collection, trash or quarantine directory or be a subdirectory of the upload or data directory.\nChange the 'WEBHOOK_DIRECTORY' environment variable to another one.");
+    }
     let state = State::new(
         auth_config,
+        config,
         PathBuf::from(&upload_directory),
         PathBuf::from(&data_directory),
+        PathBuf::from(&thumbnail_directory),
+        PathBuf::from(&collection_directory),
+        PathBuf::from(&trash_directory),
     )
     .await;
+    recovery::recover(&state, &PathBuf::from(&quarantine_directory)).await;
 
     ttl_killer::start_ttl_killer(state.clone());
+    trash::start_trash_purger(state.clone());
+    eviction::start_evictor(state.clone());
+    recovery::start_scrubber(state.clone());
+    webhook::start_webhook_sender(state.clone());
 
-    let router = Router::new()
-        .route("/upload/:filename", routing::post(upload::upload))
-        .route("/d/:filename", routing::get(download::download))
-        .with_state(state);
+    let track_uploads = middleware::from_fn_with_state(state.clone(), metrics::track_uploads);
+    let track_downloads = middleware::from_fn_with_state(state.clone(), metrics::track_downloads);
+    let mut router = Router::new()
+        .route(
+            "/upload/:filename",
+            routing::post(upload::upload).layer(track_uploads),
+        )
+        .route(
+            "/d/:filename",
+            routing::get(download::download)
+                .layer(track_downloads.clone())
+                .patch(update::update),
+        )
+        .route("/d/:filename/info", routing::get(download::info))
+        .route("/e2e", routing::get(pages::e2e_upload))
+        .route("/files", routing::get(list::list))
+        .route(
+            "/archive",
+            routing::get(archive::archive).layer(track_downloads.clone()),
+        )
+        .route("/f/:filename", routing::get(pages::file_page))
+        .route("/t/:filename", routing::get(thumbnail::thumbnail))
+        .route(
+            "/c",
+            routing::get(collection::list_collections).post(collection::create_collection),
+        )
+        .route(
+            "/c/:id",
+            routing::get(collection::collection).delete(collection::delete_collection),
+        )
+        .route(
+            "/c/:id/unlock",
+            routing::post(collection::unlock_collection),
+        )
+        .route(
+            "/c/:id/archive",
+            routing::get(collection::collection_archive).layer(track_downloads),
+        )
+        .route(
+            "/c/:id/files/:filename",
+            routing::put(collection::add_member).delete(collection::remove_member),
+        )
+        .route("/trash", routing::get(trash::list_trash))
+        .route(
+            "/trash/:filename",
+            routing::delete(trash::delete_from_trash),
+        )
+        .route("/trash/:filename/restore", routing::post(trash::restore))
+        .route("/", routing::get(pages::index_page));
+    if state.config.audit.enabled {
+        router = router.route("/audit", routing::get(audit::query));
+    }
+    router = router
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            audit::track_client,
+        ))
+        .layer(middleware::from_fn(logging::trace_request));
+    if state.config.metrics.enabled {
+        match &state.config.metrics.listen_address {
+            Some(listen_address) => metrics::serve_separately(state.clone(), listen_address).await,
+            None => router = router.route("/metrics", routing::get(metrics::metrics)),
+        }
+    }
+    let router = router.with_state(state);
 
     let listen_address =
         env::var("LISTEN_ADDRESS").unwrap_or_else(|_| "127.0.0.1:3000".to_string());
     let listener = TcpListener::bind(&listen_address)
         .await
         .expect("Error while listening on listen address");
-    println!("INFO: Server listening on http://{listen_address}");
-    serve(listener, router)
-        .await
-        .expect("Error while serving http server");
+    info!("server listening on http://{listen_address}");
+    // the audit log contains the ip of the clients
+    serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .expect("Error while serving http server");
 }
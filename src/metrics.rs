@@ -0,0 +1,353 @@
+use std::{
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{self, Request},
+    http::{header, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing, serve, Router,
+};
+use http_body::{Frame, SizeHint};
+use prometheus::{
+    HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use serde::Deserialize;
+use tokio::{fs::read_dir, io, net::TcpListener, sync::Mutex};
+use tracing::{error, info};
+
+use crate::{error::UploadError, state::State};
+
+/// the buckets of the transfer durations in seconds, transfers of big files take minutes
+const DURATION_BUCKETS: [f64; 12] = [
+    0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0,
+];
+
+/// the minimal time between two measurements of the upload directory, scrapes in between get the last measurement
+const STORAGE_MEASUREMENT_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Deserialize, Default)]
+#[serde(default)]
+pub struct MetricsConfig {
+    /// Serve metrics in the Prometheus text format at `/metrics`
+    pub enabled: bool,
+    /// Serve the metrics on this address instead of the address of the server
+    pub listen_address: Option<String>,
+}
+
+/// the metrics of the server, they are rendered when `/metrics` is requested
+pub struct Metrics {
+    registry: Registry,
+    transfer_duration: HistogramVec,
+    transferred_bytes: IntCounterVec,
+    active_transfers: IntGaugeVec,
+    auth_attempts: IntCounterVec,
+    ttl_deletions: IntCounterVec,
+    storage_used: IntGauge,
+    stored_files: IntGauge,
+    /// the time of the last measurement of the upload directory
+    storage_measured_at: Mutex<Option<Instant>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let transfer_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "transfer_duration_seconds",
+                "The duration of uploads and downloads until the body was transferred",
+            )
+            .buckets(DURATION_BUCKETS.to_vec()),
+            &["direction", "status"],
+        )
+        .unwrap();
+        let transferred_bytes = IntCounterVec::new(
+            Opts::new(
+                "transferred_bytes_total",
+                "The bytes of the bodies of uploads and downloads",
+            ),
+            &["direction"],
+        )
+        .unwrap();
+        let active_transfers = IntGaugeVec::new(
+            Opts::new("active_transfers", "The uploads and downloads in progress"),
+            &["direction"],
+        )
+        .unwrap();
+        let auth_attempts = IntCounterVec::new(
+            Opts::new(
+                "auth_attempts_total",
+                "The authorizations of requests by their auth scheme",
+            ),
+            &["scheme", "result"],
+        )
+        .unwrap();
+        let ttl_deletions = IntCounterVec::new(
+            Opts::new(
+                "ttl_deletions_total",
+                "The files and collections removed by the ttl killer",
+            ),
+            &["kind"],
+        )
+        .unwrap();
+        let storage_used = IntGauge::new(
+            "storage_used_bytes",
+            "The bytes used by the files in the upload directory",
+        )
+        .unwrap();
+        let stored_files =
+            IntGauge::new("stored_files", "The files in the upload directory").unwrap();
+
+        let registry = Registry::new();
+        registry
+            .register(Box::new(transfer_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(transferred_bytes.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(active_transfers.clone()))
+            .unwrap();
+        registry.register(Box::new(auth_attempts.clone())).unwrap();
+        registry.register(Box::new(ttl_deletions.clone())).unwrap();
+        registry.register(Box::new(storage_used.clone())).unwrap();
+        registry.register(Box::new(stored_files.clone())).unwrap();
+        Self {
+            registry,
+            transfer_duration,
+            transferred_bytes,
+            active_transfers,
+            auth_attempts,
+            ttl_deletions,
+            storage_used,
+            stored_files,
+            storage_measured_at: Mutex::new(None),
+        }
+    }
+}
+
+impl Metrics {
+    /// counts an authorization with the `scheme` of the `Authorization` header
+    pub fn record_auth(&self, scheme: &str, success: bool) {
+        let result = if success { "success" } else { "failure" };
+        self.auth_attempts
+            .with_label_values(&[scheme, result])
+            .inc();
+    }
+
+    /// counts a file or collection the ttl killer removed, `kind` is `file` or `collection`
+    pub fn record_ttl_deletion(&self, kind: &str) {
+        self.ttl_deletions.with_label_values(&[kind]).inc();
+    }
+
+    fn transferred_bytes(&self, direction: Direction) -> IntCounter {
+        self.transferred_bytes
+            .with_label_values(&[direction.label()])
+    }
+
+    /// measures the size and number of the files in the upload directory
+    async fn measure_storage(&self, upload_directory: &Path) -> io::Result<()> {
+        let mut size = 0;
+        let mut files = 0;
+        let mut content = read_dir(upload_directory).await?;
+        while let Some(file) = content.next_entry().await? {
+            // the file could have been removed in the meantime
+            let Ok(metadata) = file.metadata().await else {
+                continue;
+            };
+            size += metadata.len();
+            // files that weren't uploaded completely have a .upload suffix
+            if !file.file_name().to_string_lossy().ends_with(".upload") {
+                files += 1;
+            }
+        }
+        self.storage_used.set(size as i64);
+        self.stored_files.set(files);
+        Ok(())
+    }
+
+    /// renders all metrics, the upload directory is measured again if the last measurement is old enough
+    async fn render(&self, upload_directory: &Path) -> io::Result<String> {
+        // the lock is held during the measurement, so concurrent scrapes don't measure the directory twice
+        let mut measured_at = self.storage_measured_at.lock().await;
+        if measured_at
+            .is_none_or(|measured_at| measured_at.elapsed() >= STORAGE_MEASUREMENT_INTERVAL)
+        {
+            self.measure_storage(upload_directory).await?;
+            *measured_at = Some(Instant::now());
+        }
+        drop(measured_at);
+        TextEncoder::new()
+            .encode_to_string(&self.registry.gather())
+            .map_err(io::Error::other)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Direction {
+    Upload,
+    Download,
+}
+
+impl Direction {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Upload => "upload",
+            Self::Download => "download",
+        }
+    }
+}
+
+/// an upload or download in progress, its duration is recorded when it's dropped
+struct Transfer {
+    metrics: Arc<Metrics>,
+    direction: Direction,
+    /// `None` if the request was aborted before there was a response
+    status: Option<StatusCode>,
+    start: Instant,
+}
+
+impl Transfer {
+    fn start(metrics: Arc<Metrics>, direction: Direction) -> Self {
+        metrics
+            .active_transfers
+            .with_label_values(&[direction.label()])
+            .inc();
+        Self {
+            metrics,
+            direction,
+            status: None,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        let direction = self.direction.label();
+        let status = match self.status {
+            Some(status) => status.as_str().to_string(),
+            None => "aborted".to_string(),
+        };
+        self.metrics
+            .active_transfers
+            .with_label_values(&[direction])
+            .dec();
+        self.metrics
+            .transfer_duration
+            .with_label_values(&[direction, &status])
+            .observe(self.start.elapsed().as_secs_f64());
+    }
+}
+
+/// a body that counts the bytes passing through it, the transfer ends when the body is dropped
+struct CountingBody {
+    inner: Body,
+    bytes: Option<IntCounter>,
+    _transfer: Option<Transfer>,
+}
+
+impl http_body::Body for CountingBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+        if let (Poll::Ready(Some(Ok(frame))), Some(bytes)) = (&poll, &self.bytes) {
+            if let Some(data) = frame.data_ref() {
+                bytes.inc_by(data.len() as u64);
+            }
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+async fn track(state: &State, direction: Direction, request: Request, next: Next) -> Response {
+    if !state.config.metrics.enabled {
+        return next.run(request).await;
+    }
+    let metrics = state.metrics.clone();
+    let mut transfer = Transfer::start(metrics.clone(), direction);
+    // the body of uploads is in the request, the body of downloads in the response
+    let request = match direction {
+        Direction::Upload => request.map(|body| {
+            Body::new(CountingBody {
+                inner: body,
+                bytes: Some(metrics.transferred_bytes(direction)),
+                _transfer: None,
+            })
+        }),
+        Direction::Download => request,
+    };
+    // the body of the response to a HEAD request isn't sent
+    let head = request.method() == Method::HEAD;
+    let response = next.run(request).await;
+    transfer.status = Some(response.status());
+    response.map(|body| {
+        Body::new(CountingBody {
+            inner: body,
+            bytes: (matches!(direction, Direction::Download) && !head)
+                .then(|| metrics.transferred_bytes(direction)),
+            _transfer: Some(transfer),
+        })
+    })
+}
+
+/// middleware that records the uploads of a route
+pub async fn track_uploads(
+    extract::State(state): extract::State<State>,
+    request: Request,
+    next: Next,
+) -> Response {
+    track(&state, Direction::Upload, request, next).await
+}
+
+/// middleware that records the downloads of a route
+pub async fn track_downloads(
+    extract::State(state): extract::State<State>,
+    request: Request,
+    next: Next,
+) -> Response {
+    track(&state, Direction::Download, request, next).await
+}
+
+/// renders the metrics in the Prometheus text format
+pub async fn metrics(
+    extract::State(state): extract::State<State>,
+) -> Result<Response, UploadError> {
+    let metrics = state.metrics.render(&state.upload_directory).await?;
+    Ok(([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], metrics).into_response())
+}
+
+/// serves `/metrics` on its own address, so it doesn't have to be reachable from the outside
+pub async fn serve_separately(state: State, listen_address: &str) {
+    let listener = TcpListener::bind(listen_address)
+        .await
+        .expect("Error while listening on metrics listen address");
+    info!("metrics listening on http://{listen_address}/metrics");
+    let router = Router::new()
+        .route("/metrics", routing::get(metrics))
+        .with_state(state);
+    tokio::spawn(async move {
+        if let Err(e) = serve(listener, router).await {
+            error!(error = ?e, "error while serving metrics");
+        }
+    });
+}
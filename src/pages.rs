@@ -0,0 +1,121 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, Response, StatusCode},
+};
+use serde::Deserialize;
+
+mod collection;
+mod file;
+mod highlight;
+mod index;
+
+pub use collection::{collection_page, collection_password_page};
+pub use file::file_page;
+pub use index::index_page;
+
+const E2E_UPLOAD_PAGE: &str = include_str!("pages/e2e_upload.html");
+const E2E_DOWNLOAD_PAGE: &str = include_str!("pages/e2e_download.html");
+
+const STYLE: &str = include_str!("pages/style.css");
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct PagesConfig {
+    /// Serve a landing page with a preview for every file at `/f/{filename}`
+    pub file_pages: bool,
+    /// Serve a browsable index of the files at `/` to users with the list permission
+    pub index: bool,
+    /// The maximum amount of bytes of a text file that are shown in its preview
+    pub max_text_preview: u64,
+}
+
+impl Default for PagesConfig {
+    fn default() -> Self {
+        Self {
+            file_pages: false,
+            index: false,
+            max_text_preview: 256 * 1024,
+        }
+    }
+}
+
+/// checks if the request was sent by a browser that wants to display a page
+pub fn accepts_html(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .map(|accept| accept.contains("text/html"))
+        .unwrap_or_default()
+}
+
+pub fn html_response(content: impl Into<Body>) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .header("X-Content-Type-Options", "nosniff")
+        .body(content.into())
+        .unwrap()
+}
+
+/// the page that encrypts files in the browser before uploading them
+pub async fn e2e_upload() -> Response<Body> {
+    html_response(E2E_UPLOAD_PAGE)
+}
+
+/// the page that downloads and decrypts client side encrypted files in the browser
+pub fn e2e_download() -> Response<Body> {
+    html_response(E2E_DOWNLOAD_PAGE)
+}
+
+/// escapes the characters that have a special meaning in html
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// wraps the `content` into a html document with the shared style, `title` gets escaped
+fn layout(title: &str, content: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+        <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+        <meta name=\"referrer\" content=\"no-referrer\">\n<title>{}</title>\n\
+        <style>{STYLE}</style>\n</head>\n<body>\n{content}\n</body>\n</html>\n",
+        escape_html(title)
+    )
+}
+
+/// formats the size in bytes with a binary unit (e.g. `1.5 MiB`)
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if size < 1024 {
+        return format!("{size} B");
+    }
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+/// formats the unix timestamp as `<time>` element, the content is the date in utc
+fn format_timestamp(timestamp: u64) -> String {
+    let date = humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(timestamp));
+    format!(
+        "<time datetime=\"{date}\">{}</time>",
+        date.to_string().replace('T', " ").replace('Z', " UTC")
+    )
+}
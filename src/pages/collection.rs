@@ -0,0 +1,68 @@
+use axum::{body::Body, http::Response};
+
+use super::{escape_html, format_size, format_timestamp, html_response, layout};
+use crate::{collection::Collection, filename::url_encode, list::StoredFile, state::State};
+
+/// the page of a collection with a table of its files and a button to download all files
+pub fn collection_page(
+    state: &State,
+    id: &str,
+    collection: &Collection,
+    files: &[&StoredFile],
+) -> Response<Body> {
+    let title = collection.title.as_deref().unwrap_or("Collection");
+    let size = files.iter().map(|file| file.size).sum::<u64>();
+    let details = format!(
+        "<dt>Files</dt><dd>{}</dd>\n<dt>Size</dt><dd>{}</dd>\n<dt>Created</dt><dd>{}</dd>\n<dt>Expires</dt><dd>{}</dd>",
+        files.len(),
+        format_size(size),
+        format_timestamp(collection.created_at),
+        collection
+            .ttl
+            .map(format_timestamp)
+            .unwrap_or_else(|| "never".to_string())
+    );
+
+    let mut rows = String::new();
+    for file in files {
+        let name = escape_html(&url_encode(&file.name));
+        let thumbnail = if state.config.thumbnails.applies_to(&file.file_data) {
+            format!("<img src=\"/t/{name}\" alt=\"\" loading=\"lazy\">")
+        } else {
+            String::new()
+        };
+        let page = if state.config.pages.file_pages {
+            "f"
+        } else {
+            "d"
+        };
+        rows += &format!(
+            "<tr><td class=\"thumbnail\">{thumbnail}</td><td><a href=\"/{page}/{name}\">{}</a></td><td class=\"size\">{}</td><td>{}</td><td><a href=\"/d/{name}\">Download</a></td></tr>\n",
+            escape_html(file.filename()),
+            format_size(file.size),
+            format_timestamp(file.uploaded_at)
+        );
+    }
+
+    let mut content = format!("<h1>{}</h1>\n<dl>\n{details}\n</dl>\n", escape_html(title));
+    if files.is_empty() {
+        content += "<p class=\"note\">There are no files in this collection.</p>";
+    } else {
+        content += &format!(
+            "<p><a class=\"button\" href=\"/c/{id}/archive\">Download all</a></p>\n<table>\n<tr><th></th><th>Name</th><th>Size</th><th>Uploaded</th><th></th></tr>\n{rows}</table>"
+        );
+    }
+    html_response(layout(title, &content))
+}
+
+/// the form to enter the password of a collection, `wrong` shows that the last password was wrong
+pub fn collection_password_page(id: &str, wrong: bool) -> Response<Body> {
+    let mut content = "<h1>Password required</h1>\n".to_string();
+    if wrong {
+        content += "<p class=\"note\">The password is wrong.</p>\n";
+    }
+    content += &format!(
+        "<form method=\"post\" action=\"/c/{id}/unlock\"><input type=\"password\" name=\"password\" placeholder=\"Password\" autofocus> <button>Open</button></form>"
+    );
+    html_response(layout("Password required", &content))
+}
@@ -0,0 +1,181 @@
+use axum::{
+    body::Body,
+    extract,
+    http::{HeaderMap, Response},
+};
+use tokio::{fs::File, io::AsyncReadExt};
+
+use super::{
+    escape_html, format_size, format_timestamp, highlight::highlight, html_response, layout,
+};
+use crate::{
+    content_type::{essence, is_text_type, DEFAULT_CONTENT_TYPE},
+    download::{authorize_download, blob_encryption},
+    error::UploadError,
+    file_data::FileData,
+    filename::{original_filename, url_encode},
+    state::State,
+    storage,
+};
+
+/// forwards the key in the url fragment of client side encrypted files to the download link
+const FORWARD_KEY_SCRIPT: &str = "<script>\
+if (location.hash) { for (const a of document.querySelectorAll(\"a.button\")) { a.href += location.hash; } }\
+</script>";
+
+/// the landing page of a file with its details, a download button and a preview
+pub async fn file_page(
+    extract::Path(filename): extract::Path<String>,
+    extract::State(state): extract::State<State>,
+    headers: HeaderMap,
+) -> Result<Response<Body>, UploadError> {
+    if !state.config.pages.file_pages {
+        return Err(UploadError::FileNotExists);
+    }
+    // the page is only shown to users that are allowed to download the file
+    let file_data = authorize_download(&state, &headers, &filename).await?;
+
+    let name = file_data.display_name(original_filename(&filename));
+    let link = format!("/d/{}", url_encode(&filename));
+    let content_type = file_data
+        .content_type
+        .as_deref()
+        .unwrap_or(DEFAULT_CONTENT_TYPE);
+    let size = match file_data.size {
+        Some(size) => size,
+        None => state.upload_directory.join(&filename).metadata()?.len(),
+    };
+    let hash = file_data
+        .hash
+        .as_deref()
+        .or_else(|| filename.split_once('_').map(|(hash, _)| hash))
+        .unwrap_or_default();
+
+    let mut details = format!(
+        "<dt>Size</dt><dd>{} ({size} bytes)</dd>\n<dt>Type</dt><dd>{}</dd>\n",
+        format_size(size),
+        escape_html(content_type)
+    );
+    if let Some(uploaded_at) = file_data.uploaded_at {
+        details += &format!(
+            "<dt>Uploaded</dt><dd>{}</dd>\n",
+            format_timestamp(uploaded_at)
+        );
+    }
+    if let Some(tags) = &file_data.tags {
+        details += &format!("<dt>Tags</dt><dd>{}</dd>\n", escape_html(&tags.join(", ")));
+    }
+    for (key, value) in file_data.meta.iter().flatten() {
+        details += &format!(
+            "<dt>{}</dt><dd>{}</dd>\n",
+            escape_html(key),
+            escape_html(value)
+        );
+    }
+    if let Some(collection) = &file_data.collection {
+        details += &format!(
+            "<dt>Collection</dt><dd><a href=\"/c/{0}\">{0}</a></dd>\n",
+            escape_html(collection)
+        );
+    }
+    details += &format!(
+        "<dt>Expires</dt><dd>{}</dd>\n<dt>BLAKE3</dt><dd><code>{}</code></dd>",
+        file_data
+            .ttl
+            .map(format_timestamp)
+            .unwrap_or_else(|| "never".to_string()),
+        escape_html(hash)
+    );
+
+    // client side encrypted files can only be decrypted by the download page
+    let preview = if file_data.encrypted.unwrap_or_default() {
+        format!("<p class=\"note\">This file is end-to-end encrypted and gets decrypted in your browser while downloading.</p>\n{FORWARD_KEY_SCRIPT}")
+    } else {
+        preview(&state, &file_data, &filename, content_type, &link).await?
+    };
+
+    let content = format!(
+        "<h1>{}</h1>\n<dl>\n{details}\n</dl>\n<p><a class=\"button\" href=\"{}\">Download</a></p>\n{preview}",
+        escape_html(name),
+        escape_html(&link)
+    );
+    Ok(html_response(layout(name, &content)))
+}
+
+/// embeds the file into the page if the browser can display it
+async fn preview(
+    state: &State,
+    file_data: &FileData,
+    filename: &str,
+    content_type: &str,
+    link: &str,
+) -> Result<String, UploadError> {
+    let source = escape_html(&format!("{link}?inline=1"));
+    let essence = essence(content_type).to_ascii_lowercase();
+    let preview = match essence.split_once('/').unwrap_or_default().0 {
+        "image" => format!(
+            "<img src=\"{source}\" alt=\"{}\">",
+            escape_html(file_data.display_name(original_filename(filename)))
+        ),
+        "video" => format!("<video controls preload=\"metadata\" src=\"{source}\"></video>"),
+        "audio" => format!("<audio controls preload=\"metadata\" src=\"{source}\"></audio>"),
+        _ if essence == "application/pdf"
+            && state.config.content_type.may_be_inline(content_type) =>
+        {
+            format!("<iframe src=\"{source}\" title=\"PDF preview\"></iframe>")
+        }
+        _ if is_text_type(content_type) => {
+            text_preview(
+                state,
+                file_data,
+                filename,
+                state.config.pages.max_text_preview,
+            )
+            .await?
+        }
+        _ => return Ok(String::new()),
+    };
+    Ok(format!("<div class=\"preview\">\n{preview}\n</div>"))
+}
+
+/// shows the beginning of a text file, code gets highlighted
+async fn text_preview(
+    state: &State,
+    file_data: &FileData,
+    filename: &str,
+    limit: u64,
+) -> Result<String, UploadError> {
+    let file = File::open(state.upload_directory.join(filename)).await?;
+    let reader = storage::reader(
+        file,
+        file_data.compressed.unwrap_or_default(),
+        blob_encryption(state, file_data, filename)?,
+    )
+    .await?;
+    let mut content = Vec::new();
+    // read one byte more than the limit to know if the preview is truncated
+    reader.take(limit + 1).read_to_end(&mut content).await?;
+    let truncated = content.len() as u64 > limit;
+    content.truncate(limit as usize);
+    // don't show a replacement character for a character that got cut at the end
+    if let Err(e) = std::str::from_utf8(&content) {
+        if e.error_len().is_none() {
+            content.truncate(e.valid_up_to());
+        }
+    }
+
+    let mut preview = format!(
+        "<pre><code>{}</code></pre>",
+        highlight(
+            file_data.display_name(original_filename(filename)),
+            &String::from_utf8_lossy(&content)
+        )
+    );
+    if truncated {
+        preview += &format!(
+            "\n<p class=\"note\">Only the first {} are shown.</p>",
+            format_size(limit)
+        );
+    }
+    Ok(preview)
+}
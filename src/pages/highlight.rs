@@ -0,0 +1,409 @@
+use super::escape_html;
+
+/// the tokens of a language family the highlighter knows about
+struct Syntax {
+    keywords: &'static [&'static str],
+    case_insensitive: bool,
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+}
+
+const RUST: Syntax = Syntax {
+    keywords: &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+        "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+        "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait",
+        "true", "type", "unsafe", "use", "where", "while",
+    ],
+    case_insensitive: false,
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"'],
+};
+
+/// c, c++, java, javascript, typescript, go, c#, kotlin, swift, php and dart
+const C_LIKE: Syntax = Syntax {
+    keywords: &[
+        "abstract",
+        "async",
+        "await",
+        "break",
+        "case",
+        "catch",
+        "class",
+        "const",
+        "continue",
+        "default",
+        "defer",
+        "delete",
+        "do",
+        "else",
+        "enum",
+        "export",
+        "extends",
+        "false",
+        "final",
+        "finally",
+        "fn",
+        "for",
+        "fun",
+        "func",
+        "function",
+        "go",
+        "if",
+        "implements",
+        "import",
+        "in",
+        "instanceof",
+        "interface",
+        "let",
+        "namespace",
+        "new",
+        "null",
+        "nil",
+        "package",
+        "private",
+        "protected",
+        "public",
+        "return",
+        "static",
+        "struct",
+        "switch",
+        "this",
+        "throw",
+        "throws",
+        "true",
+        "try",
+        "type",
+        "typedef",
+        "typeof",
+        "union",
+        "using",
+        "val",
+        "var",
+        "void",
+        "while",
+        "yield",
+    ],
+    case_insensitive: false,
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\'', '`'],
+};
+
+const PYTHON: Syntax = Syntax {
+    keywords: &[
+        "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+        "elif", "else", "except", "False", "finally", "for", "from", "global", "if", "import",
+        "in", "is", "lambda", "None", "nonlocal", "not", "or", "pass", "raise", "return", "True",
+        "try", "while", "with", "yield",
+    ],
+    case_insensitive: false,
+    line_comments: &["#"],
+    block_comment: None,
+    quotes: &['"', '\''],
+};
+
+const RUBY: Syntax = Syntax {
+    keywords: &[
+        "begin", "break", "case", "class", "def", "do", "else", "elsif", "end", "ensure", "false",
+        "for", "if", "in", "module", "next", "nil", "not", "or", "and", "require", "rescue",
+        "return", "self", "then", "true", "unless", "until", "when", "while", "yield",
+    ],
+    case_insensitive: false,
+    line_comments: &["#"],
+    block_comment: None,
+    quotes: &['"', '\''],
+};
+
+const SHELL: Syntax = Syntax {
+    keywords: &[
+        "case", "do", "done", "echo", "elif", "else", "esac", "exit", "export", "fi", "for",
+        "function", "if", "in", "local", "return", "set", "then", "until", "while",
+    ],
+    case_insensitive: false,
+    line_comments: &["#"],
+    block_comment: None,
+    quotes: &['"', '\''],
+};
+
+const SQL: Syntax = Syntax {
+    keywords: &[
+        "add",
+        "all",
+        "alter",
+        "and",
+        "as",
+        "asc",
+        "by",
+        "case",
+        "create",
+        "delete",
+        "desc",
+        "distinct",
+        "drop",
+        "else",
+        "end",
+        "exists",
+        "from",
+        "group",
+        "having",
+        "in",
+        "index",
+        "insert",
+        "into",
+        "is",
+        "join",
+        "key",
+        "left",
+        "like",
+        "limit",
+        "not",
+        "null",
+        "on",
+        "or",
+        "order",
+        "primary",
+        "references",
+        "right",
+        "select",
+        "set",
+        "table",
+        "then",
+        "union",
+        "update",
+        "values",
+        "when",
+        "where",
+    ],
+    case_insensitive: true,
+    line_comments: &["--"],
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\''],
+};
+
+const LUA: Syntax = Syntax {
+    keywords: &[
+        "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "if", "in",
+        "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+    ],
+    case_insensitive: false,
+    line_comments: &["--"],
+    block_comment: None,
+    quotes: &['"', '\''],
+};
+
+/// yaml, toml, ini and other configuration files
+const CONFIG: Syntax = Syntax {
+    keywords: &["true", "false", "null", "yes", "no", "on", "off"],
+    case_insensitive: false,
+    line_comments: &["#", ";"],
+    block_comment: None,
+    quotes: &['"', '\''],
+};
+
+const JSON: Syntax = Syntax {
+    keywords: &["true", "false", "null"],
+    case_insensitive: false,
+    line_comments: &[],
+    block_comment: None,
+    quotes: &['"'],
+};
+
+/// html, xml and svg
+const MARKUP: Syntax = Syntax {
+    keywords: &[],
+    case_insensitive: false,
+    line_comments: &[],
+    block_comment: Some(("<!--", "-->")),
+    quotes: &['"', '\''],
+};
+
+const CSS: Syntax = Syntax {
+    keywords: &["important", "inherit", "initial", "none", "auto"],
+    case_insensitive: false,
+    line_comments: &[],
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\''],
+};
+
+/// chooses the syntax by the extension of the `filename`
+fn syntax(filename: &str) -> Option<&'static Syntax> {
+    let (_, extension) = filename.rsplit_once('.')?;
+    Some(match extension.to_ascii_lowercase().as_str() {
+        "rs" => &RUST,
+        "c" | "h" | "cc" | "cpp" | "hpp" | "cxx" | "java" | "js" | "mjs" | "cjs" | "jsx" | "ts"
+        | "tsx" | "go" | "cs" | "kt" | "kts" | "swift" | "php" | "dart" | "scala" | "zig" => {
+            &C_LIKE
+        }
+        "py" | "pyi" => &PYTHON,
+        "rb" => &RUBY,
+        "sh" | "bash" | "zsh" | "fish" => &SHELL,
+        "sql" => &SQL,
+        "lua" => &LUA,
+        "toml" | "yaml" | "yml" | "ini" | "conf" | "cfg" | "env" => &CONFIG,
+        "json" => &JSON,
+        "html" | "htm" | "xml" | "svg" | "xhtml" => &MARKUP,
+        "css" | "scss" => &CSS,
+        _ => return None,
+    })
+}
+
+fn span(class: &str, content: &str) -> String {
+    format!("<span class=\"{class}\">{}</span>", escape_html(content))
+}
+
+/// returns the escaped `code` with keywords, strings, numbers and comments wrapped into spans
+/// the language is guessed by the extension of the `filename`, unknown languages are only escaped
+pub fn highlight(filename: &str, code: &str) -> String {
+    let Some(syntax) = syntax(filename) else {
+        return escape_html(code);
+    };
+    let mut html = String::with_capacity(code.len() * 2);
+    let mut rest = code;
+    while let Some(c) = rest.chars().next() {
+        let end = if let Some((start, end)) = syntax
+            .block_comment
+            .filter(|(start, _)| rest.starts_with(start))
+        {
+            let end = rest[start.len()..]
+                .find(end)
+                .map(|position| start.len() + position + end.len())
+                .unwrap_or(rest.len());
+            html.push_str(&span("c", &rest[..end]));
+            end
+        } else if syntax
+            .line_comments
+            .iter()
+            .any(|comment| rest.starts_with(comment))
+        {
+            let end = rest.find('\n').unwrap_or(rest.len());
+            html.push_str(&span("c", &rest[..end]));
+            end
+        } else if syntax.quotes.contains(&c) {
+            // strings that aren't closed on the same line are most likely no strings (e.g. lifetimes)
+            match string_end(rest, c) {
+                Some(end) => {
+                    html.push_str(&span("s", &rest[..end]));
+                    end
+                }
+                None => {
+                    html.push_str(&escape_html(&rest[..c.len_utf8()]));
+                    c.len_utf8()
+                }
+            }
+        } else if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '.' && c != '_')
+                .unwrap_or(rest.len());
+            html.push_str(&span("n", &rest[..end]));
+            end
+        } else if c.is_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !c.is_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            let keyword = syntax.keywords.iter().any(|keyword| {
+                if syntax.case_insensitive {
+                    keyword.eq_ignore_ascii_case(word)
+                } else {
+                    *keyword == word
+                }
+            });
+            if keyword {
+                html.push_str(&span("k", word));
+            } else {
+                html.push_str(&escape_html(word));
+            }
+            end
+        } else {
+            html.push_str(&escape_html(&rest[..c.len_utf8()]));
+            c.len_utf8()
+        };
+        rest = &rest[end..];
+    }
+    html
+}
+
+/// returns the position after the closing `quote` of the string at the beginning of `code`
+fn string_end(code: &str, quote: char) -> Option<usize> {
+    let mut escaped = false;
+    for (position, c) in code.char_indices().skip(1) {
+        match c {
+            '\n' => return None,
+            '\\' if !escaped => escaped = true,
+            c if c == quote && !escaped => return Some(position + c.len_utf8()),
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_languages_are_only_escaped() {
+        assert_eq!(
+            highlight("notes.txt", "fn <b>\"x\" & 'y'</b>"),
+            "fn &lt;b&gt;&quot;x&quot; &amp; &#39;y&#39;&lt;/b&gt;"
+        );
+        assert_eq!(highlight("Makefile", "if 1"), "if 1");
+    }
+
+    #[test]
+    fn tokens_are_wrapped_into_spans() {
+        assert_eq!(
+            highlight("main.rs", "let x = 42; // answer"),
+            "<span class=\"k\">let</span> x = <span class=\"n\">42</span>; <span class=\"c\">// answer</span>"
+        );
+        assert_eq!(
+            highlight("a.py", "s = 'it\\'s'"),
+            "s = <span class=\"s\">&#39;it\\&#39;s&#39;</span>"
+        );
+        // identifiers that contain keywords aren't keywords
+        assert_eq!(highlight("a.rs", "letter"), "letter");
+    }
+
+    #[test]
+    fn content_of_tokens_is_escaped() {
+        assert_eq!(
+            highlight("a.js", "\"<script>\""),
+            "<span class=\"s\">&quot;&lt;script&gt;&quot;</span>"
+        );
+        assert_eq!(
+            highlight("a.html", "<!-- <b> -->"),
+            "<span class=\"c\">&lt;!-- &lt;b&gt; --&gt;</span>"
+        );
+    }
+
+    #[test]
+    fn unclosed_strings_and_comments() {
+        // lifetimes aren't strings
+        assert_eq!(highlight("a.rs", "&'a str"), "&amp;&#39;a str");
+        assert_eq!(
+            highlight("a.c", "x /* open"),
+            "x <span class=\"c\">/* open</span>"
+        );
+        assert_eq!(highlight("a.js", "\"line\nend\""), "&quot;line\nend&quot;");
+    }
+
+    #[test]
+    fn keywords_can_be_case_insensitive() {
+        assert_eq!(
+            highlight("query.SQL", "SELECT name"),
+            "<span class=\"k\">SELECT</span> name"
+        );
+        assert_eq!(highlight("a.py", "none"), "none");
+    }
+
+    #[test]
+    fn multibyte_characters_are_kept() {
+        assert_eq!(
+            highlight("a.rs", "let ä = \"ö\"; // ü"),
+            "<span class=\"k\">let</span> ä = <span class=\"s\">&quot;ö&quot;</span>; <span class=\"c\">// ü</span>"
+        );
+    }
+}
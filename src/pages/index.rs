@@ -0,0 +1,129 @@
+use axum::{
+    body::Body,
+    extract,
+    http::{HeaderMap, Response},
+};
+
+use super::{escape_html, format_size, format_timestamp, html_response, layout};
+use crate::{
+    auth::AuthRequest,
+    auth_helper::authorize_by_headers,
+    error::UploadError,
+    filename::url_encode,
+    list::{query_files, stored_files, ListQuery, SortField, SortOrder},
+    state::State,
+};
+
+/// the columns that can be sorted by, with their label and their name in the query
+const COLUMNS: [(&str, SortField, &str); 4] = [
+    ("Name", SortField::Name, "name"),
+    ("Size", SortField::Size, "size"),
+    ("Uploaded", SortField::UploadedAt, "uploaded_at"),
+    ("Expires", SortField::ExpiresAt, "expires_at"),
+];
+
+/// returns the query string with the `changes` applied, parameters that are changed to `None` get removed
+/// the values must not need percent encoding
+fn query_with(raw_query: Option<&str>, changes: &[(&str, Option<&str>)]) -> String {
+    let mut parameters = raw_query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|parameter| {
+            let name = parameter.split('=').next().unwrap_or_default();
+            !name.is_empty() && !changes.iter().any(|(changed, _)| *changed == name)
+        })
+        .map(|parameter| parameter.to_string())
+        .collect::<Vec<String>>();
+    for (name, value) in changes {
+        if let Some(value) = value {
+            parameters.push(format!("{name}={value}"));
+        }
+    }
+    format!("?{}", parameters.join("&"))
+}
+
+/// a browsable list of the files the user is allowed to see, it accepts the query parameters of `/files`
+pub async fn index_page(
+    extract::State(state): extract::State<State>,
+    extract::Query(query): extract::Query<ListQuery>,
+    extract::RawQuery(raw_query): extract::RawQuery,
+    headers: HeaderMap,
+) -> Result<Response<Body>, UploadError> {
+    if !state.config.pages.index {
+        return Err(UploadError::FileNotExists);
+    }
+    let authorization = authorize_by_headers(&state, &headers, AuthRequest::List)?;
+    let files = stored_files(&state).await?;
+    let (page, next_cursor) = query_files(&files, &authorization, &query)?;
+    let raw_query = raw_query.as_deref();
+
+    let mut header = String::new();
+    for (label, field, name) in COLUMNS {
+        // clicking the sorted column again reverses the order
+        let (order, arrow) = match (query.sort == field, query.order) {
+            (true, SortOrder::Asc) => ("desc", " &#9650;"),
+            (true, SortOrder::Desc) => ("asc", " &#9660;"),
+            (false, _) => ("asc", ""),
+        };
+        let href = query_with(
+            raw_query,
+            &[
+                ("sort", Some(name)),
+                ("order", Some(order)),
+                ("cursor", None),
+            ],
+        );
+        header += &format!(
+            "<th><a href=\"{}\">{label}</a>{arrow}</th>",
+            escape_html(&href)
+        );
+    }
+    if authorization.admin {
+        header += "<th>Uploader</th>";
+    }
+
+    // the files link to their pages if they are enabled, to the downloads otherwise
+    let link = if state.config.pages.file_pages {
+        "f"
+    } else {
+        "d"
+    };
+    let mut rows = String::new();
+    for file in &page {
+        rows += &format!(
+            "<tr><td><a href=\"/{link}/{}\">{}</a></td><td class=\"size\">{}</td><td>{}</td><td>{}</td>",
+            escape_html(&url_encode(&file.name)),
+            escape_html(file.filename()),
+            format_size(file.size),
+            format_timestamp(file.uploaded_at),
+            file.file_data
+                .ttl
+                .map(format_timestamp)
+                .unwrap_or_else(|| "never".to_string())
+        );
+        if authorization.admin {
+            rows += &format!(
+                "<td>{}</td>",
+                escape_html(file.file_data.uploader.as_deref().unwrap_or_default())
+            );
+        }
+        rows += "</tr>\n";
+    }
+
+    let mut content = format!(
+        "<h1>Files</h1>\n<form method=\"get\"><input name=\"prefix\" value=\"{}\" placeholder=\"Filename prefix\"> <button>Filter</button></form>\n",
+        escape_html(query.prefix.as_deref().unwrap_or_default())
+    );
+    if page.is_empty() {
+        content += "<p class=\"note\">There are no files.</p>";
+    } else {
+        content += &format!("<table>\n<tr>{header}</tr>\n{rows}</table>");
+    }
+    if let Some(cursor) = next_cursor {
+        content += &format!(
+            "\n<p><a href=\"{}\">Next page</a></p>",
+            escape_html(&query_with(raw_query, &[("cursor", Some(&cursor))]))
+        );
+    }
+    Ok(html_response(layout("Files", &content)))
+}
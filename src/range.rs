@@ -0,0 +1,139 @@
+use axum::http::{header, HeaderMap};
+
+use crate::error::UploadError;
+
+/// a range of bytes, `end` is inclusive
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// the value of the `Content-Range` header of the response
+    pub fn content_range(&self, size: u64) -> String {
+        format!("bytes {}-{}/{size}", self.start, self.end)
+    }
+}
+
+/// parses the `Range` header of a request for a file with `size` bytes
+/// only single byte ranges (`bytes=0-99`, `bytes=100-` or `bytes=-100`) are supported,
+/// other ranges return `Ok(None)` and the whole file should be responded
+/// returns `Err(UploadError::RangeNotSatisfiable)` if the range is outside of the file
+pub fn parse_range(headers: &HeaderMap, size: u64) -> Result<Option<ByteRange>, UploadError> {
+    let Some(range) = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().strip_prefix("bytes="))
+    else {
+        return Ok(None);
+    };
+    if range.contains(',') {
+        return Ok(None);
+    }
+    let Some((start, end)) = range.split_once('-') else {
+        return Ok(None);
+    };
+    let (start, end) = (start.trim(), end.trim());
+
+    let range = if start.is_empty() {
+        // a suffix range contains the last `end` bytes
+        let Ok(suffix) = end.parse::<u64>() else {
+            return Ok(None);
+        };
+        if suffix == 0 || size == 0 {
+            return Err(UploadError::RangeNotSatisfiable(size));
+        }
+        ByteRange {
+            start: size.saturating_sub(suffix),
+            end: size - 1,
+        }
+    } else {
+        let Ok(start) = start.parse::<u64>() else {
+            return Ok(None);
+        };
+        let end = if end.is_empty() {
+            size.saturating_sub(1)
+        } else {
+            let Ok(end) = end.parse::<u64>() else {
+                return Ok(None);
+            };
+            if end < start {
+                return Ok(None);
+            }
+            end.min(size.saturating_sub(1))
+        };
+        if start >= size {
+            return Err(UploadError::RangeNotSatisfiable(size));
+        }
+        ByteRange { start, end }
+    };
+    Ok(Some(range))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn range(value: &str, size: u64) -> Result<Option<(u64, u64)>, UploadError> {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, HeaderValue::from_str(value).unwrap());
+        parse_range(&headers, size).map(|range| range.map(|range| (range.start, range.end)))
+    }
+
+    #[test]
+    fn single_ranges_are_parsed() {
+        assert_eq!(range("bytes=0-99", 1000).unwrap(), Some((0, 99)));
+        assert_eq!(range("bytes=100-", 1000).unwrap(), Some((100, 999)));
+        assert_eq!(range("bytes=-100", 1000).unwrap(), Some((900, 999)));
+        assert_eq!(range(" bytes= 5 - 5 ", 1000).unwrap(), Some((5, 5)));
+    }
+
+    #[test]
+    fn ranges_are_clamped_to_the_file() {
+        assert_eq!(range("bytes=900-2000", 1000).unwrap(), Some((900, 999)));
+        assert_eq!(range("bytes=-2000", 1000).unwrap(), Some((0, 999)));
+    }
+
+    #[test]
+    fn unsupported_ranges_return_the_whole_file() {
+        assert!(parse_range(&HeaderMap::new(), 1000).unwrap().is_none());
+        for value in [
+            "bytes=0-1,5-6",
+            "items=0-1",
+            "bytes=a-b",
+            "bytes=5",
+            "bytes=10-5",
+            "bytes=-x",
+        ] {
+            assert!(range(value, 1000).unwrap().is_none(), "{value} was parsed");
+        }
+    }
+
+    #[test]
+    fn ranges_outside_of_the_file_are_not_satisfiable() {
+        for (value, size) in [
+            ("bytes=1000-", 1000),
+            ("bytes=-0", 1000),
+            ("bytes=0-", 0),
+            ("bytes=-5", 0),
+        ] {
+            assert!(
+                matches!(range(value, size), Err(UploadError::RangeNotSatisfiable(s)) if s == size),
+                "{value} was satisfiable"
+            );
+        }
+    }
+
+    #[test]
+    fn content_range_contains_the_size() {
+        let range = ByteRange { start: 0, end: 99 };
+        assert_eq!(range.len(), 100);
+        assert_eq!(range.content_range(1000), "bytes 0-99/1000");
+    }
+}
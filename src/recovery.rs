@@ -0,0 +1,209 @@
+use std::{path::Path, time::Duration};
+
+use blake3::Hasher;
+use serde::Deserialize;
+use tokio::{
+    fs::{create_dir_all, read_dir, remove_file, File},
+    io::{self, AsyncReadExt},
+    time::sleep,
+};
+use tracing::{error, info, info_span, warn, Instrument};
+
+use crate::{
+    download::blob_encryption,
+    error::UploadError,
+    file_data::deserialize_optional_duration,
+    list::{stored_files, StoredFile},
+    state::State,
+    storage::{self, move_file},
+    thumbnail::clean_up_thumbnails,
+};
+
+#[derive(Clone, Copy, Deserialize, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OrphanAction {
+    /// orphans are deleted
+    #[default]
+    Delete,
+    /// orphans are moved into the quarantine directory
+    Quarantine,
+    /// orphans are only reported
+    Report,
+}
+
+#[derive(Clone, Deserialize, Default)]
+#[serde(default)]
+pub struct RecoveryConfig {
+    /// What happens with unfinished uploads and file data without a stored file at startup
+    pub orphans: OrphanAction,
+    /// The time between two scrubs that compare the stored files with their blake3 hash, no scrubs if it isn't set
+    #[serde(deserialize_with = "deserialize_optional_duration")]
+    pub scrub_interval: Option<u64>,
+}
+
+#[derive(Default)]
+struct RecoveryReport {
+    unfinished_uploads: usize,
+    data_without_file: usize,
+    files_without_data: usize,
+}
+
+/// deletes, quarantines or only reports the orphan at `path`, `subdirectory` is the directory
+/// in the quarantine it's moved to
+async fn handle_orphan(
+    state: &State,
+    quarantine_directory: &Path,
+    path: &Path,
+    subdirectory: &str,
+) -> io::Result<()> {
+    match state.config.recovery.orphans {
+        OrphanAction::Delete => remove_file(path).await,
+        OrphanAction::Quarantine => {
+            let directory = quarantine_directory.join(subdirectory);
+            create_dir_all(&directory).await?;
+            move_file(path, &directory.join(path.file_name().unwrap_or_default())).await
+        }
+        OrphanAction::Report => Ok(()),
+    }
+}
+
+async fn recover_upload_directory(
+    state: &State,
+    quarantine_directory: &Path,
+    report: &mut RecoveryReport,
+) -> io::Result<()> {
+    let mut content = read_dir(&state.upload_directory).await?;
+    while let Some(file) = content.next_entry().await? {
+        let name = file.file_name().to_string_lossy().into_owned();
+        // files that weren't uploaded completely have a .upload suffix
+        if name.ends_with(".upload") {
+            report.unfinished_uploads += 1;
+            warn!(file = %name, "found an unfinished upload");
+            if let Err(e) = handle_orphan(state, quarantine_directory, &file.path(), "upload").await
+            {
+                error!(file = %name, error = ?e, "error while recovering the unfinished upload");
+            }
+        } else if !state.data_directory.join(&name).exists() {
+            // files without file data are valid, their file data was empty when they were uploaded
+            report.files_without_data += 1;
+        }
+    }
+    Ok(())
+}
+
+async fn recover_data_directory(
+    state: &State,
+    quarantine_directory: &Path,
+    report: &mut RecoveryReport,
+) -> io::Result<()> {
+    let mut content = read_dir(&state.data_directory).await?;
+    while let Some(file) = content.next_entry().await? {
+        let name = file.file_name().to_string_lossy().into_owned();
+        if state.upload_directory.join(file.file_name()).exists() {
+            continue;
+        }
+        report.data_without_file += 1;
+        warn!(file = %name, "found file data without a stored file");
+        if let Err(e) = handle_orphan(state, quarantine_directory, &file.path(), "data").await {
+            error!(file = %name, error = ?e, "error while recovering the file data");
+        }
+    }
+    Ok(())
+}
+
+/// finds unfinished uploads, file data without a stored file and stored files without file data,
+/// the orphans are deleted, quarantined or only reported depending on the config
+pub async fn recover(state: &State, quarantine_directory: &Path) {
+    let mut report = RecoveryReport::default();
+    if let Err(e) = recover_upload_directory(state, quarantine_directory, &mut report).await {
+        error!(error = ?e, "error while recovering the upload directory");
+    }
+    if let Err(e) = recover_data_directory(state, quarantine_directory, &mut report).await {
+        error!(error = ?e, "error while recovering the data directory");
+    }
+    // thumbnails can be generated again
+    if let Err(e) = clean_up_thumbnails(state).await {
+        error!(error = ?e, "error while cleaning up thumbnails");
+    }
+    let action = match state.config.recovery.orphans {
+        OrphanAction::Delete => "deleted",
+        OrphanAction::Quarantine => "quarantined",
+        OrphanAction::Report => "kept",
+    };
+    info!(
+        unfinished_uploads = report.unfinished_uploads,
+        data_without_file = report.data_without_file,
+        files_without_data = report.files_without_data,
+        orphans = action,
+        "recovery finished"
+    );
+}
+
+/// checks if the content of the stored file still has the blake3 `hash`
+async fn scrub_file(state: &State, file: &StoredFile, hash: &str) -> Result<bool, UploadError> {
+    let blob = File::open(state.upload_directory.join(&file.name)).await?;
+    let encryption = blob_encryption(state, &file.file_data, &file.name)?;
+    let mut hasher = Hasher::new();
+    let mut buffer = vec![0; 64 * 1024];
+    // the content can't be read (e.g. decrypted) if the blob is damaged
+    let read = async {
+        let compressed = file.file_data.compressed.unwrap_or_default();
+        let mut reader = storage::reader(blob, compressed, encryption).await?;
+        loop {
+            let read = reader.read(&mut buffer).await?;
+            if read == 0 {
+                return Ok::<(), io::Error>(());
+            }
+            hasher.update(&buffer[..read]);
+        }
+    };
+    if let Err(e) = read.await {
+        error!(file = %file.name, error = ?e, "error while reading the file for scrubbing");
+        return Ok(false);
+    }
+    Ok(hasher.finalize().to_hex().as_str() == hash)
+}
+
+async fn scrub(state: &State) -> Result<(), UploadError> {
+    let mut checked = 0;
+    let mut corrupted = 0;
+    for file in stored_files(state).await? {
+        // files of older versions don't have a hash
+        let Some(hash) = &file.file_data.hash else {
+            continue;
+        };
+        match scrub_file(state, &file, hash).await {
+            Ok(true) => checked += 1,
+            Ok(false) => {
+                checked += 1;
+                corrupted += 1;
+                error!(
+                    file = %file.name,
+                    "file is corrupted, its content doesn't match its blake3 hash"
+                );
+            }
+            // the file could have been removed in the meantime
+            Err(e) => error!(file = %file.name, error = ?e, "error while scrubbing the file"),
+        }
+    }
+    info!(checked, corrupted, "scrub finished");
+    Ok(())
+}
+
+pub fn start_scrubber(state: State) {
+    let Some(scrub_interval) = state.config.recovery.scrub_interval else {
+        return;
+    };
+    tokio::spawn(
+        async move {
+            info!("started scrubber");
+            loop {
+                sleep(Duration::from_secs(scrub_interval.max(1))).await;
+                if let Err(e) = scrub(&state).await {
+                    error!(error = ?e, "error while scrubbing the stored files");
+                }
+            }
+        }
+        .instrument(info_span!("scrubber")),
+    );
+}
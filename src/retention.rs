@@ -0,0 +1,174 @@
+use serde::{Deserialize, Deserializer};
+
+use crate::file_data::deserialize_duration;
+
+/// the header of upload responses containing the unix timestamp the file gets deleted at
+pub const EXPIRES_HEADER_NAME: &str = "X-Expires";
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// Limit the lifetime of files by their size, small files are kept longer than big ones
+    pub enabled: bool,
+    /// The lifetime of files of `max_size` bytes and bigger
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub min_age: u64,
+    /// The lifetime of empty files
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub max_age: u64,
+    /// The size in bytes files get the `min_age` at
+    pub max_size: u64,
+    /// The steepness of the curve between `max_age` and `min_age`, higher values shorten the lifetime of middle-sized files
+    #[serde(deserialize_with = "deserialize_exponent")]
+    pub exponent: f64,
+}
+
+/// negative exponents would give big files an infinite lifetime
+fn deserialize_exponent<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let exponent = f64::deserialize(deserializer)?;
+    if !exponent.is_finite() || exponent < 0.0 {
+        return Err(serde::de::Error::custom(format!(
+            "invalid exponent {exponent}, it must not be negative"
+        )));
+    }
+    Ok(exponent)
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_age: 30 * 24 * 60 * 60,
+            max_age: 365 * 24 * 60 * 60,
+            max_size: 512 * 1024 * 1024,
+            exponent: 3.0,
+        }
+    }
+}
+
+impl RetentionConfig {
+    /// the lifetime of a file of `size` bytes in seconds
+    /// `min_age + (max_age - min_age) * (1 - size / max_size) ^ exponent`
+    pub fn max_lifetime(&self, size: u64) -> u64 {
+        let ratio = (size as f64 / self.max_size.max(1) as f64).min(1.0);
+        let range = self.max_age.saturating_sub(self.min_age) as f64;
+        self.min_age
+            .saturating_add((range * (1.0 - ratio).powf(self.exponent)) as u64)
+    }
+
+    /// shortens the ttl of a file of `size` bytes uploaded at `uploaded_at` to its maximal lifetime,
+    /// files that don't have a ttl get one
+    pub fn apply(&self, ttl: &mut Option<u64>, size: u64, uploaded_at: u64) {
+        if !self.enabled {
+            return;
+        }
+        let max_ttl = uploaded_at.saturating_add(self.max_lifetime(size));
+        *ttl = Some(ttl.map_or(max_ttl, |ttl| ttl.min(max_ttl)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> RetentionConfig {
+        RetentionConfig {
+            enabled: true,
+            min_age: 100,
+            max_age: 1000,
+            max_size: 1000,
+            exponent: 1.0,
+        }
+    }
+
+    #[test]
+    fn lifetime_shrinks_with_size() {
+        let config = config();
+        assert_eq!(config.max_lifetime(0), 1000);
+        assert_eq!(config.max_lifetime(500), 550);
+        assert_eq!(config.max_lifetime(1000), 100);
+        assert_eq!(config.max_lifetime(u64::MAX), 100);
+    }
+
+    #[test]
+    fn exponent_shortens_middle_sized_files() {
+        let config = RetentionConfig {
+            exponent: 2.0,
+            ..config()
+        };
+        assert_eq!(config.max_lifetime(0), 1000);
+        assert_eq!(config.max_lifetime(500), 325);
+        assert_eq!(config.max_lifetime(1000), 100);
+    }
+
+    #[test]
+    fn zero_max_size_gives_min_age() {
+        let config = RetentionConfig {
+            max_size: 0,
+            ..config()
+        };
+        assert_eq!(config.max_lifetime(0), 1000);
+        assert_eq!(config.max_lifetime(1), 100);
+    }
+
+    #[test]
+    fn negative_exponents_are_rejected() {
+        assert!(toml::from_str::<RetentionConfig>("exponent = -1.0").is_err());
+        assert!(toml::from_str::<RetentionConfig>("exponent = nan").is_err());
+        let config = toml::from_str::<RetentionConfig>("exponent = 0.5").unwrap();
+        assert_eq!(config.exponent, 0.5);
+    }
+
+    #[test]
+    fn lifetime_doesnt_overflow() {
+        let long = RetentionConfig {
+            min_age: u64::MAX - 1,
+            max_age: u64::MAX,
+            ..config()
+        };
+        assert_eq!(long.max_lifetime(0), u64::MAX);
+        let negative = RetentionConfig {
+            exponent: -1.0,
+            ..config()
+        };
+        assert_eq!(negative.max_lifetime(1000), u64::MAX);
+    }
+
+    #[test]
+    fn apply_sets_and_shortens_the_ttl() {
+        let config = config();
+        let mut ttl = None;
+        config.apply(&mut ttl, 1000, 5000);
+        assert_eq!(ttl, Some(5100));
+
+        let mut ttl = Some(9000);
+        config.apply(&mut ttl, 1000, 5000);
+        assert_eq!(ttl, Some(5100));
+
+        // shorter ttls are kept
+        let mut ttl = Some(5050);
+        config.apply(&mut ttl, 1000, 5000);
+        assert_eq!(ttl, Some(5050));
+    }
+
+    #[test]
+    fn apply_counts_from_the_upload() {
+        let mut ttl = None;
+        config().apply(&mut ttl, 0, u64::MAX - 10);
+        assert_eq!(ttl, Some(u64::MAX));
+    }
+
+    #[test]
+    fn apply_does_nothing_when_disabled() {
+        let config = RetentionConfig {
+            enabled: false,
+            ..config()
+        };
+        let mut ttl = None;
+        config.apply(&mut ttl, 1000, 5000);
+        assert_eq!(ttl, None);
+        let mut ttl = Some(9000);
+        config.apply(&mut ttl, 1000, 5000);
+        assert_eq!(ttl, Some(9000));
+    }
+}
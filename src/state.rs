@@ -1,21 +1,53 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::Arc};
 
-use tokio::fs::{create_dir, read_dir, remove_file};
+use tokio::{
+    fs::{create_dir, create_dir_all},
+    sync::{Mutex, Notify},
+};
 
-use crate::auth::AuthConfig;
+use crate::{
+    audit::AuditLog, auth::AuthConfig, config::Config, encryption::Encryption, metrics::Metrics,
+    trash::TRASH_SUBDIRECTORIES, ttl_killer::ExpiryQueue, webhook::WebhookQueue,
+};
 
 #[derive(Clone)]
 pub struct State {
     pub auth_config: AuthConfig,
+    pub config: Config,
     pub upload_directory: PathBuf,
     pub data_directory: PathBuf,
+    /// the directory the thumbnails of images are stored in
+    pub thumbnail_directory: PathBuf,
+    /// the directory the settings of the collections are stored in
+    pub collection_directory: PathBuf,
+    /// the directory removed files are moved into if the trash is enabled
+    pub trash_directory: PathBuf,
+    /// the master keys if encryption is enabled
+    pub encryption: Option<Arc<Encryption>>,
+    /// the upcoming expiries of files and collections
+    pub expiry_queue: ExpiryQueue,
+    /// wakes the evictor up to check the disk usage
+    pub eviction_check: Arc<Notify>,
+    /// the counters and histograms served at `/metrics`
+    pub metrics: Arc<Metrics>,
+    /// the audit log if it's enabled
+    pub audit: Option<AuditLog>,
+    /// the deliveries of the webhooks if there are webhooks
+    pub webhooks: Option<WebhookQueue>,
+    /// serializes the changes of file data and the removal of stored files,
+    /// so changes don't get lost and a removed file doesn't get its file data back
+    pub file_data_lock: Arc<Mutex<()>>,
 }
 
 impl State {
     pub async fn new(
         auth_config: AuthConfig,
+        config: Config,
         upload_directory: PathBuf,
         data_directory: PathBuf,
+        thumbnail_directory: PathBuf,
+        collection_directory: PathBuf,
+        trash_directory: PathBuf,
     ) -> Self {
         // check if the upload directory exists and create it if not
         if !upload_directory.exists() {
@@ -30,40 +62,67 @@ impl State {
                 .expect("Error while creating data directory");
         }
 
-        // cleaning up files that wasn't uploaded completely (have a .upload suffix)
-        let mut content = read_dir(&upload_directory)
-            .await
-            .expect("Error while listing files of upload directory");
-        while let Ok(Some(file)) = content.next_entry().await {
-            let filename = file.file_name();
-            let filename = filename.to_str().expect("Error while transforming file name into string while listing files of upload directory");
-            if filename.ends_with(".upload") {
-                println!("INFO: Cleaning up file {filename}");
-                remove_file(file.path())
+        if !thumbnail_directory.exists() {
+            create_dir(&thumbnail_directory)
+                .await
+                .expect("Error while creating thumbnail directory");
+        }
+
+        if !collection_directory.exists() {
+            create_dir(&collection_directory)
+                .await
+                .expect("Error while creating collection directory");
+        }
+
+        if config.trash.enabled {
+            for subdirectory in TRASH_SUBDIRECTORIES {
+                create_dir_all(trash_directory.join(subdirectory))
                     .await
-                    .expect("Error while removing file that was not uploaded completely");
+                    .expect("Error while creating trash directory");
             }
         }
 
-        // cleaning up data files that doesn't belong to an existing file
-        let mut content = read_dir(&data_directory)
-            .await
-            .expect("Error while listing files of data directory");
-        while let Ok(Some(file)) = content.next_entry().await {
-            let filename = file.file_name();
-            let upload_file_path = upload_directory.join(&filename);
-            if !upload_file_path.exists() {
-                println!("INFO: Cleaning up data file {}", filename.to_str().expect("Error while transforming file name into string while listing files of data directory"));
-                remove_file(file.path()).await.expect(
-                    "Error while removing data file that doesn't have a belonging upload file",
-                );
+        let encryption = Encryption::load(&config.encryption).map(Arc::new);
+        if let Some(encryption) = &encryption {
+            if config.encryption.rewrap_on_startup {
+                let trash_directories = TRASH_SUBDIRECTORIES
+                    .map(|subdirectory| trash_directory.join(subdirectory))
+                    .into_iter()
+                    .filter(|directory| directory.exists());
+                for directory in [
+                    upload_directory.clone(),
+                    data_directory.clone(),
+                    thumbnail_directory.clone(),
+                    collection_directory.clone(),
+                ]
+                .into_iter()
+                .chain(trash_directories)
+                {
+                    encryption
+                        .rewrap_directory(&directory)
+                        .await
+                        .expect("Error while wrapping data keys with the current master key");
+                }
             }
         }
 
+        let audit = AuditLog::open(&config.audit).await;
+        let webhooks = WebhookQueue::open(&config.webhooks).await;
         Self {
             auth_config,
+            config,
             upload_directory,
             data_directory,
+            thumbnail_directory,
+            collection_directory,
+            trash_directory,
+            encryption,
+            expiry_queue: ExpiryQueue::default(),
+            eviction_check: Arc::new(Notify::new()),
+            metrics: Arc::new(Metrics::default()),
+            audit,
+            webhooks,
+            file_data_lock: Arc::new(Mutex::new(())),
         }
     }
 }
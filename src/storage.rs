@@ -0,0 +1,117 @@
+use async_compression::{
+    tokio::{bufread::ZstdDecoder, write::ZstdEncoder},
+    Level,
+};
+use std::{
+    ffi::OsString,
+    io::{self, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use tokio::{
+    fs::{copy, remove_file, rename, File},
+    io::{AsyncRead, AsyncSeekExt, AsyncWrite, BufReader},
+};
+
+use crate::encryption::Encryption;
+
+/// a writer that writes the content of an uploaded file into its stored blob
+pub type BlobWriter = Box<dyn AsyncWrite + Send + Unpin>;
+/// a reader that reads the original content of a file from its stored blob
+pub type BlobReader = Box<dyn AsyncRead + Send + Unpin>;
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// Compress stored files with zstd if the upload doesn't decide it by the `File-Data-Compress` header
+    pub compress: bool,
+    /// The zstd compression level (1-22)
+    pub compression_level: i32,
+    /// Remove the exif, xmp and iptc metadata (e.g. the gps location) of uploaded jpeg images
+    pub strip_exif: bool,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            compress: false,
+            compression_level: 3,
+            strip_exif: false,
+        }
+    }
+}
+
+impl StorageConfig {
+    /// wraps the blob `file` so everything written to the writer gets compressed if `compress` is true
+    /// and encrypted if `encryption` is set
+    /// the writer must be shut down to finish the blob
+    pub fn writer(
+        &self,
+        file: File,
+        compress: bool,
+        encryption: Option<&Encryption>,
+    ) -> io::Result<BlobWriter> {
+        let file: BlobWriter = match encryption {
+            Some(encryption) => Box::new(encryption.writer(file)?),
+            None => Box::new(file),
+        };
+        Ok(if compress {
+            Box::new(ZstdEncoder::with_quality(
+                file,
+                Level::Precise(self.compression_level),
+            ))
+        } else {
+            file
+        })
+    }
+}
+
+/// wraps the blob `file` so the reader returns the stored (maybe compressed) content starting at `offset`
+/// the content gets decrypted if `encryption` is set
+pub async fn raw_reader(
+    mut file: File,
+    encryption: Option<&Encryption>,
+    offset: u64,
+) -> io::Result<BlobReader> {
+    match encryption {
+        Some(encryption) => encryption.reader(file, offset).await,
+        None => {
+            if offset > 0 {
+                file.seek(SeekFrom::Start(offset)).await?;
+            }
+            Ok(Box::new(file))
+        }
+    }
+}
+
+/// wraps the blob `file` so the reader returns the original content of the file
+pub async fn reader(
+    file: File,
+    compressed: bool,
+    encryption: Option<&Encryption>,
+) -> io::Result<BlobReader> {
+    let reader = raw_reader(file, encryption, 0).await?;
+    Ok(if compressed {
+        Box::new(ZstdDecoder::new(BufReader::new(reader)))
+    } else {
+        reader
+    })
+}
+
+/// moves the file to `to`, the file is copied if `to` is on another file system (e.g. another docker volume)
+pub async fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match rename(from, to).await {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            // the copy gets its final name when it's complete, so a crash can't leave a partial file behind
+            let mut temporary = OsString::from(to.as_os_str());
+            temporary.push(".moving");
+            let temporary = PathBuf::from(temporary);
+            copy(from, &temporary).await?;
+            File::open(&temporary).await?.sync_all().await?;
+            rename(&temporary, to).await?;
+            remove_file(from).await
+        }
+        result => result,
+    }
+}
@@ -0,0 +1,263 @@
+use std::{io::Cursor, path::PathBuf};
+
+use axum::{
+    body::Body,
+    extract,
+    http::{HeaderMap, Response, StatusCode},
+};
+use image::{
+    codecs::{jpeg::JpegEncoder, webp::WebPEncoder},
+    imageops::FilterType,
+    DynamicImage, ImageDecoder, ImageReader, ImageResult, Limits,
+};
+use serde::Deserialize;
+use tokio::{
+    fs::{read, read_dir, remove_file, write, File},
+    io::{self, AsyncReadExt},
+    task::spawn_blocking,
+};
+use tracing::{error, info, Instrument};
+
+use crate::{
+    content_type::essence,
+    download::{authorize_download, blob_encryption},
+    encryption::is_encrypted,
+    error::UploadError,
+    file_data::FileData,
+    state::State,
+    storage,
+};
+
+/// the content types thumbnails can be generated for
+const SUPPORTED_TYPES: [&str; 5] = [
+    "image/jpeg",
+    "image/png",
+    "image/gif",
+    "image/webp",
+    "image/bmp",
+];
+
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThumbnailFormat {
+    /// lossless webp
+    Webp,
+    Jpeg,
+}
+
+impl ThumbnailFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Webp => "webp",
+            Self::Jpeg => "jpg",
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            Self::Webp => "image/webp",
+            Self::Jpeg => "image/jpeg",
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct ThumbnailConfig {
+    /// Generate thumbnails of uploaded images
+    pub enabled: bool,
+    /// The maximal width and height of the thumbnails in pixels, one thumbnail is generated for every size
+    pub sizes: Vec<u32>,
+    pub format: ThumbnailFormat,
+    /// The quality of jpeg thumbnails (1-100)
+    pub quality: u8,
+    /// No thumbnails are generated for images bigger than this amount of bytes
+    pub max_image_size: u64,
+}
+
+impl Default for ThumbnailConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sizes: vec![256],
+            format: ThumbnailFormat::Webp,
+            quality: 80,
+            max_image_size: 64 * 1024 * 1024,
+        }
+    }
+}
+
+impl ThumbnailConfig {
+    /// checks if thumbnails should be generated for the file
+    pub fn applies_to(&self, file_data: &FileData) -> bool {
+        self.enabled
+            && !self.sizes.is_empty()
+            && !file_data.encrypted.unwrap_or_default()
+            && file_data.size.unwrap_or_default() <= self.max_image_size
+            && file_data
+                .content_type
+                .as_deref()
+                .is_some_and(|content_type| {
+                    SUPPORTED_TYPES.contains(&essence(content_type).to_ascii_lowercase().as_str())
+                })
+    }
+}
+
+/// the path of the thumbnail of the file `filename` with the `size`
+fn thumbnail_path(state: &State, filename: &str, size: u32) -> PathBuf {
+    let format = state.config.thumbnails.format;
+    state
+        .thumbnail_directory
+        .join(format!("{filename}.{size}.{}", format.extension()))
+}
+
+/// returns the name of the file the thumbnail belongs to (`{filename}.{size}.{extension}`)
+fn thumbnail_owner(thumbnail_name: &str) -> Option<&str> {
+    let mut parts = thumbnail_name.rsplitn(3, '.');
+    let (_extension, size) = (parts.next()?, parts.next()?);
+    size.parse::<u32>().ok()?;
+    parts.next()
+}
+
+/// decodes the image, applies its exif orientation and encodes a thumbnail for every size
+fn render(config: &ThumbnailConfig, content: Vec<u8>) -> ImageResult<Vec<(u32, Vec<u8>)>> {
+    let mut reader = ImageReader::new(Cursor::new(content)).with_guessed_format()?;
+    // the default limits prevent decompression bombs from allocating too much memory
+    reader.limits(Limits::default());
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+
+    let mut thumbnails = Vec::with_capacity(config.sizes.len());
+    for &size in &config.sizes {
+        // small images are not scaled up
+        let thumbnail = if image.width() > size || image.height() > size {
+            image.resize(size, size, FilterType::Triangle)
+        } else {
+            image.clone()
+        };
+        // the thumbnail is encoded from the pixels only, so it doesn't contain any metadata
+        let mut encoded = Vec::new();
+        match config.format {
+            ThumbnailFormat::Webp => DynamicImage::ImageRgba8(thumbnail.to_rgba8())
+                .write_with_encoder(WebPEncoder::new_lossless(&mut encoded))?,
+            ThumbnailFormat::Jpeg => {
+                DynamicImage::ImageRgb8(thumbnail.to_rgb8()).write_with_encoder(
+                    JpegEncoder::new_with_quality(&mut encoded, config.quality.clamp(1, 100)),
+                )?
+            }
+        }
+        thumbnails.push((size, encoded));
+    }
+    Ok(thumbnails)
+}
+
+async fn generate(state: &State, filename: &str, file_data: &FileData) -> Result<(), UploadError> {
+    let file = File::open(state.upload_directory.join(filename)).await?;
+    let mut reader = storage::reader(
+        file,
+        file_data.compressed.unwrap_or_default(),
+        blob_encryption(state, file_data, filename)?,
+    )
+    .await?;
+    let mut content = Vec::new();
+    reader.read_to_end(&mut content).await?;
+
+    let config = state.config.thumbnails.clone();
+    let thumbnails = spawn_blocking(move || render(&config, content))
+        .await
+        .map_err(io::Error::other)?
+        .map_err(io::Error::other)?;
+    for (size, mut thumbnail) in thumbnails {
+        if let Some(encryption) = &state.encryption {
+            thumbnail = encryption.encrypt_bytes(&thumbnail)?;
+        }
+        write(thumbnail_path(state, filename, size), thumbnail).await?;
+    }
+
+    // the file could have been removed while the thumbnails were generated
+    if !state.upload_directory.join(filename).exists() {
+        remove_thumbnails(state, filename).await;
+    }
+    Ok(())
+}
+
+/// generates the thumbnails of an uploaded image in the background
+pub fn start_generating(state: State, filename: String, file_data: FileData) {
+    tokio::spawn(
+        async move {
+            if let Err(e) = generate(&state, &filename, &file_data).await {
+                error!(file = %filename, error = ?e, "error while generating thumbnails");
+            }
+        }
+        .in_current_span(),
+    );
+}
+
+/// removes the thumbnails of the file, they may not exist
+pub async fn remove_thumbnails(state: &State, filename: &str) {
+    for &size in &state.config.thumbnails.sizes {
+        let _ = remove_file(thumbnail_path(state, filename, size)).await;
+    }
+}
+
+/// removes thumbnails that don't belong to an existing file
+pub async fn clean_up_thumbnails(state: &State) -> io::Result<()> {
+    let mut content = read_dir(&state.thumbnail_directory).await?;
+    while let Some(file) = content.next_entry().await? {
+        let name = file.file_name();
+        let owner = name.to_str().and_then(thumbnail_owner);
+        if !owner.is_some_and(|owner| state.upload_directory.join(owner).exists()) {
+            info!(thumbnail = %name.to_string_lossy(), "cleaning up thumbnail");
+            remove_file(file.path()).await?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct ThumbnailQuery {
+    /// one of the configured sizes, the first one is used by default
+    size: Option<u32>,
+}
+
+pub async fn thumbnail(
+    extract::Path(filename): extract::Path<String>,
+    extract::State(state): extract::State<State>,
+    extract::Query(query): extract::Query<ThumbnailQuery>,
+    headers: HeaderMap,
+) -> Result<Response<Body>, UploadError> {
+    let config = &state.config.thumbnails;
+    let size = match query.size {
+        Some(size) if !config.sizes.contains(&size) => {
+            return Err(UploadError::InvalidQueryArgument("size".to_string()))
+        }
+        Some(size) => size,
+        None => *config.sizes.first().ok_or(UploadError::FileNotExists)?,
+    };
+    // the thumbnail may be downloaded by everyone who may download the file
+    authorize_download(&state, &headers, &filename).await?;
+
+    // the thumbnail doesn't exist if the file is no image or if it's still being generated
+    let path = thumbnail_path(&state, &filename, size);
+    if !path.exists() {
+        return Err(UploadError::FileNotExists);
+    }
+    let mut content = read(path).await?;
+    if is_encrypted(&content) {
+        let encryption = state
+            .encryption
+            .as_deref()
+            .ok_or(UploadError::InternalServerError)?;
+        content = encryption.decrypt_bytes(&content)?;
+    }
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", config.format.content_type())
+        .header("Content-Length", content.len().to_string())
+        .header("X-Content-Type-Options", "nosniff")
+        .body(Body::from(content))
+        .unwrap())
+}
@@ -0,0 +1,351 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use axum::{
+    extract,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{read_dir, remove_file},
+    io,
+    time::sleep,
+};
+use tracing::{error, info, info_span, Instrument};
+
+use crate::{
+    audit::{self, AuditAction, AuditEvent},
+    auth_helper::authorize_admin,
+    error::UploadError,
+    file_data::{current_unix_timestamp, deserialize_duration, read_json, write_json, FileData},
+    filename::{is_valid_stored_filename, original_filename, url_encode},
+    list::{FileEntry, StoredFile},
+    state::State,
+    storage::move_file,
+    thumbnail::{self, remove_thumbnails},
+    ttl_killer::Expiring,
+    webhook::{self, WebhookEvent},
+};
+
+/// the subdirectories of the trash directory, the blobs and file data are stored like in the
+/// upload and data directory, the entries contain when and why the files were removed
+pub const TRASH_SUBDIRECTORIES: [&str; 3] = ["upload", "data", "entries"];
+
+/// the purge pass runs at least this often
+const MAX_PURGE_INTERVAL: u64 = 10 * 60;
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct TrashConfig {
+    /// Move removed files into the trash instead of deleting them
+    pub enabled: bool,
+    /// The time removed files are kept in the trash until they are deleted permanently
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub retention: u64,
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            retention: 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+/// why and when a file was moved into the trash
+#[derive(Serialize, Deserialize)]
+struct TrashEntry {
+    deleted_at: u64,
+    reason: String,
+}
+
+fn upload_path(state: &State, name: &str) -> PathBuf {
+    state.trash_directory.join("upload").join(name)
+}
+
+fn data_path(state: &State, name: &str) -> PathBuf {
+    state.trash_directory.join("data").join(name)
+}
+
+fn entry_path(state: &State, name: &str) -> PathBuf {
+    state.trash_directory.join("entries").join(name)
+}
+
+async fn remove_if_exists(path: PathBuf) -> io::Result<()> {
+    match remove_file(path).await {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// moves the file if it exists, `true` if it was moved
+async fn move_if_exists(from: &Path, to: &Path) -> io::Result<bool> {
+    match move_file(from, to).await {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// the file data of the trashed file
+async fn trashed_file_data(state: &State, name: &str) -> Result<FileData, UploadError> {
+    Ok(
+        FileData::read_from(&data_path(state, name), state.encryption.as_deref())
+            .await?
+            .unwrap_or_default(),
+    )
+}
+
+/// deletes the trashed file permanently
+async fn purge(state: &State, name: &str) -> io::Result<()> {
+    remove_if_exists(upload_path(state, name)).await?;
+    remove_if_exists(data_path(state, name)).await?;
+    remove_if_exists(entry_path(state, name)).await
+}
+
+async fn delete_files(state: &State, name: &str) -> io::Result<()> {
+    remove_thumbnails(state, name).await;
+    remove_if_exists(state.data_directory.join(name)).await?;
+    remove_if_exists(state.upload_directory.join(name)).await
+}
+
+/// deletes a stored file with its file data and thumbnails permanently
+pub async fn delete_stored_file(state: &State, name: &str) -> io::Result<()> {
+    let _lock = state.file_data_lock.lock().await;
+    delete_files(state, name).await
+}
+
+/// removes a stored file with its file data and thumbnails, the file is moved into the trash
+/// instead of being deleted if the trash is enabled
+pub async fn remove_stored_file(
+    state: &State,
+    name: &str,
+    reason: &str,
+) -> Result<(), UploadError> {
+    let _lock = state.file_data_lock.lock().await;
+    if !state.config.trash.enabled {
+        return Ok(delete_files(state, name).await?);
+    }
+
+    remove_thumbnails(state, name).await;
+    // a file that was trashed earlier with the same name gets replaced
+    purge(state, name).await?;
+    // the blob is moved first, so a failure can't leave file data without a blob behind
+    let file_path = state.upload_directory.join(name);
+    let moved = move_if_exists(&file_path, &upload_path(state, name)).await?;
+    if let Err(e) = move_if_exists(&state.data_directory.join(name), &data_path(state, name)).await
+    {
+        if moved {
+            move_file(&upload_path(state, name), &file_path).await?;
+        }
+        return Err(e.into());
+    }
+    let entry = TrashEntry {
+        deleted_at: current_unix_timestamp(),
+        reason: reason.to_string(),
+    };
+    write_json(
+        &entry,
+        &entry_path(state, name),
+        state.encryption.as_deref(),
+    )
+    .await
+}
+
+/// deletes the files whose retention in the trash is over
+async fn purge_expired(state: &State) -> Result<(), UploadError> {
+    let now = current_unix_timestamp();
+    let mut content = read_dir(state.trash_directory.join("entries")).await?;
+    while let Some(file) = content.next_entry().await? {
+        let Ok(name) = file.file_name().into_string() else {
+            continue;
+        };
+        match read_json::<TrashEntry>(&file.path(), state.encryption.as_deref()).await {
+            Ok(Some(entry))
+                if entry
+                    .deleted_at
+                    .saturating_add(state.config.trash.retention)
+                    < now =>
+            {
+                let file_data = trashed_file_data(state, &name).await?;
+                purge(state, &name).await?;
+                audit::record(
+                    state,
+                    AuditEvent::file(AuditAction::Delete, &name, &file_data)
+                        .with_detail("purged from the trash"),
+                );
+                webhook::notify(state, WebhookEvent::Deleted, &name, &file_data);
+                info!(file = %name, "file got deleted permanently from the trash");
+            }
+            Err(e) => error!(file = %name, error = ?e, "error while reading trash entry"),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+pub fn start_trash_purger(state: State) {
+    if !state.config.trash.enabled {
+        return;
+    }
+    tokio::spawn(
+        async move {
+            info!("started trash purger");
+            let interval = state.config.trash.retention.clamp(1, MAX_PURGE_INTERVAL);
+            loop {
+                if let Err(e) = purge_expired(&state).await {
+                    error!(error = ?e, "error while purging the trash");
+                }
+                sleep(Duration::from_secs(interval)).await;
+            }
+        }
+        .instrument(info_span!("trash_purger")),
+    );
+}
+
+/// checks the name of a file in the trash
+fn trashed_file_exists(state: &State, name: &str) -> Result<(), UploadError> {
+    if !is_valid_stored_filename(name) || !upload_path(state, name).exists() {
+        return Err(UploadError::FileNotExists);
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct TrashedFile {
+    name: String,
+    filename: String,
+    size: u64,
+    uploader: Option<String>,
+    deleted_at: u64,
+    /// the unix timestamp the file gets deleted permanently at
+    purge_at: u64,
+    reason: String,
+    /// `POST` to this link restores the file
+    restore: String,
+}
+
+#[derive(Serialize)]
+struct TrashResponse {
+    files: Vec<TrashedFile>,
+}
+
+/// lists the files in the trash, the recently removed files first
+pub async fn list_trash(
+    extract::State(state): extract::State<State>,
+    headers: HeaderMap,
+) -> Result<Response, UploadError> {
+    authorize_admin(&state, &headers)?;
+    let mut files = Vec::new();
+    let mut content = read_dir(state.trash_directory.join("entries")).await?;
+    while let Some(file) = content.next_entry().await? {
+        let Ok(name) = file.file_name().into_string() else {
+            continue;
+        };
+        let Ok(metadata) = upload_path(&state, &name).metadata() else {
+            continue;
+        };
+        let Some(entry) =
+            read_json::<TrashEntry>(&file.path(), state.encryption.as_deref()).await?
+        else {
+            continue;
+        };
+        let file_data = trashed_file_data(&state, &name).await?;
+        files.push(TrashedFile {
+            filename: file_data.display_name(original_filename(&name)).to_string(),
+            size: file_data.size.unwrap_or(metadata.len()),
+            uploader: file_data.uploader,
+            purge_at: entry
+                .deleted_at
+                .saturating_add(state.config.trash.retention),
+            deleted_at: entry.deleted_at,
+            reason: entry.reason,
+            restore: format!("/trash/{}/restore", url_encode(&name)),
+            name,
+        });
+    }
+    files.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at).then(a.name.cmp(&b.name)));
+    Ok(Json(TrashResponse { files }).into_response())
+}
+
+/// moves a file out of the trash, an expired ttl is removed so the file isn't removed again
+pub async fn restore(
+    extract::Path(filename): extract::Path<String>,
+    extract::State(state): extract::State<State>,
+    headers: HeaderMap,
+) -> Result<Response, UploadError> {
+    authorize_admin(&state, &headers)?;
+    trashed_file_exists(&state, &filename)?;
+    if state.upload_directory.join(&filename).exists() {
+        return Err(UploadError::FileAlreadyExists(filename));
+    }
+
+    let mut file_data = trashed_file_data(&state, &filename).await?;
+    let file_path = state.upload_directory.join(&filename);
+    move_file(&upload_path(&state, &filename), &file_path).await?;
+    if file_data.expired() {
+        file_data.ttl = None;
+    }
+    if !file_data.is_empty() {
+        // the file must not be restored without its file data (e.g. its download permission)
+        if let Err(e) = file_data
+            .write_to(
+                &state.data_directory.join(&filename),
+                state.encryption.as_deref(),
+            )
+            .await
+        {
+            move_file(&file_path, &upload_path(&state, &filename)).await?;
+            return Err(e);
+        }
+    }
+    purge(&state, &filename).await?;
+    info!(file = %filename, "restored file from the trash");
+    audit::record(
+        &state,
+        AuditEvent::file(AuditAction::Restore, &filename, &file_data),
+    );
+
+    state
+        .expiry_queue
+        .schedule(file_data.ttl, Expiring::File(filename.clone()));
+    if state.config.thumbnails.applies_to(&file_data) {
+        thumbnail::start_generating(state.clone(), filename.clone(), file_data.clone());
+    }
+
+    let file = StoredFile {
+        size: match file_data.size {
+            Some(size) => size,
+            None => state.upload_directory.join(&filename).metadata()?.len(),
+        },
+        uploaded_at: file_data.uploaded_at.unwrap_or_default(),
+        name: filename,
+        file_data,
+    };
+    Ok(Json(FileEntry::from(&file)).into_response())
+}
+
+/// deletes a file in the trash permanently
+pub async fn delete_from_trash(
+    extract::Path(filename): extract::Path<String>,
+    extract::State(state): extract::State<State>,
+    headers: HeaderMap,
+) -> Result<Response, UploadError> {
+    authorize_admin(&state, &headers)?;
+    trashed_file_exists(&state, &filename)?;
+    let file_data = trashed_file_data(&state, &filename).await?;
+    purge(&state, &filename).await?;
+    info!(file = %filename, "file got deleted permanently from the trash");
+    audit::record(
+        &state,
+        AuditEvent::file(AuditAction::Delete, &filename, &file_data)
+            .with_detail("deleted from the trash"),
+    );
+    webhook::notify(&state, WebhookEvent::Deleted, &filename, &file_data);
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
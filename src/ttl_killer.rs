@@ -1,44 +1,242 @@
-use std::time::Duration;
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
+use serde::Deserialize;
 use tokio::{
-    fs::{read_dir, remove_file},
-    io,
-    time::sleep,
+    fs::read_dir,
+    sync::Notify,
+    time::{sleep_until, Instant},
+};
+use tracing::{error, info, info_span, Instrument};
+
+use crate::{
+    audit::{self, AuditAction, AuditEvent},
+    collection::{remove_collection, remove_expired_collections, Collection},
+    error::UploadError,
+    file_data::{current_unix_timestamp, FileData},
+    state::State,
+    trash::remove_stored_file,
+    webhook::{self, WebhookEvent},
 };
 
-use crate::{file_data::FileData, state::State};
+/// the seconds until the removal of an expired file is tried again after it failed
+const REMOVAL_RETRY_DELAY: u64 = 60;
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct ExpiryConfig {
+    /// The seconds between two scans of all file data, the scan catches expiries the queue missed
+    pub sweep_interval: u64,
+}
+
+impl Default for ExpiryConfig {
+    fn default() -> Self {
+        Self {
+            sweep_interval: 3600,
+        }
+    }
+}
+
+/// something that is removed when its ttl is reached
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Expiring {
+    /// a stored file by its name
+    File(String),
+    /// a collection by its id, its files are removed with it
+    Collection(String),
+}
+
+/// a min heap of the ttls and what expires at them
+type ExpiryHeap = BinaryHeap<Reverse<(u64, Expiring)>>;
+
+/// the upcoming expiries ordered by their ttl, the ttl killer sleeps until the earliest one is due
+///
+/// entries are never updated in place, when an entry is due the ttl killer rereads the file data
+/// (or collection) and only removes it if it's still expired
+#[derive(Clone, Default)]
+pub struct ExpiryQueue {
+    queue: Arc<Mutex<ExpiryHeap>>,
+    changed: Arc<Notify>,
+}
+
+impl ExpiryQueue {
+    /// schedules the removal at `ttl`, nothing is scheduled if `ttl` is `None`
+    pub fn schedule(&self, ttl: Option<u64>, expiring: Expiring) {
+        let Some(ttl) = ttl else {
+            return;
+        };
+        self.queue.lock().unwrap().push(Reverse((ttl, expiring)));
+        self.changed.notify_one();
+    }
+
+    fn clear(&self) {
+        self.queue.lock().unwrap().clear();
+    }
+
+    /// the ttl of the earliest entry
+    fn next(&self) -> Option<u64> {
+        self.queue
+            .lock()
+            .unwrap()
+            .peek()
+            .map(|Reverse((ttl, _))| *ttl)
+    }
+
+    /// removes the earliest entry from the queue if its ttl is in the past
+    fn pop_due(&self) -> Option<Expiring> {
+        let mut queue = self.queue.lock().unwrap();
+        match queue.peek() {
+            Some(Reverse((ttl, _))) if *ttl < current_unix_timestamp() => {
+                queue.pop().map(|Reverse((_, expiring))| expiring)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// schedules the removal of an expired file again after it failed
+fn retry_later(state: &State, filename: String) {
+    state.expiry_queue.schedule(
+        Some(current_unix_timestamp() + REMOVAL_RETRY_DELAY),
+        Expiring::File(filename),
+    );
+}
+
+/// removes the file with its data and thumbnails (or moves it into the trash)
+async fn remove_expired_file(
+    state: &State,
+    filename: &str,
+    file_data: &FileData,
+) -> Result<(), UploadError> {
+    remove_stored_file(state, filename, "expired").await?;
+    audit::record(
+        state,
+        AuditEvent::file(AuditAction::Expire, filename, file_data),
+    );
+    webhook::notify(state, WebhookEvent::Expired, filename, file_data);
+    state.metrics.record_ttl_deletion("file");
+    info!(file = %filename, "file got removed because the ttl was reached");
+    Ok(())
+}
+
+/// removes the file or collection if it's still expired, its ttl could have been changed since it was scheduled
+async fn remove_if_expired(state: &State, expiring: Expiring) {
+    match expiring {
+        Expiring::File(filename) => {
+            let file_data = FileData::read_from(
+                &state.data_directory.join(&filename),
+                state.encryption.as_deref(),
+            )
+            .await;
+            match file_data {
+                Ok(Some(file_data)) if file_data.expired() => {
+                    if let Err(e) = remove_expired_file(state, &filename, &file_data).await {
+                        error!(file = %filename, error = ?e, "error while removing expired file");
+                        retry_later(state, filename);
+                    }
+                }
+                Err(e) => {
+                    error!(file = %filename, error = ?e, "error while reading file data");
+                }
+                // the file was already removed or it doesn't expire anymore
+                _ => {}
+            }
+        }
+        Expiring::Collection(id) => match Collection::read(state, &id).await {
+            Ok(Some(collection)) if collection.expired() => {
+                match remove_collection(state, &id, true).await {
+                    Ok(()) => {
+                        state.metrics.record_ttl_deletion("collection");
+                        info!(collection = %id, "collection got removed because the ttl was reached")
+                    }
+                    Err(e) => {
+                        error!(collection = %id, error = ?e, "error while removing collection")
+                    }
+                }
+            }
+            Err(e) => error!(collection = %id, error = ?e, "error while reading collection"),
+            _ => {}
+        },
+    }
+}
+
+/// scans all collections and file data, removes everything that is expired and rebuilds the queue
+async fn sweep(state: &State) -> Result<(), UploadError> {
+    state.expiry_queue.clear();
+
+    // the files of expired collections are removed with their collection
+    if let Err(e) = remove_expired_collections(state).await {
+        error!(error = ?e, "error while removing expired collections");
+    }
 
-async fn ttl_killer(state: State) -> Result<(), io::Error> {
-    let mut content = read_dir(state.data_directory).await?;
+    let mut content = read_dir(&state.data_directory).await?;
     while let Some(file) = content.next_entry().await? {
-        let file_path = file.path();
-        let file_data = FileData::read_from(&file_path).await;
-        if let Ok(Some(file_data)) = file_data {
-            if file_data.expired() {
-                remove_file(file_path).await?;
-                let upload_path = state.upload_directory.join(file.file_name());
-                remove_file(upload_path).await?;
-                println!(
-                    "INFO: File {} got removed because the ttl was reached.",
-                    file.file_name().to_str().unwrap()
-                );
+        let Ok(filename) = file.file_name().into_string() else {
+            continue;
+        };
+        let file_data = FileData::read_from(&file.path(), state.encryption.as_deref()).await;
+        match file_data {
+            Ok(Some(file_data)) if file_data.expired() => {
+                // one failed removal must not stop the queue from being rebuilt
+                if let Err(e) = remove_expired_file(state, &filename, &file_data).await {
+                    error!(file = %filename, error = ?e, "error while removing expired file");
+                    retry_later(state, filename);
+                }
+            }
+            Ok(Some(file_data)) => {
+                state
+                    .expiry_queue
+                    .schedule(file_data.ttl, Expiring::File(filename));
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!(file = %filename, error = ?e, "error while parsing file data");
             }
-        } else if let Err(e) = file_data {
-            println!("ERROR: Error while discovering file data: Parsing error while parsing file {}: {e:?}", file.file_name().to_str().unwrap());
         }
     }
 
     Ok(())
 }
 
+/// the instant at which a ttl is in the past
+fn instant_of(ttl: u64) -> Instant {
+    let due = UNIX_EPOCH + Duration::from_secs(ttl + 1);
+    Instant::now() + due.duration_since(SystemTime::now()).unwrap_or_default()
+}
+
 pub fn start_ttl_killer(state: State) {
-    tokio::spawn(async move {
-        println!("INFO: Started ttl killer");
-        loop {
-            if let Err(e) = ttl_killer(state.clone()).await {
-                println!("ERROR: Error while running ttl killer: {e:?}");
+    tokio::spawn(
+        async move {
+            info!("started ttl killer");
+            let sweep_interval = Duration::from_secs(state.config.expiry.sweep_interval.max(1));
+            // the first sweep builds the queue at startup
+            let mut next_sweep = Instant::now();
+            loop {
+                if Instant::now() >= next_sweep {
+                    if let Err(e) = sweep(&state).await {
+                        error!(error = ?e, "error while sweeping expired files");
+                    }
+                    next_sweep = Instant::now() + sweep_interval;
+                }
+                while let Some(expiring) = state.expiry_queue.pop_due() {
+                    remove_if_expired(&state, expiring).await;
+                }
+
+                let wake_up = match state.expiry_queue.next() {
+                    Some(ttl) => instant_of(ttl).min(next_sweep),
+                    None => next_sweep,
+                };
+                // new entries could be due earlier than the one the killer is sleeping for
+                tokio::select! {
+                    _ = sleep_until(wake_up) => {}
+                    _ = state.expiry_queue.changed.notified() => {}
+                }
             }
-            sleep(Duration::from_secs(10)).await;
         }
-    });
+        .instrument(info_span!("ttl_killer")),
+    );
 }
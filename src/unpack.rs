@@ -0,0 +1,376 @@
+use std::{
+    io::Cursor,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use async_compression::tokio::bufread::{GzipDecoder, ZstdDecoder};
+use async_zip::base::read::stream::ZipFileReader;
+use axum::{
+    body::Body,
+    http::{Response, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use futures_util::StreamExt;
+use serde::Serialize;
+use tokio::{
+    fs::remove_file,
+    io::{copy, sink, AsyncBufRead, AsyncRead, AsyncReadExt, BufReader},
+};
+use tokio_util::{compat::FuturesAsyncReadCompatExt, io::InspectReader};
+use tracing::{error, info, warn};
+
+use crate::{
+    error::UploadError,
+    file_data::{current_unix_timestamp, FileData},
+    filename::url_encode,
+    state::State,
+    thumbnail::remove_thumbnails,
+    upload::{publish, PendingFile},
+};
+
+/// the maximal amount of entries an archive may contain
+const MAX_ENTRIES: usize = 10_000;
+
+/// the amount of bytes that are read to detect the format of the archive
+const HEAD_LENGTH: usize = 512;
+
+#[derive(Clone, Copy)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+impl ArchiveKind {
+    /// detects the kind of the archive by the magic bytes at its beginning
+    fn detect(head: &[u8]) -> Option<Self> {
+        if infer::archive::is_zip(head) {
+            Some(Self::Zip)
+        } else if infer::archive::is_tar(head) {
+            Some(Self::Tar)
+        } else if infer::archive::is_gz(head) {
+            Some(Self::TarGz)
+        } else if infer::archive::is_zst(head) {
+            Some(Self::TarZst)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ManifestEntry {
+    /// the path of the file in the archive
+    path: String,
+    /// the link to the stored file, it's missing if the file couldn't be stored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    link: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    /// the unix timestamp the file gets deleted at
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+    /// the reason why the file wasn't stored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Manifest {
+    files: Vec<ManifestEntry>,
+}
+
+fn invalid_archive(error: impl ToString) -> UploadError {
+    UploadError::InvalidArchive(error.to_string())
+}
+
+/// returns the filename of the entry at `path`, entries whose path could escape
+/// the directory they are extracted to (zip slip) are rejected
+fn entry_filename(path: &str) -> Option<&str> {
+    // absolute paths and windows drive letters
+    if path.starts_with(['/', '\\']) || path.get(1..2) == Some(":") {
+        return None;
+    }
+    let mut components = path.split(['/', '\\']);
+    if components.clone().any(|component| component == "..") {
+        return None;
+    }
+    // the files are stored without their directories
+    components
+        .next_back()
+        .filter(|name| !name.is_empty() && *name != ".")
+}
+
+/// stores the files of an archive, it keeps track of everything that has been extracted
+struct Unpacker<'a> {
+    state: &'a State,
+    file_data: FileData,
+    /// the maximal size of all extracted files together
+    max_size: u64,
+    size: u64,
+    manifest: Vec<ManifestEntry>,
+    /// the names and file data of the files that were stored, they are published when the whole archive was extracted
+    stored: Vec<(String, FileData)>,
+}
+
+impl Unpacker<'_> {
+    fn skip(&mut self, path: String, reason: &str) {
+        self.manifest.push(ManifestEntry {
+            path,
+            link: None,
+            size: None,
+            expires_at: None,
+            error: Some(reason.to_string()),
+        });
+    }
+
+    /// checks the path of the entry, returns the filename it gets stored with
+    fn accept(&mut self, path: &str) -> Result<Option<String>, UploadError> {
+        if self.manifest.len() >= MAX_ENTRIES {
+            return Err(invalid_archive(format!(
+                "it contains more than {MAX_ENTRIES} files"
+            )));
+        }
+        let filename = entry_filename(path)
+            .and_then(|filename| self.state.config.filename.apply(filename).ok());
+        if filename.is_none() {
+            self.skip(path.to_string(), "The path is not allowed");
+        }
+        Ok(filename)
+    }
+
+    /// stores the content of the entry, only errors that abort the extraction are returned
+    async fn store(
+        &mut self,
+        path: String,
+        filename: &str,
+        reader: &mut (impl AsyncRead + Unpin),
+    ) -> Result<(), UploadError> {
+        let mut file = PendingFile::create(self.state, filename, &self.file_data).await?;
+        let mut buffer = vec![0; 64 * 1024];
+        let mut size = 0;
+        loop {
+            let read = match reader.read(&mut buffer).await {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) => {
+                    file.remove().await?;
+                    return Err(invalid_archive(e));
+                }
+            };
+            // the sizes of the entries in the archive can't be trusted (zip bombs)
+            size += read as u64;
+            self.size += read as u64;
+            if self.size > self.max_size {
+                file.remove().await?;
+                return Err(UploadError::FileIsTooBig(self.max_size));
+            }
+            // the disk could be full
+            if let Err(e) = file.write(&buffer[..read]).await {
+                file.remove().await?;
+                self.state.eviction_check.notify_one();
+                return Err(e.into());
+            }
+        }
+
+        let mut file_data = self.file_data.clone();
+        self.state
+            .config
+            .retention
+            .apply(&mut file_data.ttl, size, current_unix_timestamp());
+        let ttl = file_data.ttl;
+        match file.store(self.state, file_data).await {
+            Ok((name, file_data)) => {
+                self.manifest.push(ManifestEntry {
+                    path,
+                    link: Some(format!("/d/{}", url_encode(&name))),
+                    size: Some(size),
+                    expires_at: ttl,
+                    error: None,
+                });
+                self.stored.push((name, file_data));
+            }
+            Err(UploadError::FileAlreadyExists(name)) => self.manifest.push(ManifestEntry {
+                path,
+                link: Some(format!("/d/{}", url_encode(&name))),
+                size: None,
+                expires_at: None,
+                error: Some("The file already exists".to_string()),
+            }),
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    /// removes the files that were already stored because the extraction failed
+    async fn abort(&self) {
+        for (name, _) in &self.stored {
+            let _ = remove_file(self.state.upload_directory.join(name)).await;
+            let _ = remove_file(self.state.data_directory.join(name)).await;
+            remove_thumbnails(self.state, name).await;
+        }
+    }
+
+    async fn unpack_tar(
+        &mut self,
+        reader: impl AsyncRead + Unpin + Send,
+    ) -> Result<(), UploadError> {
+        let mut archive = tokio_tar::Archive::new(reader);
+        let mut entries = archive.entries().map_err(invalid_archive)?;
+        while let Some(entry) = entries.next().await {
+            let mut entry = entry.map_err(invalid_archive)?;
+            let path = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+            let entry_type = entry.header().entry_type();
+            if entry_type.is_dir() {
+                continue;
+            }
+            // links could point to files outside of the archive
+            if !entry_type.is_file() {
+                self.skip(path, "Only regular files are extracted");
+                continue;
+            }
+            if let Some(filename) = self.accept(&path)? {
+                self.store(path, &filename, &mut entry).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn unpack_zip(&mut self, reader: impl AsyncBufRead + Unpin) -> Result<(), UploadError> {
+        let mut zip = ZipFileReader::with_tokio(reader);
+        while let Some(mut entry) = zip.next_with_entry().await.map_err(invalid_archive)? {
+            let zip_entry = entry.reader().entry();
+            let path = zip_entry
+                .filename()
+                .as_str()
+                .map_err(invalid_archive)?
+                .to_string();
+            if !zip_entry.dir().map_err(invalid_archive)? {
+                if let Some(filename) = self.accept(&path)? {
+                    self.store(path, &filename, &mut entry.reader_mut().compat())
+                        .await?;
+                }
+            }
+            // skipped entries are read until their end to get to the next entry
+            zip = entry.skip().await.map_err(invalid_archive)?;
+        }
+        Ok(())
+    }
+}
+
+/// extracts the uploaded archive (zip, tar, tar.gz or tar.zst) and stores every file of it,
+/// responds with a manifest containing the links to the stored files
+pub async fn unpack(
+    state: &State,
+    reader: impl AsyncRead + Unpin + Send,
+    filename: &str,
+    file_data: FileData,
+    max_size: u64,
+    content_size: Option<u64>,
+) -> Result<Response<Body>, UploadError> {
+    // the whole archive counts against the maximal filesize, also the entries that are skipped
+    let received = AtomicU64::new(0);
+    let mut reader =
+        InspectReader::new(reader.take(max_size.saturating_add(1)), |chunk: &[u8]| {
+            received.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        });
+    let mut head = Vec::with_capacity(HEAD_LENGTH);
+    (&mut reader)
+        .take(HEAD_LENGTH as u64)
+        .read_to_end(&mut head)
+        .await?;
+    let kind = ArchiveKind::detect(&head)
+        .ok_or_else(|| invalid_archive(format!("the format of {filename} is not supported")))?;
+    let mut reader = BufReader::new(Cursor::new(head).chain(reader));
+
+    let mut unpacker = Unpacker {
+        state,
+        file_data,
+        max_size,
+        size: 0,
+        manifest: Vec::new(),
+        stored: Vec::new(),
+    };
+    let mut result = match kind {
+        ArchiveKind::Zip => unpacker.unpack_zip(&mut reader).await,
+        ArchiveKind::Tar => unpacker.unpack_tar(&mut reader).await,
+        ArchiveKind::TarGz => {
+            let mut decoder = GzipDecoder::new(&mut reader);
+            decoder.multiple_members(true);
+            unpacker.unpack_tar(decoder).await
+        }
+        ArchiveKind::TarZst => unpacker.unpack_tar(ZstdDecoder::new(&mut reader)).await,
+    };
+    // the rest of the body (e.g. the central directory of a zip) belongs to the upload too
+    if result.is_ok() {
+        result = copy(&mut reader, &mut sink())
+            .await
+            .map(|_| ())
+            .map_err(invalid_archive);
+    }
+    drop(reader);
+    // check if the upload was completed
+    let received = received.into_inner();
+    if received > max_size {
+        result = Err(UploadError::FileIsTooBig(max_size));
+    } else if let Some(content_size) = content_size.filter(|content_size| *content_size > received)
+    {
+        warn!(file = %filename, announced = content_size, received, "the upload is incomplete, less bytes than announced were received");
+        result = Err(UploadError::IncompleteUpload(content_size, received));
+    }
+    if let Err(e) = result {
+        error!(file = %filename, "extracting failed, removing the extracted files");
+        unpacker.abort().await;
+        return Err(e);
+    }
+    info!(file = %filename, files = unpacker.stored.len(), "extracted archive");
+    // the files are only published when the whole archive was extracted
+    for (name, file_data) in unpacker.stored {
+        publish(state, &name, file_data);
+    }
+
+    let manifest = Manifest {
+        files: unpacker.manifest,
+    };
+    Ok((StatusCode::CREATED, Json(manifest)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_filename_strips_directories() {
+        assert_eq!(entry_filename("file.txt"), Some("file.txt"));
+        assert_eq!(entry_filename("dir/sub/file.txt"), Some("file.txt"));
+        assert_eq!(entry_filename("dir\\file.txt"), Some("file.txt"));
+        assert_eq!(entry_filename("./file.txt"), Some("file.txt"));
+        assert_eq!(entry_filename("dir/..file"), Some("..file"));
+    }
+
+    #[test]
+    fn entry_filename_rejects_path_traversal() {
+        for path in [
+            "../file.txt",
+            "dir/../../file.txt",
+            "dir/..",
+            "..\\file.txt",
+            "dir\\..\\..\\file.txt",
+            "/etc/passwd",
+            "\\windows\\system32",
+            "C:\\file.txt",
+            "C:file.txt",
+        ] {
+            assert_eq!(entry_filename(path), None, "{path}");
+        }
+    }
+
+    #[test]
+    fn entry_filename_rejects_directories() {
+        for path in ["", ".", "dir/", "dir\\", "dir/."] {
+            assert_eq!(entry_filename(path), None, "{path}");
+        }
+    }
+}
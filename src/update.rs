@@ -0,0 +1,104 @@
+use axum::{
+    body::Bytes,
+    extract,
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+    Json,
+};
+use tracing::info;
+
+use crate::{
+    audit::{self, AuditAction, AuditEvent},
+    auth::{AuthRequest, Authorization},
+    auth_helper::authorize_by_headers,
+    error::UploadError,
+    file_data::{current_unix_timestamp, FileData, FileDataUpdate},
+    filename::is_valid_stored_filename,
+    list::{FileEntry, StoredFile},
+    state::State,
+    ttl_killer::Expiring,
+};
+
+/// reads the file data of a stored file the user may change, only the uploader and admins may change a file
+pub async fn changeable_file_data(
+    state: &State,
+    authorization: &Authorization,
+    filename: &str,
+) -> Result<FileData, UploadError> {
+    if !is_valid_stored_filename(filename) || !state.upload_directory.join(filename).exists() {
+        return Err(UploadError::FileNotExists);
+    }
+    let file_data = FileData::read_from(
+        &state.data_directory.join(filename),
+        state.encryption.as_deref(),
+    )
+    .await?
+    .unwrap_or_default();
+    // expired files are removed soon
+    if file_data.expired() {
+        return Err(UploadError::FileNotExists);
+    }
+    if !authorization.owns(&file_data.uploader) {
+        return Err(UploadError::Forbidden);
+    }
+    Ok(file_data)
+}
+
+/// changes the file data of an uploaded file with a json body or the `File-Data-*` headers
+pub async fn update(
+    extract::Path(filename): extract::Path<String>,
+    extract::State(state): extract::State<State>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, UploadError> {
+    let authorization = authorize_by_headers(&state, &headers, AuthRequest::Upload)?;
+    let lock = state.file_data_lock.lock().await;
+    let mut file_data = changeable_file_data(&state, &authorization, &filename).await?;
+
+    // the json body is used if there is one, the headers otherwise
+    let mut update = if body.is_empty() {
+        FileDataUpdate::try_from(&headers)?
+    } else {
+        serde_json::from_slice::<FileDataUpdate>(&body)
+            .map_err(|e| UploadError::InvalidJson(e.to_string()))?
+    };
+    // the display name must follow the same rules as uploaded filenames
+    if let Some(Some(display_name)) = &mut update.display_name {
+        *display_name = state.config.filename.apply(display_name)?;
+    }
+    if update.pinned.is_some() && !authorization.admin {
+        return Err(UploadError::Forbidden);
+    }
+    file_data.update(update, &authorization.ttl_policy)?;
+    let uploaded_at = file_data.uploaded_at.unwrap_or_else(current_unix_timestamp);
+    let size = match file_data.size {
+        Some(size) => size,
+        None => state.upload_directory.join(&filename).metadata()?.len(),
+    };
+    state
+        .config
+        .retention
+        .apply(&mut file_data.ttl, size, uploaded_at);
+
+    let data_path = state.data_directory.join(&filename);
+    file_data
+        .write_to(&data_path, state.encryption.as_deref())
+        .await?;
+    drop(lock);
+    info!(file = %filename, "updated the file data");
+    audit::record(
+        &state,
+        AuditEvent::file(AuditAction::Update, &filename, &file_data),
+    );
+    state
+        .expiry_queue
+        .schedule(file_data.ttl, Expiring::File(filename.clone()));
+
+    let file = StoredFile {
+        size,
+        uploaded_at: file_data.uploaded_at.unwrap_or_default(),
+        name: filename,
+        file_data,
+    };
+    Ok(Json(FileEntry::from(&file)).into_response())
+}
@@ -1,26 +1,188 @@
+use std::{borrow::Cow, path::PathBuf};
+
 use axum::{
     body::{Body, HttpBody},
     extract,
     http::{HeaderMap, Response, StatusCode},
 };
 use blake3::Hasher;
-use futures_util::StreamExt;
+use futures_util::{StreamExt, TryStreamExt};
 use tokio::{
     fs::{remove_file, rename, File},
-    io::AsyncWriteExt,
+    io::{self, AsyncWriteExt},
 };
+use tokio_util::io::StreamReader;
+use tracing::{info, warn};
 
 use crate::{
-    auth::AuthRequest, auth_helper::authorize_by_headers, error::UploadError, file_data::FileData,
-    random::generate_random_characters, state::State,
+    audit::{self, AuditAction, AuditEvent},
+    auth::AuthRequest,
+    auth_helper::authorize_by_headers,
+    collection,
+    content_type::{detect, SNIFF_LENGTH},
+    error::UploadError,
+    exif::MetadataStripper,
+    file_data::{
+        current_unix_timestamp, parse_bool_header, FileData, FILE_DATA_EXTRACT_HEADER_NAME,
+    },
+    filename::url_encode,
+    logging::record_bytes,
+    random::generate_random_characters,
+    retention::EXPIRES_HEADER_NAME,
+    state::State,
+    storage::BlobWriter,
+    thumbnail,
+    ttl_killer::Expiring,
+    unpack,
+    webhook::{self, WebhookEvent},
 };
 
-/// checks if filename is invalid and return `Err(UploadError::InvalidFilename)` if not
-fn validate_filename(filename: &str) -> Result<(), UploadError> {
-    if filename.contains('/') {
-        return Err(UploadError::InvalidFilename);
+/// a file that is being uploaded, it has a `.upload` suffix until it's finished
+pub struct PendingFile {
+    /// the filename the file was uploaded with
+    filename: String,
+    path: PathBuf,
+    writer: BlobWriter,
+    /// start hashing the file by creating a blake3 hasher
+    hasher: Hasher,
+    /// the beginning of the file is used to detect the content type
+    head: Vec<u8>,
+    /// the size of the stored file, it's smaller than the uploaded size if metadata got stripped
+    size: u64,
+    metadata_stripper: Option<MetadataStripper>,
+}
+
+impl PendingFile {
+    /// creates the `.upload` file, `file_data` decides how the file gets stored
+    pub async fn create(
+        state: &State,
+        filename: &str,
+        file_data: &FileData,
+    ) -> Result<Self, UploadError> {
+        // the filename starts with random characters
+        let upload_filename = generate_random_characters(8) + "_" + filename + ".upload";
+        let path = state.upload_directory.join(&upload_filename);
+        let writer = state.config.storage.writer(
+            File::create(&path).await?,
+            file_data.compressed.unwrap_or_default(),
+            state.encryption.as_deref(),
+        )?;
+        // the metadata of client side encrypted files can't be stripped
+        let metadata_stripper = (state.config.storage.strip_exif
+            && !file_data.encrypted.unwrap_or_default())
+        .then(MetadataStripper::default);
+        Ok(Self {
+            filename: filename.to_string(),
+            path,
+            writer,
+            hasher: Hasher::new(),
+            head: Vec::with_capacity(SNIFF_LENGTH),
+            size: 0,
+            metadata_stripper,
+        })
+    }
+
+    /// writes a chunk of the uploaded file
+    pub async fn write(&mut self, chunk: &[u8]) -> io::Result<()> {
+        let chunk = match &mut self.metadata_stripper {
+            Some(metadata_stripper) => metadata_stripper.push(chunk),
+            None => Cow::Borrowed(chunk),
+        };
+        self.write_stored(&chunk).await
+    }
+
+    async fn write_stored(&mut self, data: &[u8]) -> io::Result<()> {
+        self.writer.write_all(data).await?;
+        self.hasher.update(data);
+        if self.head.len() < SNIFF_LENGTH {
+            let missing = (SNIFF_LENGTH - self.head.len()).min(data.len());
+            self.head.extend_from_slice(&data[..missing]);
+        }
+        self.size += data.len() as u64;
+        Ok(())
+    }
+
+    /// removes the `.upload` file because the upload failed
+    pub async fn remove(self) -> io::Result<()> {
+        // we will close the file before removing because you can't remove the file before closing the file on windows
+        drop(self.writer);
+        remove_file(&self.path).await
+    }
+
+    /// stores the uploaded file and publishes it, returns the name of the stored file
+    pub async fn finish(self, state: &State, file_data: FileData) -> Result<String, UploadError> {
+        let (real_filename, file_data) = self.store(state, file_data).await?;
+        publish(state, &real_filename, file_data);
+        Ok(real_filename)
+    }
+
+    /// moves the uploaded file to its final name and saves its file data, returns the name of the stored file
+    /// and its file data, the file has to be published afterwards
+    pub async fn store(
+        mut self,
+        state: &State,
+        mut file_data: FileData,
+    ) -> Result<(String, FileData), UploadError> {
+        if let Some(metadata_stripper) = self.metadata_stripper.take() {
+            self.write_stored(&metadata_stripper.finish()).await?;
+        }
+        // finish the blob (e.g. the compression frame) and flush it to the disk
+        self.writer.shutdown().await?;
+        drop(self.writer);
+
+        // compute the blake3 hash
+        let blake3_hex = self.hasher.finalize().to_hex().to_string();
+        // the real filename has the format {first 8 characters of blake3 hash (hex) of file}_{filename}
+        let real_filename = format!("{}_{}", &blake3_hex[..8], self.filename);
+        file_data.hash = Some(blake3_hex);
+        let real_path = state.upload_directory.join(&real_filename);
+
+        // check if the path already exists
+        if real_path.exists() {
+            warn!(file = %real_filename, "user tried to upload a file but there is already one with the same name");
+            remove_file(self.path).await?;
+            return Err(UploadError::FileAlreadyExists(real_filename));
+        }
+
+        // the upload is completed so the file will be renamed to the correct filename
+        rename(&self.path, real_path).await?;
+
+        // insert file data
+        file_data.size = Some(self.size);
+        file_data.uploaded_at = Some(current_unix_timestamp());
+        // the content of client side encrypted files can't be detected
+        if state.config.content_type.detect && !file_data.encrypted.unwrap_or_default() {
+            file_data.content_type = Some(detect(&self.filename, &self.head));
+        }
+        if !file_data.is_empty() {
+            let data_path = state.data_directory.join(&real_filename);
+            file_data
+                .write_to(&data_path, state.encryption.as_deref())
+                .await?;
+        }
+
+        record_bytes(self.size);
+        Ok((real_filename, file_data))
+    }
+}
+
+/// announces a stored file, it's recorded in the audit log, sent to the webhooks,
+/// scheduled for its expiry and gets its thumbnails
+pub fn publish(state: &State, real_filename: &str, file_data: FileData) {
+    info!(file = %real_filename, "uploaded file");
+    audit::record(
+        state,
+        AuditEvent::file(AuditAction::Upload, real_filename, &file_data),
+    );
+    webhook::notify(state, WebhookEvent::Uploaded, real_filename, &file_data);
+    state.eviction_check.notify_one();
+    state
+        .expiry_queue
+        .schedule(file_data.ttl, Expiring::File(real_filename.to_string()));
+
+    if state.config.thumbnails.applies_to(&file_data) {
+        thumbnail::start_generating(state.clone(), real_filename.to_string(), file_data);
     }
-    Ok(())
 }
 
 pub async fn upload(
@@ -30,7 +192,8 @@ pub async fn upload(
     body: Body,
 ) -> Result<Response<Body>, UploadError> {
     // authorize the request by its `Authorization` header
-    let max_filesize = authorize_by_headers(&state, &headers, AuthRequest::Upload)?;
+    let authorization = authorize_by_headers(&state, &headers, AuthRequest::Upload)?;
+    let max_filesize = authorization.max_filesize;
 
     let mut content_size: Option<u64> = None;
     // check if the user tries to upload a file that is too big or empty if he gave us the `Content-Length` header
@@ -48,13 +211,30 @@ pub async fn upload(
         }
     }
 
-    validate_filename(&filename)?;
+    // check the filename against the configured policy, it may get sanitized
+    let filename = state.config.filename.apply(&filename)?;
 
-    // the filename starts with random characters
-    let upload_filename = generate_random_characters(8) + "_" + &filename + ".upload";
+    let extract = parse_bool_header(&headers, FILE_DATA_EXTRACT_HEADER_NAME)?.unwrap_or_default();
+    // parse the file data before receiving the file, so invalid headers are rejected early
+    let mut file_data = FileData::try_from(headers)?;
+    // the `File-Data-Compress` header overrides the server configuration
+    file_data
+        .compressed
+        .get_or_insert(state.config.storage.compress);
+    if state.encryption.is_some() {
+        file_data.encrypted_at_rest = Some(true);
+    }
+    // files can only be uploaded into collections the user may change
+    if let Some(id) = &file_data.collection {
+        collection::authorize_upload(&state, &authorization, id).await?;
+    }
+    file_data.apply_ttl_policy(&authorization.ttl_policy)?;
+    // pinned files could fill the disk, so only admins may pin them
+    if file_data.pinned.is_some() && !authorization.admin {
+        return Err(UploadError::Forbidden);
+    }
+    file_data.uploader = authorization.identity;
 
-    // the file will have a `.upload` suffix until the upload is finished
-    let upload_path = state.upload_directory.clone().join(&upload_filename);
     let mut stream = body.into_data_stream();
 
     // if the body seems to be empty return a invalid body error
@@ -62,65 +242,71 @@ pub async fn upload(
         return Err(UploadError::InvalidBody);
     }
 
-    let mut file = File::create(&upload_path).await?;
+    if extract {
+        // the content of client side encrypted archives can't be read
+        if file_data.encrypted.unwrap_or_default() {
+            return Err(UploadError::InvalidFileDataArgument(
+                FILE_DATA_EXTRACT_HEADER_NAME.to_string(),
+            ));
+        }
+        let reader = StreamReader::new(stream.map_err(io::Error::other));
+        return unpack::unpack(
+            &state,
+            reader,
+            &filename,
+            file_data,
+            max_filesize,
+            content_size,
+        )
+        .await;
+    }
+
+    let mut file = PendingFile::create(&state, &filename, &file_data).await?;
 
     // size will contain the already uploaded filesize
     let mut size: u64 = 0;
-    // start hashing the file by creating a blake3 hasher
-    let mut hasher = Hasher::new();
     while let Some(Ok(value)) = stream.next().await {
         size += value.len() as u64;
         // the file got to big, remove the file and return a file is too big error
         if size > max_filesize {
-            // we will close the file before removing because you can't remove the file before closing the file on windows
-            drop(file);
-            remove_file(&upload_path).await?;
+            file.remove().await?;
             return Err(UploadError::FileIsTooBig(max_filesize));
         }
-        // write the chunk to the file
-        file.write_all(&value).await?;
-        hasher.update(&value);
+        // write the chunk to the file, the disk could be full
+        if let Err(e) = file.write(&value).await {
+            file.remove().await?;
+            state.eviction_check.notify_one();
+            return Err(e.into());
+        }
     }
-    drop(file);
 
     // check if the upload was completed
     if let Some(content_size) = content_size {
         if content_size > size {
-            println!("ERROR: There was an error while uploading {upload_filename}. The upload seems to be incomplete: The user tried to upload {content_size} bytes but only {size} bytes was received.");
-            remove_file(&upload_path).await?;
+            warn!(file = %filename, announced = content_size, received = size, "the upload is incomplete, less bytes than announced were received");
+            file.remove().await?;
             return Err(UploadError::IncompleteUpload(content_size, size));
         }
     }
-    // compute the blake3 hash
-    let blake3_hex = hasher.finalize().to_hex().to_string();
-    // the real filename has the format {first 8 characters of blake3 hash (hex) of file}_{filename}
-    let real_filename = format!("{}_{filename}", &blake3_hex[..8]);
-    let real_path = state.upload_directory.clone().join(&real_filename);
-
-    // check if the path already exists
-    if real_path.exists() {
-        println!("ERROR: User tried to upload a file but there is already one with the same name: {real_filename}");
-        remove_file(upload_path).await?;
-        return Err(UploadError::InternalServerError);
-    }
-
-    // the upload is completed so the file will be renamed to the correct filename
-    rename(&upload_path, real_path).await?;
 
-    // insert file data
-    let file_data = FileData::try_from(headers)?;
-    if !file_data.is_empty() {
-        let data_path = state.data_directory.join(&real_filename);
-        file_data.write_to(&data_path).await?;
-    }
-
-    println!("INFO: Uploaded {real_filename}");
+    // small files may be kept longer than big ones
+    state
+        .config
+        .retention
+        .apply(&mut file_data.ttl, size, current_unix_timestamp());
+    let ttl = file_data.ttl;
+    let real_filename = file.finish(&state, file_data).await?;
 
     // respond with a CREATED response that includes the link to the created file in body and the `Location` header
-    Ok(Response::builder()
-        .header("Location", format!("/d/{real_filename}"))
+    let link = format!("/d/{}", url_encode(&real_filename));
+    let mut response = Response::builder();
+    if let Some(ttl) = ttl {
+        response = response.header(EXPIRES_HEADER_NAME, ttl);
+    }
+    Ok(response
+        .header("Location", &link)
         .header("Content-Type", "text/plain")
         .status(StatusCode::CREATED)
-        .body(Body::from(format!("/d/{real_filename}")))
+        .body(Body::from(link))
         .unwrap())
 }
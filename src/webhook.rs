@@ -0,0 +1,570 @@
+use std::{env, path::PathBuf, sync::Arc, time::Duration};
+
+use axum::http::header;
+use hmac::{Hmac, KeyInit, Mac};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::{
+    fs::{create_dir_all, read_dir, remove_file, rename},
+    sync::Notify,
+    time::sleep,
+};
+use tracing::{error, info, info_span, warn, Instrument};
+
+use crate::{
+    encryption::Encryption,
+    error::UploadError,
+    file_data::{current_unix_timestamp, deserialize_duration, read_json, write_json, FileData},
+    random::generate_random_characters,
+    state::State,
+};
+
+/// the header containing the HMAC-SHA256 of the body as `sha256=<hex>`
+pub const SIGNATURE_HEADER_NAME: &str = "X-Webhook-Signature";
+pub const EVENT_HEADER_NAME: &str = "X-Webhook-Event";
+/// the header containing the id of the delivery, it stays the same when a delivery is retried
+pub const DELIVERY_HEADER_NAME: &str = "X-Webhook-Delivery";
+
+/// the length of the generated delivery ids
+const DELIVERY_ID_LENGTH: usize = 16;
+
+/// the suffix of deliveries that are being written
+const TEMPORARY_SUFFIX: &str = ".tmp";
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// a file was uploaded completely
+    Uploaded,
+    Downloaded,
+    /// a file was removed because its ttl or the ttl of its collection was reached
+    Expired,
+    /// a file was removed with its collection, evicted or deleted permanently from the trash
+    Deleted,
+}
+
+impl WebhookEvent {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Uploaded => "uploaded",
+            Self::Downloaded => "downloaded",
+            Self::Expired => "expired",
+            Self::Deleted => "deleted",
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Webhook {
+    /// The url the events are posted to
+    pub url: String,
+    /// The key the bodies are signed with, the signature is sent in the `X-Webhook-Signature` header
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// The events that are sent to the url, all events if it isn't set
+    #[serde(default)]
+    pub events: Option<Vec<WebhookEvent>>,
+}
+
+impl Webhook {
+    fn applies_to(&self, event: WebhookEvent) -> bool {
+        self.events
+            .as_ref()
+            .is_none_or(|events| events.contains(&event))
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct WebhookConfig {
+    /// The receivers of the events
+    pub hooks: Vec<Webhook>,
+    /// The attempts to deliver an event before it's dropped
+    pub max_attempts: u32,
+    /// The delay before the first retry, it doubles with every failed attempt
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub retry_delay: u64,
+    /// The longest delay between two attempts
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub max_retry_delay: u64,
+    /// The time a receiver has to respond
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub timeout: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            hooks: Vec::new(),
+            max_attempts: 10,
+            retry_delay: 10,
+            max_retry_delay: 60 * 60,
+            timeout: 10,
+        }
+    }
+}
+
+impl WebhookConfig {
+    /// the delay after the failed attempt `attempts`
+    fn retry_delay(&self, attempts: u32) -> u64 {
+        let factor = 2u64.saturating_pow(attempts.saturating_sub(1));
+        self.retry_delay
+            .saturating_mul(factor)
+            .min(self.max_retry_delay)
+    }
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    id: &'a str,
+    event: WebhookEvent,
+    /// the unix timestamp of the event
+    time: u64,
+    file: &'a str,
+    file_data: &'a FileData,
+}
+
+/// an event waiting to be delivered to a receiver
+#[derive(Serialize, Deserialize)]
+struct Delivery {
+    id: String,
+    event: WebhookEvent,
+    /// the index of the webhook in the config, webhooks with the same url may have different secrets and events
+    hook: usize,
+    url: String,
+    /// the serialized payload, it's stored as it's signed
+    body: String,
+    attempts: u32,
+    /// the unix timestamp of the next attempt
+    next_attempt_at: u64,
+}
+
+/// the directory the deliveries are queued in
+pub fn directory() -> PathBuf {
+    PathBuf::from(env::var("WEBHOOK_DIRECTORY").unwrap_or_else(|_| "webhooks".to_string()))
+}
+
+/// the deliveries are stored in `WEBHOOK_DIRECTORY`, so they survive restarts
+#[derive(Clone)]
+pub struct WebhookQueue {
+    directory: PathBuf,
+    /// wakes the sender up when a delivery was added
+    changed: Arc<Notify>,
+}
+
+impl WebhookQueue {
+    /// creates the queue directory, `None` if there are no webhooks
+    pub async fn open(config: &WebhookConfig) -> Option<Self> {
+        if config.hooks.is_empty() {
+            return None;
+        }
+        let directory = directory();
+        create_dir_all(&directory)
+            .await
+            .expect("Error while creating webhook directory");
+        Some(Self {
+            directory,
+            changed: Arc::new(Notify::new()),
+        })
+    }
+}
+
+/// writes the delivery under a temporary name first, so the sender never reads a partially written delivery
+async fn write_delivery(
+    queue: &WebhookQueue,
+    encryption: Option<&Encryption>,
+    delivery: &Delivery,
+) -> Result<(), UploadError> {
+    let temporary_path = queue
+        .directory
+        .join(format!("{}{TEMPORARY_SUFFIX}", delivery.id));
+    write_json(delivery, &temporary_path, encryption).await?;
+    rename(temporary_path, queue.directory.join(&delivery.id)).await?;
+    Ok(())
+}
+
+/// queues the event for every webhook that wants to receive it
+async fn enqueue(
+    state: &State,
+    queue: &WebhookQueue,
+    event: WebhookEvent,
+    time: u64,
+    filename: &str,
+    file_data: &FileData,
+) -> Result<(), UploadError> {
+    for (index, hook) in state.config.webhooks.hooks.iter().enumerate() {
+        if !hook.applies_to(event) {
+            continue;
+        }
+        let id = generate_random_characters(DELIVERY_ID_LENGTH);
+        let payload = Payload {
+            id: &id,
+            event,
+            time,
+            file: filename,
+            file_data,
+        };
+        let delivery = Delivery {
+            body: serde_json::to_string(&payload)?,
+            event,
+            hook: index,
+            url: hook.url.clone(),
+            attempts: 0,
+            next_attempt_at: 0,
+            id,
+        };
+        write_delivery(queue, state.encryption.as_deref(), &delivery).await?;
+    }
+    queue.changed.notify_one();
+    Ok(())
+}
+
+/// sends the event about the file to the webhooks, the deliveries are queued in the background
+/// and failed deliveries are retried later
+pub fn notify(state: &State, event: WebhookEvent, filename: &str, file_data: &FileData) {
+    if state.webhooks.is_none() {
+        return;
+    }
+    let time = current_unix_timestamp();
+    let state = state.clone();
+    let filename = filename.to_string();
+    let file_data = file_data.clone();
+    tokio::spawn(
+        async move {
+            let Some(queue) = &state.webhooks else {
+                return;
+            };
+            if let Err(e) = enqueue(&state, queue, event, time, &filename, &file_data).await {
+                error!(file = %filename, error = ?e, "error while queueing webhook");
+            }
+        }
+        .in_current_span(),
+    );
+}
+
+fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// posts the delivery to its receiver, `true` if the receiver accepted it
+async fn send(client: &Client, hook: &Webhook, delivery: &Delivery) -> bool {
+    let mut request = client
+        .post(&delivery.url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER_NAME, delivery.event.name())
+        .header(DELIVERY_HEADER_NAME, &delivery.id)
+        .body(delivery.body.clone());
+    if let Some(secret) = &hook.secret {
+        request = request.header(SIGNATURE_HEADER_NAME, sign(secret, &delivery.body));
+    }
+    match request.send().await {
+        Ok(response) if response.status().is_success() => true,
+        Ok(response) => {
+            warn!(url = %delivery.url, id = %delivery.id, status = response.status().as_u16(), "webhook receiver rejected the delivery");
+            false
+        }
+        Err(e) => {
+            warn!(url = %delivery.url, id = %delivery.id, error = %e, "error while sending webhook");
+            false
+        }
+    }
+}
+
+fn earliest(timestamp: Option<u64>, other: u64) -> u64 {
+    timestamp.map_or(other, |timestamp| timestamp.min(other))
+}
+
+/// sends the deliveries that are due, returns when the next delivery is due
+async fn send_due(
+    config: &WebhookConfig,
+    encryption: Option<&Encryption>,
+    queue: &WebhookQueue,
+    client: &Client,
+) -> Result<Option<u64>, UploadError> {
+    let mut next_attempt_at = None;
+    let mut content = read_dir(&queue.directory).await?;
+    while let Some(file) = content.next_entry().await? {
+        // the delivery is still being written
+        if file
+            .file_name()
+            .to_string_lossy()
+            .ends_with(TEMPORARY_SUFFIX)
+        {
+            continue;
+        }
+        let path = file.path();
+        let mut delivery = match read_json::<Delivery>(&path, encryption).await {
+            Ok(Some(delivery)) => delivery,
+            Ok(None) => continue,
+            Err(e) => {
+                error!(path = %path.display(), error = ?e, "error while reading webhook delivery");
+                continue;
+            }
+        };
+        if delivery.next_attempt_at > current_unix_timestamp() {
+            next_attempt_at = Some(earliest(next_attempt_at, delivery.next_attempt_at));
+            continue;
+        }
+        // the webhook could have been removed from the config since the event was queued
+        let Some(hook) = config
+            .hooks
+            .get(delivery.hook)
+            .filter(|hook| hook.url == delivery.url)
+        else {
+            warn!(url = %delivery.url, id = %delivery.id, "dropping webhook delivery of a removed webhook");
+            remove_file(&path).await?;
+            continue;
+        };
+        if send(client, hook, &delivery).await {
+            remove_file(&path).await?;
+            continue;
+        }
+        delivery.attempts += 1;
+        if delivery.attempts >= config.max_attempts {
+            error!(url = %delivery.url, id = %delivery.id, attempts = delivery.attempts, "dropping webhook delivery after too many attempts");
+            remove_file(&path).await?;
+            continue;
+        }
+        delivery.next_attempt_at = current_unix_timestamp() + config.retry_delay(delivery.attempts);
+        write_delivery(queue, encryption, &delivery).await?;
+        next_attempt_at = Some(earliest(next_attempt_at, delivery.next_attempt_at));
+    }
+    Ok(next_attempt_at)
+}
+
+pub fn start_webhook_sender(state: State) {
+    let Some(queue) = state.webhooks.clone() else {
+        return;
+    };
+    tokio::spawn(
+        async move {
+            info!("started webhook sender");
+            let client = Client::builder()
+                .timeout(Duration::from_secs(state.config.webhooks.timeout.max(1)))
+                .build()
+                .expect("Error while creating webhook client");
+            loop {
+                // the queued deliveries are sent at startup
+                let config = &state.config.webhooks;
+                let wait =
+                    match send_due(config, state.encryption.as_deref(), &queue, &client).await {
+                        Ok(Some(next_attempt_at)) => {
+                            next_attempt_at.saturating_sub(current_unix_timestamp())
+                        }
+                        Ok(None) => config.max_retry_delay,
+                        Err(e) => {
+                            error!(error = ?e, "error while sending webhooks");
+                            config.retry_delay
+                        }
+                    };
+                tokio::select! {
+                    _ = sleep(Duration::from_secs(wait.max(1))) => {}
+                    _ = queue.changed.notified() => {}
+                }
+            }
+        }
+        .instrument(info_span!("webhook_sender")),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use axum::{
+        http::{HeaderMap, StatusCode},
+        routing::post,
+        Router,
+    };
+    use tokio::{fs::remove_dir_all, net::TcpListener};
+
+    use super::*;
+
+    fn hook(url: &str, secret: Option<&str>, events: Option<Vec<WebhookEvent>>) -> Webhook {
+        Webhook {
+            url: url.to_string(),
+            secret: secret.map(str::to_string),
+            events,
+        }
+    }
+
+    /// the signatures of the requests the receiver got
+    type Received = Arc<Mutex<Vec<Option<String>>>>;
+
+    /// starts a receiver that accepts deliveries at `/ok` and rejects them at `/fail`, returns its base url
+    async fn receiver(received: Received) -> String {
+        let record = move |headers: HeaderMap| {
+            received.lock().unwrap().push(
+                headers
+                    .get(SIGNATURE_HEADER_NAME)
+                    .map(|value| value.to_str().unwrap().to_string()),
+            );
+        };
+        let ok = record.clone();
+        let router = Router::new()
+            .route(
+                "/ok",
+                post(move |headers: HeaderMap| async move {
+                    ok(headers);
+                    StatusCode::OK
+                }),
+            )
+            .route(
+                "/fail",
+                post(move |headers: HeaderMap| async move {
+                    record(headers);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                }),
+            );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        format!("http://{address}")
+    }
+
+    async fn queue() -> WebhookQueue {
+        let directory = env::temp_dir().join(generate_random_characters(16));
+        create_dir_all(&directory).await.unwrap();
+        WebhookQueue {
+            directory,
+            changed: Arc::new(Notify::new()),
+        }
+    }
+
+    async fn queue_delivery(queue: &WebhookQueue, hook: usize, url: &str, attempts: u32) -> String {
+        let id = generate_random_characters(DELIVERY_ID_LENGTH);
+        let delivery = Delivery {
+            id: id.clone(),
+            event: WebhookEvent::Uploaded,
+            hook,
+            url: url.to_string(),
+            body: "{}".to_string(),
+            attempts,
+            next_attempt_at: 0,
+        };
+        write_delivery(queue, None, &delivery).await.unwrap();
+        id
+    }
+
+    async fn read_delivery(queue: &WebhookQueue, id: &str) -> Option<Delivery> {
+        read_json::<Delivery>(&queue.directory.join(id), None)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn sign_is_the_hmac_of_the_body() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", "what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_ne!(sign("other", "body"), sign("secret", "body"));
+    }
+
+    #[test]
+    fn retry_delay_doubles_until_the_maximum() {
+        let config = WebhookConfig {
+            retry_delay: 10,
+            max_retry_delay: 60,
+            ..Default::default()
+        };
+        let delays = (1..=5)
+            .map(|attempts| config.retry_delay(attempts))
+            .collect::<Vec<u64>>();
+        assert_eq!(delays, [10, 20, 40, 60, 60]);
+        assert_eq!(config.retry_delay(u32::MAX), 60);
+    }
+
+    #[test]
+    fn hooks_apply_to_their_events() {
+        assert!(hook("", None, None).applies_to(WebhookEvent::Downloaded));
+        let hook = hook("", None, Some(vec![WebhookEvent::Uploaded]));
+        assert!(hook.applies_to(WebhookEvent::Uploaded));
+        assert!(!hook.applies_to(WebhookEvent::Deleted));
+    }
+
+    #[tokio::test]
+    async fn successful_deliveries_are_removed() {
+        let received = Received::default();
+        let url = format!("{}/ok", receiver(received.clone()).await);
+        let config = WebhookConfig {
+            hooks: vec![
+                hook(&url, Some("first"), None),
+                hook(&url, Some("second"), None),
+            ],
+            ..Default::default()
+        };
+        let queue = queue().await;
+        let id = queue_delivery(&queue, 1, &url, 0).await;
+
+        let next_attempt_at = send_due(&config, None, &queue, &Client::new())
+            .await
+            .unwrap();
+        assert_eq!(next_attempt_at, None);
+        assert!(read_delivery(&queue, &id).await.is_none());
+        // the delivery is signed with the secret of its own webhook
+        assert_eq!(*received.lock().unwrap(), [Some(sign("second", "{}"))]);
+        remove_dir_all(&queue.directory).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_deliveries_are_rescheduled_and_dropped_after_max_attempts() {
+        let received = Received::default();
+        let url = format!("{}/fail", receiver(received.clone()).await);
+        let config = WebhookConfig {
+            hooks: vec![hook(&url, None, None)],
+            max_attempts: 3,
+            retry_delay: 10,
+            ..Default::default()
+        };
+        let queue = queue().await;
+        let id = queue_delivery(&queue, 0, &url, 1).await;
+
+        let next_attempt_at = send_due(&config, None, &queue, &Client::new())
+            .await
+            .unwrap()
+            .unwrap();
+        let delivery = read_delivery(&queue, &id).await.unwrap();
+        assert_eq!(delivery.attempts, 2);
+        assert_eq!(delivery.next_attempt_at, next_attempt_at);
+        assert!(next_attempt_at >= current_unix_timestamp() + 19);
+
+        // the last attempt fails too
+        queue_delivery(&queue, 0, &url, 2).await;
+        remove_file(queue.directory.join(&id)).await.unwrap();
+        assert_eq!(
+            send_due(&config, None, &queue, &Client::new())
+                .await
+                .unwrap(),
+            None
+        );
+        assert!(read_dir(&queue.directory)
+            .await
+            .unwrap()
+            .next_entry()
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(received.lock().unwrap().len(), 2);
+        remove_dir_all(&queue.directory).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn deliveries_of_removed_webhooks_are_dropped() {
+        let config = WebhookConfig {
+            hooks: vec![hook("http://127.0.0.1:1/other", None, None)],
+            ..Default::default()
+        };
+        let queue = queue().await;
+        let id = queue_delivery(&queue, 0, "http://127.0.0.1:1/removed", 0).await;
+        send_due(&config, None, &queue, &Client::new())
+            .await
+            .unwrap();
+        assert!(read_delivery(&queue, &id).await.is_none());
+        remove_dir_all(&queue.directory).await.unwrap();
+    }
+}